@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tracing::{debug, error, info, warn};
+
+use crate::config::AdminConfig;
+use crate::error::RevbotError;
+use crate::webex;
+use crate::AppState;
+
+/// How often the checks are made.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Sends the alert to the admin, straight away and around mutes, quiet hours
+/// and the queue, which may be what's broken.
+async fn alert(state: &AppState, admin: &AdminConfig, text: String) {
+    let webex_client = state.webex_client();
+    let recipients = admin.email.iter().map(|email| webex::Message::to_person(email.to_owned(), text.clone()))
+        .chain(admin.room_id.iter().map(|room_id| webex::Message::to_room(room_id.to_owned(), text.clone())));
+    for message in recipients {
+        if let Err(err) = webex_client.send_message(message).await {
+            error!("Couldn't alert the admin ({}): {}", err, text);
+        }
+    }
+}
+
+/// Checks for trouble revbot can't sort out by itself, and tells the admin
+/// when it starts and when it's over.
+///
+/// Alerts go through Webex too, so an access token which Webex rejects can
+/// only be alerted about if it's been replaced since, otherwise it's logged.
+pub async fn run_checks(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut gitlab_failures = 0;
+    let mut raised: HashSet<&'static str> = HashSet::new();
+    loop {
+        interval.tick().await;
+        let config = state.config();
+        let admin = match &config.admin {
+            Some(admin) => admin,
+            None => continue,
+        };
+
+        let mut problems: Vec<(&'static str, String)> = Vec::new();
+        match state.gitlab_client().get_current_user().await {
+            Ok(_) => gitlab_failures = 0,
+            Err(err) if err.is_transient() => debug!("Couldn't check the GitLab access token: {}", err),
+            Err(err) => {
+                gitlab_failures += 1;
+                if gitlab_failures >= admin.gitlab_failures {
+                    problems.push(("gitlab", format!("GitLab ({}) rejected the access token {} times in a row: {}", config.gitlab.hostname, gitlab_failures, err)));
+                }
+            }
+        }
+        if !config.webex.mock {
+            if let Err(RevbotError::Webex { source, .. }) = state.webex_client().get_me().await {
+                if source.status() == Some(StatusCode::UNAUTHORIZED) {
+                    error!("Webex rejected the access token, check webex.access_token");
+                    problems.push(("webex", "Webex rejected the access token".to_owned()));
+                }
+            }
+        }
+        if let Some(queue) = &state.queue {
+            let backlog = queue.backlog();
+            if backlog > admin.queue_backlog {
+                problems.push(("queue", format!("{} messages are waiting to be delivered", backlog)));
+            }
+        }
+
+        for (check, problem) in &problems {
+            if raised.insert(check) {
+                warn!("Alerting the admin: {}", problem);
+                alert(&state, admin, format!("🚨 {}", problem)).await;
+            }
+        }
+        let resolved: Vec<&'static str> = raised.iter().copied().filter(|check| !problems.iter().any(|(problem, _)| problem == check)).collect();
+        for check in resolved {
+            raised.remove(check);
+            info!("Telling the admin the {} problem is over", check);
+            alert(&state, admin, format!("✅ The {} problem is over", check)).await;
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::message::Action;
+
+const ADAPTIVE_CARD_CONTENT_TYPE: &str = "application/vnd.microsoft.card.adaptive";
+
+/// The buttons for a message about `event`, if that event is sent as a card.
+pub fn actions_for(event: &str, actions: Vec<Action>, config: &Config) -> Vec<Action> {
+    if config.webex.card_events.iter().any(|card_event| card_event == event) {
+        actions
+    } else {
+        Vec::new()
+    }
+}
+
+fn card_action(action: &Action) -> Value {
+    match &action.submit {
+        Some(submit) => json!({
+            "type": "Action.Submit",
+            "title": action.title,
+            "data": submit,
+        }),
+        None => json!({
+            "type": "Action.OpenUrl",
+            "title": action.title,
+            "url": action.url,
+        }),
+    }
+}
+
+/// An Adaptive Card attachment showing the message with a button for each action.
+///
+/// Clients which can't show cards show the markdown of the message instead.
+pub fn adaptive_card(markdown: &str, actions: &[Action]) -> Value {
+    json!({
+        "contentType": ADAPTIVE_CARD_CONTENT_TYPE,
+        "content": {
+            "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+            "type": "AdaptiveCard",
+            "version": "1.2",
+            "body": [{
+                "type": "TextBlock",
+                "text": markdown,
+                "wrap": true,
+            }],
+            "actions": actions.iter().map(card_action).collect::<Vec<_>>(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{MergeRequestAction, MergeRequestRef};
+
+    #[test]
+    fn test_adaptive_card_actions() {
+        let merge_request = MergeRequestRef { project_id: 7, iid: 42 };
+        let actions = vec![
+            Action::open("Open MR", "https://gitlab.example.com/mr/42"),
+            Action::submit("Approve", "https://gitlab.example.com/mr/42", MergeRequestAction::Approve, merge_request),
+        ];
+        let card = adaptive_card("Review me", &actions);
+
+        let card_actions = &card["content"]["actions"];
+        assert_eq!("Action.OpenUrl", card_actions[0]["type"]);
+        assert_eq!("Action.Submit", card_actions[1]["type"]);
+        assert_eq!(json!({"action": "approve", "project_id": 7, "iid": 42}), card_actions[1]["data"]);
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, info, warn};
+
+use crate::gitlab::common::{MergeRequest, StatusState, UserBasic};
+use crate::identity;
+use crate::markdown;
+use crate::message::{Message, MergeRequestAction, Recipient, Submit};
+use crate::subscriptions;
+use crate::templates;
+use crate::webex::{ReceivedMessage, WebhookData};
+use crate::AppState;
+
+const HELP: &str = "I send you notifications about your GitLab merge requests. \
+    In a space, mention me before a command.\n\n\
+    - `help`: this message\n\
+    - `my mrs`: the open merge requests you're assigned to or reviewing\n\
+    - `mute 2h`: no notifications for a while, they're dropped, not saved for later\n\
+    - `unmute`: notifications again\n\
+    - `settings`: the notifications you turned off\n\
+    - `disable pipeline_running`: no more notifications of this kind, `enable` turns them back on\n\
+    - `subscribe group/project pipelines,merges`: post the project's pipelines and merges (or `merge_requests`, `comments`, `deployments` or single notifications) in this space, `unsubscribe group/project` stops them\n\
+    - `link <username>`: in a 1:1 space, get notifications for this GitLab user here, once you've shown it's you\n\
+    - `unlink`: forget the GitLab users you linked\n\
+    - `register-token <token>`: in a 1:1 space, a GitLab access token (with the `api` scope) to approve and merge with from cards\n\
+    - `forget-token`: forget that token\n\
+    - `retry group/project!42`: retry the failed jobs of the merge request's pipeline, with your token";
+
+/// How long `mute` on its own lasts.
+const DEFAULT_MUTE: Duration = Duration::from_secs(60 * 60);
+
+/// GitLab's Reporter role, the least that sees a private project's merge
+/// requests and pipelines.
+const REPORTER_ACCESS_LEVEL: u64 = 20;
+
+/// More than this and the reply gets too long to read.
+const MAX_LISTED_MERGE_REQUESTS: usize = 20;
+
+/// A GitLab access token, which is kept out of the logs.
+#[derive(PartialEq)]
+struct Token(String);
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Token(…)")
+    }
+}
+
+/// Something someone asked revbot to do.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    MyMergeRequests,
+    Mute(Duration),
+    Unmute,
+    Settings,
+    /// Turns an event, by template name, on or off.
+    SetEvent { event: String, enabled: bool },
+    Link(String),
+    Unlink,
+    RegisterToken(Token),
+    ForgetToken,
+    Retry { project: String, iid: u64 },
+    Subscribe { project: String, kinds: Vec<String> },
+    Unsubscribe { project: String },
+    Unknown(String),
+}
+
+impl Command {
+    /// Parses the text of a message, without the mention of revbot in front
+    /// of it in a space.
+    fn parse(text: &str) -> Self {
+        let words: Vec<String> = text.split_whitespace().map(|word| word.to_lowercase()).collect();
+        let words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+        match words.as_slice() {
+            [] | ["help"] => Command::Help,
+            ["my", "mrs"] | ["my", "mr"] => Command::MyMergeRequests,
+            ["mute"] => Command::Mute(DEFAULT_MUTE),
+            ["mute", duration] => match humantime::parse_duration(duration) {
+                Ok(duration) => Command::Mute(duration),
+                Err(_) => Command::Unknown(text.trim().to_owned()),
+            },
+            ["unmute"] => Command::Unmute,
+            ["settings"] => Command::Settings,
+            ["enable", event] => Command::SetEvent { event: event.to_string(), enabled: true },
+            ["disable", event] => Command::SetEvent { event: event.to_string(), enabled: false },
+            ["link", username] => Command::Link(username.trim_start_matches('@').to_owned()),
+            ["unlink"] => Command::Unlink,
+            // Tokens are case sensitive, unlike commands.
+            ["register-token", _, ..] => Command::RegisterToken(Token(text.split_whitespace().nth(1).unwrap_or_default().to_owned())),
+            ["forget-token"] => Command::ForgetToken,
+            // Project paths are kept as they were written too.
+            ["retry", _] => {
+                let target = text.split_whitespace().nth(1).unwrap_or_default();
+                match target.rsplit_once('!').map(|(project, iid)| (project, iid.parse())) {
+                    Some((project, Ok(iid))) if !project.is_empty() => Command::Retry { project: project.to_owned(), iid },
+                    _ => Command::Unknown(text.trim().to_owned()),
+                }
+            }
+            ["subscribe", _, kinds] => Command::Subscribe {
+                project: text.split_whitespace().nth(1).unwrap_or_default().to_owned(),
+                kinds: kinds.split(',').filter(|kind| !kind.is_empty()).map(str::to_owned).collect(),
+            },
+            ["unsubscribe", _] => Command::Unsubscribe { project: text.split_whitespace().nth(1).unwrap_or_default().to_owned() },
+            _ => Command::Unknown(text.trim().to_owned()),
+        }
+    }
+}
+
+/// The text of the message, without revbot's name if it was mentioned.
+fn command_text<'a>(message: &'a ReceivedMessage, bot_name: &str) -> &'a str {
+    let text = message.text.trim_start();
+    match text.get(..bot_name.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(bot_name) => &text[bot_name.len()..],
+        _ => text,
+    }
+}
+
+/// How long ago, roughly, e.g. `3d` or `5h`.
+fn age(since: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let age = now - since;
+    if age.num_days() > 0 {
+        format!("{}d", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h", age.num_hours())
+    } else {
+        format!("{}m", age.num_minutes().max(0))
+    }
+}
+
+fn pipeline_text(merge_request: Option<&MergeRequest>) -> &'static str {
+    match merge_request.and_then(|merge_request| merge_request.pipeline.as_ref()).map(|pipeline| pipeline.status) {
+        Some(StatusState::Success) => "🌞 passed",
+        Some(StatusState::Failed) => "⛈️ failed",
+        Some(StatusState::Running) => "⏳ running",
+        Some(StatusState::Pending) | Some(StatusState::Created) => "🕒 pending",
+        Some(_) => "⚪ other",
+        None => "no pipeline",
+    }
+}
+
+fn role_text(merge_request: &MergeRequest, user: &UserBasic) -> &'static str {
+    let listed = |users: &Option<Vec<UserBasic>>| users.iter().flatten().any(|listed| listed.id == user.id);
+    match (listed(&merge_request.assignees), listed(&merge_request.reviewers)) {
+        (true, true) => "assignee and reviewer",
+        (true, false) => "assignee",
+        _ => "reviewer",
+    }
+}
+
+async fn my_merge_requests(person_email: &str, state: &AppState) -> String {
+    let gitlab_client = state.gitlab_client();
+    let user = match identity::gitlab_user(person_email, &gitlab_client, &state.config()).await {
+        Some(user) => user,
+        None => return format!("Sorry, I couldn't find a GitLab user with the email {}.", person_email),
+    };
+    let merge_requests = match gitlab_client.list_merge_requests_for_user(user.id).await {
+        Some(merge_requests) => merge_requests,
+        None => return "Sorry, I couldn't get your merge requests from GitLab, try again later.".to_owned(),
+    };
+    if merge_requests.is_empty() {
+        return "You've no open merge requests to work on or review. 🎉".to_owned();
+    }
+
+    let now = Utc::now();
+    let mut text = format!("Your {} open merge requests:\n", merge_requests.len());
+    for merge_request in merge_requests.iter().take(MAX_LISTED_MERGE_REQUESTS) {
+        // Listed merge requests don't say how their pipeline is doing.
+        let details = gitlab_client.get_merge_request_details(merge_request.project_id, merge_request.iid).await.ok();
+        text.push_str(&format!(
+            "\n- [!{mr_iid} {mr_title}]({mr_url}) as {role}, {pipeline}, {age} old",
+            mr_iid=merge_request.iid, mr_title=markdown::escape(&merge_request.title), mr_url=merge_request.web_url,
+            role=role_text(merge_request, &user), pipeline=pipeline_text(details.as_ref()),
+            age=age(merge_request.created_at, now)));
+    }
+    if merge_requests.len() > MAX_LISTED_MERGE_REQUESTS {
+        text.push_str(&format!("\n\n…and {} more.", merge_requests.len() - MAX_LISTED_MERGE_REQUESTS));
+    }
+
+    text
+}
+
+async fn mute(person_email: &str, duration: Duration, state: &AppState) -> String {
+    let until = match chrono::Duration::from_std(duration).ok().and_then(|duration| Utc::now().checked_add_signed(duration)) {
+        Some(until) => until,
+        None => return "Sorry, that's too long to mute for.".to_owned(),
+    };
+    match state.mutes.mute(person_email, until).await {
+        Ok(_) => format!("🔇 Muted until {} UTC, notifications until then are dropped.", until.format("%Y-%m-%d %H:%M")),
+        Err(err) => {
+            warn!("Couldn't mute {}: {}", person_email, err);
+            "Sorry, I couldn't mute you, try again later.".to_owned()
+        }
+    }
+}
+
+async fn unmute(person_email: &str, state: &AppState) -> String {
+    match state.mutes.unmute(person_email).await {
+        Ok(_) => "🔔 Unmuted.".to_owned(),
+        Err(err) => {
+            warn!("Couldn't unmute {}: {}", person_email, err);
+            "Sorry, I couldn't unmute you, try again later.".to_owned()
+        }
+    }
+}
+
+fn settings(person_email: &str, state: &AppState) -> String {
+    let disabled = state.preferences.disabled(person_email);
+    if disabled.is_empty() {
+        return "🔔 You get every notification. `disable pipeline_running` turns one kind off.".to_owned();
+    }
+    let events: Vec<String> = disabled.iter().map(|event| format!("`{}`", event)).collect();
+    format!("🔕 You turned off: {}. `enable <kind>` turns one back on.", events.join(", "))
+}
+
+async fn set_event(person_email: &str, event: &str, enabled: bool, state: &AppState) -> String {
+    if !templates::is_event(event) {
+        return format!("Sorry, there are no {} notifications.", markdown::escape(event));
+    }
+    match state.preferences.set(person_email, event, enabled).await {
+        Ok(_) if enabled => format!("🔔 You'll get {} notifications again.", event),
+        Ok(_) => format!("🔕 You won't get {} notifications any more.", event),
+        Err(err) => {
+            warn!("Couldn't save preferences of {}: {}", person_email, err);
+            "Sorry, I couldn't save that, try again later.".to_owned()
+        }
+    }
+}
+
+async fn subscribe(message: &ReceivedMessage, project: &str, kinds: &[String], state: &AppState) -> String {
+    if let Some(kind) = kinds.iter().find(|kind| !subscriptions::is_kind(kind)) {
+        return format!("Sorry, there's nothing called {} to subscribe to. Try `help`.", markdown::escape(kind));
+    }
+    let config = state.config();
+    if !config.projects.allows(project) {
+        return format!("Sorry, I don't send messages about {}.", markdown::escape(project));
+    }
+    // Anyone in the space would see what's sent, which is too much for sensitive projects.
+    if config.redaction.is_sensitive(project) {
+        return format!("Sorry, {} is sensitive, its messages aren't sent to spaces.", markdown::escape(project));
+    }
+
+    // Spaces only get what whoever subscribes them can see.
+    let gitlab_client = state.gitlab_client();
+    let user = match identity::gitlab_user(&message.person_email, &gitlab_client, &config).await {
+        Some(user) => user,
+        None => return format!("Sorry, I couldn't find a GitLab user with the email {}.", message.person_email),
+    };
+    match gitlab_client.get_project_access_level(project, user.id).await {
+        Ok(Some(access_level)) if access_level >= REPORTER_ACCESS_LEVEL => {}
+        Ok(_) => {
+            info!("Not subscribing room {} to {}, which @{} can't see", message.room_id, project, user.username);
+            return format!("Sorry, only members of {} (as a Reporter or more) can subscribe to it.", markdown::escape(project));
+        }
+        Err(err) => {
+            warn!("Couldn't check the access of @{} to {}: {}", user.username, project, err);
+            return "Sorry, I couldn't check that you can see the project, try again later.".to_owned();
+        }
+    }
+
+    let kinds: BTreeSet<String> = kinds.iter().cloned().collect();
+    match state.subscriptions.subscribe(project, &message.room_id, &kinds).await {
+        Ok(_) => format!("📬 Subscribed this space to {} in {}.", kinds.into_iter().collect::<Vec<_>>().join(", "), markdown::escape(project)),
+        Err(err) => {
+            warn!("Couldn't subscribe room {} to {}: {}", message.room_id, project, err);
+            "Sorry, I couldn't subscribe this space, try again later.".to_owned()
+        }
+    }
+}
+
+async fn unsubscribe(message: &ReceivedMessage, project: &str, state: &AppState) -> String {
+    match state.subscriptions.unsubscribe(project, &message.room_id).await {
+        Ok(true) => format!("📭 Unsubscribed this space from {}.", markdown::escape(project)),
+        Ok(false) => format!("This space isn't subscribed to {}.", markdown::escape(project)),
+        Err(err) => {
+            warn!("Couldn't unsubscribe room {} from {}: {}", message.room_id, project, err);
+            "Sorry, I couldn't unsubscribe this space, try again later.".to_owned()
+        }
+    }
+}
+
+/// Links the GitLab user to whoever sent the message once the code it gave
+/// them shows up in the user's bio, which only they can edit.
+async fn link(message: &ReceivedMessage, username: &str, state: &AppState) -> String {
+    if message.room_type != "direct" {
+        return "Let's do that in a 1:1 space.".to_owned();
+    }
+    let config = state.config();
+    if let Some(email) = config.identities.get(username) {
+        debug!("Not linking {} to @{}, who is configured to get notifications at {}", message.person_email, username, email);
+        return format!("@{} is set up to get notifications already.", markdown::escape(username));
+    }
+
+    let pending = match state.links.pending(&message.person_email, Utc::now()) {
+        Some(pending) if pending.username == username => pending,
+        _ => {
+            return match state.links.start(&message.person_email, username).await {
+                Ok(code) => format!(
+                    "To show you're @{username}, add `{code}` to the bio of your GitLab profile, \
+                    then send me `link {username}` again within the hour. You can remove it after.",
+                    username=markdown::escape(username), code=code),
+                Err(err) => {
+                    warn!("Couldn't start linking {} to @{}: {}", message.person_email, username, err);
+                    "Sorry, I couldn't start linking you, try again later.".to_owned()
+                }
+            };
+        }
+    };
+
+    let gitlab_client = state.gitlab_client();
+    let profile = match gitlab_client.find_user(Some(username), "").await {
+        Some(user) => gitlab_client.get_user_profile(user.id).await,
+        None => None,
+    };
+    match profile {
+        Some(profile) if profile.bio.as_deref().unwrap_or_default().contains(&pending.code) => {
+            match state.links.link(&message.person_email, username).await {
+                Ok(_) => {
+                    info!("Linked @{} to {}", username, message.person_email);
+                    format!("🔗 Linked! Notifications for @{} come to you here now.", markdown::escape(username))
+                }
+                Err(err) => {
+                    warn!("Couldn't link {} to @{}: {}", message.person_email, username, err);
+                    "Sorry, I couldn't link you, try again later.".to_owned()
+                }
+            }
+        }
+        Some(_) => format!("I don't see `{}` in @{}'s bio yet.", pending.code, markdown::escape(username)),
+        None => format!("Sorry, I couldn't find the GitLab user @{}.", markdown::escape(username)),
+    }
+}
+
+async fn unlink(person_email: &str, state: &AppState) -> String {
+    match state.links.unlink(person_email).await {
+        Ok(usernames) if usernames.is_empty() => "You haven't linked any GitLab users.".to_owned(),
+        Ok(usernames) => format!("Unlinked @{}.", markdown::escape(&usernames.join(", @"))),
+        Err(err) => {
+            warn!("Couldn't unlink {}: {}", person_email, err);
+            "Sorry, I couldn't unlink you, try again later.".to_owned()
+        }
+    }
+}
+
+async fn register_token(message: &ReceivedMessage, token: &Token, state: &AppState) -> String {
+    let user_tokens = match &state.user_tokens {
+        Some(user_tokens) => user_tokens,
+        None => return "Sorry, I can't keep tokens, ask whoever runs me to set actions.encryption_key.".to_owned(),
+    };
+    if message.room_type != "direct" {
+        return "⚠️ Everyone in this space can see your token now, revoke it in GitLab! \
+            Send me a new one in a 1:1 space instead.".to_owned();
+    }
+
+    let gitlab_client = state.gitlab_client();
+    let user = match identity::gitlab_user(&message.person_email, &gitlab_client, &state.config()).await {
+        Some(user) => user,
+        None => return format!("Sorry, I couldn't find a GitLab user with the email {}.", message.person_email),
+    };
+    let token_user = match gitlab_client.get_token_user(&token.0).await {
+        Ok(token_user) => token_user,
+        Err(err) => {
+            debug!("GitLab rejected the token of {}: {}", user.username, err);
+            return "Sorry, GitLab didn't accept that token.".to_owned();
+        }
+    };
+    if token_user.id != user.id {
+        return format!("Sorry, that token is @{}'s, not yours (@{}).", token_user.username, user.username);
+    }
+
+    match user_tokens.set(&user.username, &token.0).await {
+        Ok(_) => "🔑 Got it, I'll use your token when you click the buttons on my cards. \
+            You can delete your message with it now, and `forget-token` when you'd rather I didn't.".to_owned(),
+        Err(err) => {
+            warn!("Couldn't keep the token of {}: {}", user.username, err);
+            "Sorry, I couldn't keep your token, try again later.".to_owned()
+        }
+    }
+}
+
+async fn forget_token(person_email: &str, state: &AppState) -> String {
+    let user_tokens = match &state.user_tokens {
+        Some(user_tokens) => user_tokens,
+        None => return "I don't keep tokens.".to_owned(),
+    };
+    let user = match identity::gitlab_user(person_email, &state.gitlab_client(), &state.config()).await {
+        Some(user) => user,
+        None => return format!("Sorry, I couldn't find a GitLab user with the email {}.", person_email),
+    };
+
+    match user_tokens.remove(&user.username).await {
+        Ok(true) => "🗑️ Forgot your token, you may want to revoke it in GitLab too.".to_owned(),
+        Ok(false) => "I didn't have a token for you.".to_owned(),
+        Err(err) => {
+            warn!("Couldn't forget the token of {}: {}", user.username, err);
+            "Sorry, I couldn't forget your token, try again later.".to_owned()
+        }
+    }
+}
+
+fn reply(message: &ReceivedMessage, text: String) -> Message {
+    reply_in(&message.room_id, text)
+}
+
+fn reply_in(room_id: &str, text: String) -> Message {
+    Message {
+        recipient: Recipient::Room(room_id.to_owned()),
+        message: text,
+        merge_request: None,
+        actions: Vec::new(),
+        thread: None,
+        replaces: None,
+        event: None,
+    }
+}
+
+/// Answers a message sent to revbot on Webex.
+pub async fn handle_message(data: WebhookData, state: Arc<AppState>) {
+    let webex_client = state.webex_client();
+    let me = match webex_client.me().await {
+        Some(me) => me,
+        None => return,
+    };
+    // Webex tells us about our own messages too.
+    if data.person_id.as_deref() == Some(me.id.as_str()) {
+        return;
+    }
+
+    let message = match webex_client.get_message(&data.id).await {
+        Ok(message) => message,
+        Err(err) => {
+            warn!("Couldn't fetch Webex message {}: {}", data.id, err);
+            return;
+        }
+    };
+
+    let command = Command::parse(command_text(&message, &me.display_name));
+    info!("Command from {}: {:?}", message.person_email, command);
+    let text = match command {
+        Command::Help => HELP.to_owned(),
+        Command::MyMergeRequests => my_merge_requests(&message.person_email, &state).await,
+        Command::Mute(duration) => mute(&message.person_email, duration, &state).await,
+        Command::Unmute => unmute(&message.person_email, &state).await,
+        Command::Settings => settings(&message.person_email, &state),
+        Command::SetEvent { event, enabled } => set_event(&message.person_email, &event, enabled, &state).await,
+        Command::Link(username) => link(&message, &username, &state).await,
+        Command::Unlink => unlink(&message.person_email, &state).await,
+        Command::RegisterToken(token) => register_token(&message, &token, &state).await,
+        Command::ForgetToken => forget_token(&message.person_email, &state).await,
+        Command::Retry { project, iid } => retry(&message.person_email, &project, iid, &state).await,
+        Command::Subscribe { project, kinds } => subscribe(&message, &project, &kinds, &state).await,
+        Command::Unsubscribe { project } => unsubscribe(&message, &project, &state).await,
+        Command::Unknown(text) => {
+            debug!("Unknown command: {}", text);
+            format!("Sorry, I don't know how to \"{}\". Try `help`.", markdown::escape(&text))
+        }
+    };
+
+    crate::dispatch_messages(vec![reply(&message, text)], &state).await;
+}
+
+async fn retry(person_email: &str, project: &str, iid: u64, state: &AppState) -> String {
+    let merge_request = match state.gitlab_client().get_project_merge_request(project, iid).await {
+        Ok(merge_request) => merge_request,
+        Err(err) => {
+            debug!("Couldn't get {}!{}: {}", project, iid, err);
+            return format!("Sorry, I couldn't find {}!{}.", markdown::escape(project), iid);
+        }
+    };
+    let submitted = Submit {
+        action: MergeRequestAction::RetryPipeline,
+        project_id: merge_request.project_id,
+        iid,
+    };
+
+    submit(person_email, submitted, state).await
+}
+
+/// Does what was submitted in GitLab, with the token of whoever asked for it
+/// by clicking a button or with a command.
+async fn submit(person_email: &str, submit: Submit, state: &AppState) -> String {
+    let config = state.config();
+    let configured_tokens = match &config.actions {
+        Some(actions) => &actions.user_tokens,
+        None => return "Sorry, acting in GitLab for people is turned off.".to_owned(),
+    };
+    let gitlab_client = state.gitlab_client();
+    let user = match identity::gitlab_user(person_email, &gitlab_client, &config).await {
+        Some(user) => user,
+        None => return format!("Sorry, I couldn't find a GitLab user with the email {}.", person_email),
+    };
+    let registered_token = || state.user_tokens.as_ref().and_then(|user_tokens| user_tokens.get(&user.username));
+    let token = match configured_tokens.get(&user.username.to_lowercase()).cloned().or_else(registered_token) {
+        Some(token) => token,
+        None => return format!(
+            "Sorry, I've no GitLab token for @{}, so I can't do that for you. Send me one with `register-token`.",
+            user.username),
+    };
+
+    let (project_id, iid) = (submit.project_id, submit.iid);
+    let result = match submit.action {
+        MergeRequestAction::Approve => gitlab_client
+            .approve_merge_request(project_id, iid, &token)
+            .await
+            .map(|_| format!("👍 Approved !{} as @{}.", iid, user.username)),
+        MergeRequestAction::MergeWhenPipelineSucceeds => gitlab_client
+            .merge_merge_request(project_id, iid, &token)
+            .await
+            .map(|_| format!("🚀 Set !{} to merge when the pipeline succeeds, as @{}.", iid, user.username)),
+        MergeRequestAction::RetryPipeline => {
+            let pipeline = gitlab_client.get_merge_request_details(project_id, iid).await.map(|merge_request| merge_request.pipeline);
+            match pipeline {
+                Ok(Some(pipeline)) => gitlab_client
+                    .retry_pipeline(project_id, pipeline.id, &token)
+                    .await
+                    .map(|pipeline| format!("🔁 Retrying the [pipeline]({}) of !{} as @{}.", pipeline.web_url, iid, user.username)),
+                Ok(None) => return format!("!{} has no pipeline to retry.", iid),
+                Err(err) => Err(err),
+            }
+        }
+    };
+    match result {
+        Ok(text) => text,
+        Err(err) => {
+            warn!("Couldn't {:?} !{} in project {} as @{}: {}", submit.action, submit.iid, submit.project_id, user.username, err);
+            format!("Sorry, GitLab wouldn't let me do that for !{}: {}", submit.iid, err)
+        }
+    }
+}
+
+/// Acts on a button clicked on one of revbot's cards.
+pub async fn handle_card_action(data: WebhookData, state: Arc<AppState>) {
+    let webex_client = state.webex_client();
+    let action = match webex_client.get_attachment_action(&data.id).await {
+        Ok(action) => action,
+        Err(err) => {
+            warn!("Couldn't fetch Webex attachment action {}: {}", data.id, err);
+            return;
+        }
+    };
+    let submitted: Submit = match serde_json::from_value(action.inputs.clone()) {
+        Ok(submitted) => submitted,
+        Err(err) => {
+            debug!("Ignoring attachment action {} with unknown inputs: {}", action.id, err);
+            return;
+        }
+    };
+    let person = match webex_client.get_person(&action.person_id).await {
+        Ok(person) => person,
+        Err(err) => {
+            warn!("Couldn't look up who clicked {:?}: {}", submitted.action, err);
+            return;
+        }
+    };
+    let person_email = match person.emails.first() {
+        Some(email) => email,
+        None => return,
+    };
+
+    info!("Card action from {}: {:?}", person_email, submitted);
+    let text = submit(person_email, submitted, &state).await;
+    crate::dispatch_messages(vec![reply_in(&action.room_id, text)], &state).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let message = ReceivedMessage {
+            id: "message-id".to_owned(),
+            room_id: "room-id".to_owned(),
+            room_type: "direct".to_owned(),
+            person_id: "person-id".to_owned(),
+            person_email: "someone@example.com".to_owned(),
+            text: "revbot HELP".to_owned(),
+        };
+
+        assert_eq!(Command::Help, Command::parse(command_text(&message, "Revbot")));
+        assert_eq!(Command::Help, Command::parse(""));
+        assert_eq!(Command::MyMergeRequests, Command::parse("My MRs"));
+        assert_eq!(Command::Mute(Duration::from_secs(2 * 60 * 60)), Command::parse("mute 2h"));
+        assert_eq!(Command::Unknown("mute lots".to_owned()), Command::parse("mute lots"));
+        assert_eq!(Command::Unknown("mute me".to_owned()), Command::parse(" mute me "));
+        assert_eq!(Command::Settings, Command::parse("Settings"));
+        assert_eq!(Command::Link("hds-".to_owned()), Command::parse("link @Hds-"));
+        assert_eq!(Command::SetEvent { event: "pipeline_running".to_owned(), enabled: false }, Command::parse("disable Pipeline_Running"));
+        assert_eq!(Command::SetEvent { event: "note".to_owned(), enabled: true }, Command::parse("enable note"));
+        assert_eq!(Command::RegisterToken(Token("glpat-AbC".to_owned())), Command::parse("Register-Token glpat-AbC"));
+        assert_eq!("RegisterToken(Token(…))", format!("{:?}", Command::parse("register-token glpat-AbC")));
+        assert_eq!(Command::Retry { project: "Platform/revbot".to_owned(), iid: 42 }, Command::parse("retry Platform/revbot!42"));
+        assert_eq!(Command::Unknown("retry !42".to_owned()), Command::parse("retry !42"));
+        assert_eq!(
+            Command::Subscribe { project: "hds-/mr-test".to_owned(), kinds: vec!["pipelines".to_owned(), "merges".to_owned()] },
+            Command::parse("subscribe hds-/mr-test Pipelines,merges"));
+        assert_eq!(Command::Unsubscribe { project: "hds-/mr-test".to_owned() }, Command::parse("unsubscribe hds-/mr-test"));
+    }
+}
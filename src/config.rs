@@ -0,0 +1,1355 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexSet};
+use handlebars::RenderError;
+use serde::{Deserialize, Serialize};
+
+use crate::links::LinkedIdentities;
+use crate::message::Recipient;
+use crate::secrets;
+use crate::templates::{self, Templates, DEFAULT_TEMPLATES_DIR};
+
+#[derive(Deserialize, Debug)]
+pub struct GitlabConfig {
+    #[serde(default)]
+    pub access_token: String,
+    /// Read the access token from this file instead, e.g. one Kubernetes mounts.
+    pub access_token_file: Option<String>,
+    /// Look the access token up in Vault instead, as `<path>#<key>`.
+    pub access_token_vault: Option<String>,
+    pub hostname: String,
+    /// Where GitLab webhooks are received, `/gitlab` by default.
+    pub webhook_path: Option<String>,
+    pub webhook_token: Option<String>,
+    /// Keep a comment on each merge request listing who was notified about it.
+    #[serde(default)]
+    pub mirror_notifications: bool,
+    /// How long pipeline and merge request details are kept, 0 to not keep them.
+    #[serde(default = "default_gitlab_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+}
+
+impl GitlabConfig {
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl_secs)
+    }
+}
+
+fn default_gitlab_cache_ttl_secs() -> u64 {
+    30
+}
+
+/// Receiving webhooks from GitHub as well as GitLab.
+#[derive(Deserialize, Debug)]
+pub struct GithubConfig {
+    /// Where GitHub webhooks are received, `/github` by default.
+    pub webhook_path: Option<String>,
+    /// The webhook secret, which GitHub signs the webhooks with.
+    pub webhook_secret: Option<String>,
+    /// GitHub logins (in lower case) mapped to Webex emails. GitHub doesn't
+    /// tell us anyone's email, so nobody else is notified.
+    #[serde(default)]
+    pub identities: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebexConfig {
+    #[serde(default)]
+    pub access_token: String,
+    /// Read the access token from this file instead, e.g. one Kubernetes mounts.
+    pub access_token_file: Option<String>,
+    /// Look the access token up in Vault instead, as `<path>#<key>`.
+    pub access_token_vault: Option<String>,
+    pub webhook_path: Option<String>,
+    pub webhook_token: Option<String>,
+    pub whoami_link: Option<String>,
+    /// Log messages instead of sending them, e.g. when load testing.
+    #[serde(default)]
+    pub mock: bool,
+    /// Messages which Webex rate limits or fails with a 5xx are tried this many times in total.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// The wait before the first retry, which doubles for each one after.
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Look people up on Webex before messaging them, so that messages to
+    /// someone who isn't on Webex are logged instead of vanishing.
+    #[serde(default = "default_verify_recipients")]
+    pub verify_recipients: bool,
+    /// Events (by template name) sent as an Adaptive Card with buttons, instead of plain markdown.
+    #[serde(default)]
+    pub card_events: Vec<String>,
+    /// Reply to the first message about a merge request's pipelines with the
+    /// later ones, instead of sending each on its own.
+    #[serde(default)]
+    pub thread_pipelines: bool,
+    /// Edit the message about a running pipeline once it's finished, instead
+    /// of sending another one.
+    #[serde(default)]
+    pub edit_pipeline_messages: bool,
+    /// Act as a Webex integration, whose access token is refreshed when
+    /// Webex rejects it, instead of a bot with a token that doesn't expire.
+    pub oauth: Option<WebexOauthConfig>,
+}
+
+/// A Webex integration's client and the refresh token it was granted, along
+/// with where the tokens Webex hands out on each refresh are kept.
+#[derive(Deserialize, Debug)]
+pub struct WebexOauthConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    #[serde(default = "default_webex_tokens_path")]
+    pub path: String,
+}
+
+fn default_webex_tokens_path() -> String {
+    "revbot-webex-tokens".to_owned()
+}
+
+fn default_verify_recipients() -> bool {
+    true
+}
+
+fn default_max_attempts() -> u32 {
+    4
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+/// Where secrets referred to as `<path>#<key>` are looked up, in a KV
+/// (version 2) secrets engine.
+#[derive(Deserialize, Debug)]
+pub struct VaultConfig {
+    pub address: String,
+    pub token: Option<String>,
+    /// Read the Vault token from this file instead, e.g. the Vault agent's sink.
+    pub token_file: Option<String>,
+    #[serde(default = "default_vault_mount")]
+    pub mount: String,
+}
+
+fn default_vault_mount() -> String {
+    "secret".to_owned()
+}
+
+/// Tuning for the built-in HTTP server.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The IP address (v4 or v6) to listen on, unless `--address` is given.
+    pub address: String,
+    /// The port to listen on, unless `--port` is given.
+    pub port: u16,
+    /// Serve plain HTTP on this unix socket instead of the address and port.
+    pub unix_socket: Option<String>,
+    /// Serve everything under this path, e.g. `/revbot` behind a reverse proxy.
+    pub base_path: Option<String>,
+    /// Log who made requests from the last X-Forwarded-For and
+    /// X-Forwarded-Proto entries, as set by a reverse proxy, instead of the
+    /// connection.
+    pub trust_forwarded_headers: bool,
+    /// Connections which haven't sent all headers by then are closed.
+    pub header_read_timeout_secs: u64,
+    /// Requests whose body hasn't been read by then are rejected.
+    pub read_timeout_secs: u64,
+    /// Requests with a bigger body are rejected.
+    pub max_body_bytes: usize,
+    /// Further connections wait until one of the current ones is closed.
+    pub max_connections: usize,
+    /// Further webhooks are turned away with a 503 until one of the current
+    /// ones has been worked through.
+    pub max_webhook_tasks: usize,
+    pub keep_alive: bool,
+    pub tcp_keepalive_secs: Option<u64>,
+    /// How long to wait on shutdown for messages which are still being worked on.
+    pub shutdown_timeout_secs: u64,
+    /// Sent as a bearer token to the admin endpoints, which are off without it.
+    pub admin_token: Option<String>,
+    /// Where metrics are served in the Prometheus text format, if anywhere.
+    pub metrics_path: Option<String>,
+}
+
+impl ServerConfig {
+    /// Where to listen, with the address and port given on the command line
+    /// taking precedence.
+    pub fn listen_addr(&self, address: Option<&str>, port: Option<u16>) -> Result<SocketAddr, String> {
+        let address = address.unwrap_or(&self.address);
+        // IPv6 addresses may come in brackets, as in URLs.
+        let ip: IpAddr = address.trim_start_matches('[').trim_end_matches(']').parse()
+            .map_err(|_| format!("{} isn't an IP address", address))?;
+        Ok(SocketAddr::new(ip, port.unwrap_or(self.port)))
+    }
+
+    /// The token for the admin endpoints, unless it's left as a placeholder.
+    pub fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref().filter(|token| !is_unset(token))
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_owned(),
+            port: 4001,
+            unix_socket: None,
+            base_path: None,
+            trust_forwarded_headers: false,
+            header_read_timeout_secs: 10,
+            read_timeout_secs: 30,
+            max_body_bytes: 4 * 1024 * 1024,
+            max_connections: 256,
+            max_webhook_tasks: 64,
+            keep_alive: true,
+            tcp_keepalive_secs: None,
+            shutdown_timeout_secs: 20,
+            admin_token: None,
+            metrics_path: None,
+        }
+    }
+}
+
+/// How log lines are written: as text for people, or as JSON objects for log
+/// aggregators, with the fields of each event and its spans.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format, expected text or json: {}", format)),
+        }
+    }
+}
+
+/// Who's told about trouble revbot can't sort out by itself: GitLab rejecting
+/// the access token, Webex rejecting its token, or the queue backing up.
+#[derive(Deserialize, Debug)]
+pub struct AdminConfig {
+    pub email: Option<String>,
+    pub room_id: Option<String>,
+    /// How many times in a row GitLab has to reject the token.
+    #[serde(default = "default_admin_gitlab_failures")]
+    pub gitlab_failures: u32,
+    /// How many messages may be waiting in the queue.
+    #[serde(default = "default_admin_queue_backlog")]
+    pub queue_backlog: usize,
+}
+
+fn default_admin_gitlab_failures() -> u32 {
+    3
+}
+
+fn default_admin_queue_backlog() -> usize {
+    1000
+}
+
+/// Sends the spans to an OTLP collector (e.g. Jaeger or Tempo) over gRPC.
+#[derive(Deserialize, Debug)]
+pub struct TelemetryConfig {
+    #[serde(default = "default_telemetry_endpoint")]
+    pub endpoint: String,
+    #[serde(default = "default_telemetry_service_name")]
+    pub service_name: String,
+}
+
+fn default_telemetry_endpoint() -> String {
+    "http://localhost:4317".to_owned()
+}
+
+fn default_telemetry_service_name() -> String {
+    "revbot".to_owned()
+}
+
+/// Serves HTTPS with the PEM encoded certificate chain and key. With a `port`,
+/// HTTPS is served on it and plain HTTP stays on the usual port.
+#[derive(Deserialize, Debug)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub port: Option<u16>,
+}
+
+/// Which merge request actions are notified about.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MergeRequestsConfig {
+    /// Tell the assignees and reviewers when a merge request is merged.
+    pub merged: bool,
+    /// Tell the author when a merge request is closed.
+    pub closed: bool,
+}
+
+impl Default for MergeRequestsConfig {
+    fn default() -> Self {
+        Self {
+            merged: true,
+            closed: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct PipelinesConfig {
+    /// The same status of the same pipeline is only notified about once in
+    /// this many seconds, 0 turns that off.
+    pub dedup_ttl_secs: u64,
+    /// Which refs' pipelines are notified about, the first entry for the project applies.
+    pub refs: Vec<PipelineRefsConfig>,
+    /// Tell the merge request reviewers about pipelines with these statuses as well.
+    pub reviewers: Option<PipelineReviewersConfig>,
+    pub broken_branches: Option<BrokenBranchesConfig>,
+}
+
+/// Pipelines outside merge requests are otherwise not notified about, but a
+/// failed one on these branches tells whoever triggered it, and the room if
+/// there is one.
+#[derive(Deserialize, Debug)]
+pub struct BrokenBranchesConfig {
+    pub projects: Option<ProjectPatterns>,
+    pub branches: RefPatterns,
+    pub room_id: Option<String>,
+}
+
+impl BrokenBranchesConfig {
+    pub fn watches(&self, path_with_namespace: &str, branch: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace) && self.branches.is_match(branch)
+    }
+}
+
+impl Default for PipelinesConfig {
+    fn default() -> Self {
+        Self {
+            dedup_ttl_secs: 600,
+            refs: Vec::new(),
+            reviewers: None,
+            broken_branches: None,
+        }
+    }
+}
+
+impl PipelinesConfig {
+    /// Whether a pipeline on `ref_` is notified about. Projects without an
+    /// entry have every pipeline notified about.
+    pub fn notifies_ref(&self, path_with_namespace: &str, ref_: &str, merge_request_pipeline: bool) -> bool {
+        match self.refs.iter().find(|refs| project_listed(&refs.projects, path_with_namespace)) {
+            Some(refs) => refs.refs.is_match(ref_) || (merge_request_pipeline && refs.merge_requests),
+            None => true,
+        }
+    }
+
+    pub fn notifies_reviewers(&self, path_with_namespace: &str, status: &str) -> bool {
+        match &self.reviewers {
+            Some(reviewers) => {
+                project_listed(&reviewers.projects, path_with_namespace)
+                    && reviewers.statuses.iter().any(|listed| listed == status)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Which pipelines the merge request reviewers hear about, on top of whoever
+/// triggered them.
+#[derive(Deserialize, Debug)]
+pub struct PipelineReviewersConfig {
+    pub projects: Option<ProjectPatterns>,
+    #[serde(default = "default_reviewer_statuses")]
+    pub statuses: Vec<String>,
+}
+
+fn default_reviewer_statuses() -> Vec<String> {
+    vec!["failed".to_owned()]
+}
+
+/// The refs whose pipelines are notified about in some projects, e.g. `main`
+/// and `release/*`.
+#[derive(Deserialize, Debug)]
+pub struct PipelineRefsConfig {
+    pub projects: Option<ProjectPatterns>,
+    pub refs: RefPatterns,
+    /// Merge request pipelines run on a `refs/merge-requests/` ref, this
+    /// includes them whatever that ref is.
+    #[serde(default = "default_merge_request_pipelines")]
+    pub merge_requests: bool,
+}
+
+fn default_merge_request_pipelines() -> bool {
+    true
+}
+
+/// The gRPC ingestion API, for internal tools submitting their own notifications.
+///
+/// If `token` is set, requests must carry it as `authorization: Bearer <token>` metadata.
+#[derive(Deserialize, Debug)]
+pub struct GrpcConfig {
+    pub address: String,
+    pub token: Option<String>,
+}
+
+/// Where to announce feature flag toggles, and which ones to announce.
+///
+/// GitLab doesn't include the environment scopes of a flag in the webhook, so
+/// if `environments` is set the flag details are fetched from the API.
+#[derive(Deserialize, Debug)]
+pub struct FeatureFlagsConfig {
+    pub room_id: String,
+    pub projects: Option<ProjectPatterns>,
+    pub environments: Option<Vec<String>>,
+}
+
+impl FeatureFlagsConfig {
+    pub fn watches_project(&self, path_with_namespace: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace)
+    }
+}
+
+/// Where to announce milestone events.
+///
+/// Due-date reminders need the API to list milestones, so they're only sent
+/// for the `projects` which are listed without any glob syntax.
+#[derive(Deserialize, Debug)]
+pub struct MilestonesConfig {
+    pub room_id: String,
+    pub projects: Option<ProjectPatterns>,
+    pub due_soon_days: Option<i64>,
+    pub check_interval_secs: Option<u64>,
+}
+
+impl MilestonesConfig {
+    pub fn watches_project(&self, path_with_namespace: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace)
+    }
+}
+
+/// Who gets reminded about an idle merge request at one step of the escalation chain.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationTarget {
+    Author,
+    Assignees,
+    Reviewers,
+    Room(String),
+    Email(String),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EscalationStep {
+    /// Hours without any activity on the merge request.
+    pub after_hours: i64,
+    pub notify: EscalationTarget,
+}
+
+/// Reminders for open merge requests without activity, escalating along the
+/// `steps` the longer the merge request stays idle.
+///
+/// Like milestone reminders, only the `projects` listed without any glob
+/// syntax are checked. How far each merge request has been escalated is kept
+/// in `state_path`, so restarts carry on where they left off.
+#[derive(Deserialize, Debug)]
+pub struct EscalationConfig {
+    pub projects: ProjectPatterns,
+    pub steps: Vec<EscalationStep>,
+    pub check_interval_secs: Option<u64>,
+    pub state_path: Option<String>,
+}
+
+/// How long reviewers have to start on a merge request, counted in business
+/// hours in `timezone` from when they were first asked, Monday to Friday.
+///
+/// A reviewer who hasn't commented or approved by then is reminded, and after
+/// as long again `escalate_to` is told. Who was asked when is kept in `path`.
+#[derive(Deserialize, Debug)]
+pub struct ReviewSlaConfig {
+    pub projects: ProjectPatterns,
+    pub sla_hours: i64,
+    /// An IANA time zone, e.g. `Europe/Madrid`.
+    pub timezone: Tz,
+    #[serde(default = "default_business_start")]
+    pub business_start: NaiveTime,
+    #[serde(default = "default_business_end")]
+    pub business_end: NaiveTime,
+    #[serde(default = "default_review_sla_escalate_to")]
+    pub escalate_to: EscalationTarget,
+    #[serde(default = "default_review_sla_path")]
+    pub path: String,
+    pub check_interval_secs: Option<u64>,
+}
+
+fn default_business_start() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap()
+}
+
+fn default_business_end() -> NaiveTime {
+    NaiveTime::from_hms_opt(17, 0, 0).unwrap()
+}
+
+fn default_review_sla_escalate_to() -> EscalationTarget {
+    EscalationTarget::Author
+}
+
+fn default_review_sla_path() -> String {
+    "revbot-review-sla".to_owned()
+}
+
+/// A weekly summary of the merge requests opened, merged and still open in
+/// each of the `projects` listed without glob syntax, posted in a room on
+/// `weekday` at `time_of_day` (UTC).
+#[derive(Deserialize, Debug)]
+pub struct WeeklyReportConfig {
+    pub room_id: String,
+    pub projects: ProjectPatterns,
+    #[serde(default = "default_weekly_report_weekday")]
+    pub weekday: Weekday,
+    #[serde(default = "default_weekly_report_time")]
+    pub time_of_day: NaiveTime,
+}
+
+fn default_weekly_report_weekday() -> Weekday {
+    Weekday::Mon
+}
+
+fn default_weekly_report_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap()
+}
+
+/// Who gets the messages for an event matching a rule.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleTarget {
+    /// Whoever revbot would have notified without the rule.
+    Default,
+    Assignees,
+    /// The merge request author, or whoever triggered anything else.
+    Author,
+    Room(String),
+    Email(String),
+}
+
+/// Routes the messages for matching events, the first matching rule applies.
+///
+/// Every condition which is set has to match: the object kind of the webhook
+/// (e.g. `pipeline`), the project, the branch, any one of the labels, the
+/// username of whoever triggered the event and its status (e.g. `failed`, or
+/// the action like `merge` or `approved`).
+#[derive(Deserialize, Debug)]
+pub struct RuleConfig {
+    pub events: Option<Vec<String>>,
+    pub projects: Option<ProjectPatterns>,
+    pub branches: Option<RefPatterns>,
+    pub labels: Option<Vec<String>>,
+    pub users: Option<Vec<String>>,
+    pub statuses: Option<Vec<String>>,
+    /// Nobody gets the messages.
+    #[serde(default)]
+    pub drop: bool,
+    #[serde(default)]
+    pub notify: Vec<RuleTarget>,
+}
+
+/// Where to announce wiki page changes.
+#[derive(Deserialize, Debug)]
+pub struct WikiPagesConfig {
+    pub room_id: String,
+    pub projects: Option<ProjectPatterns>,
+}
+
+impl WikiPagesConfig {
+    pub fn watches_project(&self, path_with_namespace: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace)
+    }
+}
+
+/// Where to announce pushes to some branches, e.g. the protected ones. Pushes
+/// happen a lot, so only the branches listed are announced.
+#[derive(Deserialize, Debug)]
+pub struct PushesConfig {
+    pub room_id: String,
+    pub projects: Option<ProjectPatterns>,
+    pub branches: RefPatterns,
+}
+
+impl PushesConfig {
+    pub fn watches(&self, path_with_namespace: &str, branch: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace) && self.branches.is_match(branch)
+    }
+}
+
+/// Which deployments the deployer hears about, and the rooms which hear
+/// about deployments to an environment as well.
+#[derive(Deserialize, Debug)]
+pub struct DeploymentsConfig {
+    #[serde(default = "default_deployment_environments")]
+    pub environments: Vec<String>,
+    /// Rooms by environment name.
+    #[serde(default)]
+    pub rooms: HashMap<String, String>,
+    pub projects: Option<ProjectPatterns>,
+}
+
+fn default_deployment_environments() -> Vec<String> {
+    vec!["production".to_owned(), "staging".to_owned()]
+}
+
+impl DeploymentsConfig {
+    pub fn watches(&self, path_with_namespace: &str, environment: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace)
+            && self.environments.iter().any(|watched| watched.eq_ignore_ascii_case(environment))
+    }
+}
+
+/// Where to announce releases, and optionally every tag pushed.
+#[derive(Deserialize, Debug)]
+pub struct ReleasesConfig {
+    pub room_id: String,
+    pub projects: Option<ProjectPatterns>,
+    #[serde(default)]
+    pub tags: bool,
+}
+
+impl ReleasesConfig {
+    pub fn watches_project(&self, path_with_namespace: &str) -> bool {
+        project_listed(&self.projects, path_with_namespace)
+    }
+}
+
+/// A team space which also gets the merge request and pipeline messages for its projects.
+#[derive(Deserialize, Debug)]
+pub struct TeamRoomConfig {
+    pub room_id: String,
+    pub projects: ProjectPatterns,
+}
+
+/// How a reviewer is picked from a pool.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewerStrategy {
+    /// Going round the pool by merge request number.
+    #[default]
+    RoundRobin,
+    /// Whoever has the fewest open merge requests to work on or review.
+    LeastLoaded,
+}
+
+/// Reviewers to pick one from for the merge requests in the projects which
+/// are opened without any.
+#[derive(Deserialize, Debug)]
+pub struct ReviewerPoolConfig {
+    pub projects: ProjectPatterns,
+    /// GitLab usernames.
+    pub reviewers: Vec<String>,
+    #[serde(default)]
+    pub strategy: ReviewerStrategy,
+}
+
+/// Keeps messages on disk until they're delivered, so that they survive a
+/// restart or a Webex outage.
+#[derive(Deserialize, Debug)]
+pub struct QueueConfig {
+    #[serde(default = "default_queue_path")]
+    pub path: String,
+}
+
+fn default_queue_path() -> String {
+    "revbot-queue".to_owned()
+}
+
+/// Buttons on merge request cards to approve them, or to merge them once their
+/// pipeline succeeds, as whoever clicks them.
+///
+/// People's GitLab tokens are either configured, or registered by them with
+/// `register-token` if there's an `encryption_key` to keep them at `path` with.
+#[derive(Deserialize, Debug)]
+pub struct ActionsConfig {
+    /// GitLab access tokens (with the `api` scope) by username, in lower case.
+    #[serde(default)]
+    pub user_tokens: HashMap<String, String>,
+    #[serde(default = "default_user_tokens_path")]
+    pub path: String,
+    /// 32 bytes as hex, e.g. from `openssl rand -hex 32`.
+    pub encryption_key: Option<String>,
+}
+
+impl ActionsConfig {
+    /// The key registered tokens are encrypted with, if there's a usable one.
+    pub fn encryption_key(&self) -> Option<Vec<u8>> {
+        let key = hex::decode(self.encryption_key.as_deref()?.trim()).ok()?;
+        if key.len() == 32 { Some(key) } else { None }
+    }
+}
+
+fn default_user_tokens_path() -> String {
+    "revbot-user-tokens".to_owned()
+}
+
+/// Keeps the messages which couldn't be delivered on disk. With the server's
+/// `admin_token`, they can be listed and sent again at `admin_path`.
+#[derive(Deserialize, Debug)]
+pub struct DeadLettersConfig {
+    #[serde(default = "default_dead_letters_path")]
+    pub path: String,
+    #[serde(default = "default_dead_letters_admin_path")]
+    pub admin_path: String,
+}
+
+fn default_dead_letters_path() -> String {
+    "revbot-dead-letters".to_owned()
+}
+
+fn default_dead_letters_admin_path() -> String {
+    "/admin/dead-letters".to_owned()
+}
+
+/// Keeps the most recent GitLab webhooks on disk, along with what came of
+/// them. With the server's `admin_token`, they can be listed and processed
+/// again at `admin_path`.
+#[derive(Deserialize, Debug)]
+pub struct EventsConfig {
+    #[serde(default = "default_events_path")]
+    pub path: String,
+    #[serde(default = "default_events_admin_path")]
+    pub admin_path: String,
+    #[serde(default = "default_max_events")]
+    pub max_events: usize,
+}
+
+fn default_events_path() -> String {
+    "revbot-events".to_owned()
+}
+
+fn default_events_admin_path() -> String {
+    "/admin/events".to_owned()
+}
+
+fn default_max_events() -> usize {
+    1000
+}
+
+/// Where people's `mute` commands are kept, so that they survive a restart.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MutesConfig {
+    pub path: String,
+}
+
+impl Default for MutesConfig {
+    fn default() -> Self {
+        Self {
+            path: "revbot-mutes".to_owned(),
+        }
+    }
+}
+
+/// Where the events people turned off with `disable` are kept.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct PreferencesConfig {
+    pub path: String,
+}
+
+impl Default for PreferencesConfig {
+    fn default() -> Self {
+        Self {
+            path: "revbot-preferences".to_owned(),
+        }
+    }
+}
+
+/// Where the rooms' `subscribe` commands are kept.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SubscriptionsConfig {
+    pub path: String,
+}
+
+impl Default for SubscriptionsConfig {
+    fn default() -> Self {
+        Self {
+            path: "revbot-subscriptions".to_owned(),
+        }
+    }
+}
+
+/// Where the identities people linked with `link` are kept.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct LinksConfig {
+    pub path: String,
+}
+
+impl Default for LinksConfig {
+    fn default() -> Self {
+        Self {
+            path: "revbot-links".to_owned(),
+        }
+    }
+}
+
+/// People who get their messages combined into a digest, instead of one by one.
+///
+/// The digest is sent every `interval_secs`, or once a day at `time_of_day`
+/// (UTC, e.g. "09:00:00") if that's set. Held back messages are kept in memory
+/// only, so they're lost on a restart.
+#[derive(Deserialize, Debug)]
+pub struct DigestConfig {
+    pub recipients: Vec<String>,
+    pub interval_secs: Option<u64>,
+    pub time_of_day: Option<NaiveTime>,
+}
+
+impl DigestConfig {
+    pub fn wants_digest(&self, email: &str) -> bool {
+        self.recipients.iter().any(|recipient| recipient.eq_ignore_ascii_case(email))
+    }
+}
+
+/// The most messages anyone gets a minute, the rest are summed up in one
+/// message once the minute is over.
+#[derive(Deserialize, Debug)]
+pub struct RateLimitConfig {
+    pub per_minute: u32,
+}
+
+/// When some people don't want to be messaged, in their own time zone. Their
+/// messages are held in the queue until the quiet hours are over.
+#[derive(Deserialize, Debug)]
+pub struct QuietHoursConfig {
+    pub people: Vec<String>,
+    /// Quiet hours may go past midnight, e.g. from 19:00:00 to 08:00:00.
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// An IANA time zone, e.g. `Europe/Madrid`.
+    pub timezone: Tz,
+}
+
+impl QuietHoursConfig {
+    pub fn is_quiet(&self, email: &str, now: DateTime<Utc>) -> bool {
+        if !self.people.iter().any(|person| person.eq_ignore_ascii_case(email)) {
+            return false;
+        }
+
+        let time = now.with_timezone(&self.timezone).time();
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// Events (by template name) switched on or off, all of them are on unless
+/// set to `false` here.
+#[derive(Default, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct NotificationsConfig(HashMap<String, bool>);
+
+impl NotificationsConfig {
+    pub fn enabled(&self, event: &str) -> bool {
+        self.0.get(event).copied().unwrap_or(true)
+    }
+}
+
+/// Conditions under which no notifications are sent at all.
+#[derive(Deserialize, Debug)]
+pub struct FiltersConfig {
+    /// Regexes matched against merge request titles, e.g. `^chore\(deps\)`.
+    pub skip_titles: Option<TitlePatterns>,
+    /// Merge requests carrying this label are never notified about.
+    #[serde(default = "default_silence_label")]
+    pub silence_label: String,
+}
+
+fn default_silence_label() -> String {
+    "revbot::silence".to_owned()
+}
+
+impl Default for FiltersConfig {
+    fn default() -> Self {
+        Self {
+            skip_titles: None,
+            silence_label: default_silence_label(),
+        }
+    }
+}
+
+impl FiltersConfig {
+    pub fn skips_title(&self, title: &str) -> bool {
+        match &self.skip_titles {
+            Some(skip_titles) => skip_titles.is_match(title),
+            None => false,
+        }
+    }
+
+    pub fn silences_labels<'a>(&self, mut labels: impl Iterator<Item = &'a str>) -> bool {
+        labels.any(|label| label == self.silence_label)
+    }
+}
+
+/// Which projects generate messages at all, for webhooks set up on a whole group.
+///
+/// Without `allow` every project is allowed, and `deny` wins over `allow`.
+#[derive(Default, Deserialize, Debug)]
+pub struct ProjectsConfig {
+    pub allow: Option<ProjectPatterns>,
+    pub deny: Option<ProjectPatterns>,
+}
+
+impl ProjectsConfig {
+    pub fn allows(&self, path_with_namespace: &str) -> bool {
+        project_listed(&self.allow, path_with_namespace)
+            && !matches!(&self.deny, Some(deny) if deny.is_match(path_with_namespace))
+    }
+}
+
+/// Projects created in (or moved to) these groups get a webhook for revbot,
+/// once a GitLab system hook says they're there.
+#[derive(Deserialize, Debug)]
+pub struct DiscoveryConfig {
+    /// Group paths, subgroups included.
+    pub groups: Vec<String>,
+    /// Where the project webhooks are sent, i.e. revbot's GitLab webhook URL.
+    pub webhook_url: String,
+}
+
+impl DiscoveryConfig {
+    pub fn covers(&self, path_with_namespace: &str) -> bool {
+        let path_with_namespace = path_with_namespace.to_lowercase();
+        self.groups
+            .iter()
+            .map(|group| format!("{}/", group.trim_matches('/').to_lowercase()))
+            .any(|group| path_with_namespace.starts_with(&group))
+    }
+}
+
+/// Projects whose merge request titles are replaced with `[confidential]` in
+/// messages, as long as the project is private. And patterns which are
+/// scrubbed from messages and logged webhooks.
+#[derive(Default, Deserialize, Debug)]
+pub struct RedactionConfig {
+    pub sensitive_projects: Option<ProjectPatterns>,
+    pub scrub_patterns: Option<ScrubPatterns>,
+}
+
+impl RedactionConfig {
+    /// Replaces every match of the scrub patterns with `[redacted]`.
+    pub fn scrub(&self, text: &str) -> String {
+        match &self.scrub_patterns {
+            Some(scrub_patterns) => scrub_patterns.scrub(text),
+            None => text.to_owned(),
+        }
+    }
+
+    pub fn is_sensitive(&self, path_with_namespace: &str) -> bool {
+        match &self.sensitive_projects {
+            Some(sensitive_projects) => sensitive_projects.is_match(path_with_namespace),
+            None => false,
+        }
+    }
+}
+
+fn project_listed(projects: &Option<ProjectPatterns>, path_with_namespace: &str) -> bool {
+    match projects {
+        Some(projects) => projects.is_match(path_with_namespace),
+        None => true,
+    }
+}
+
+/// Glob patterns over a project's `path_with_namespace`, e.g. `platform/**` or `*/infra-*`.
+///
+/// A `*` doesn't match across a `/`, use `**` for that. The patterns are
+/// compiled when the config is loaded.
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "Vec<String>")]
+pub struct ProjectPatterns {
+    patterns: Vec<String>,
+    glob_set: GlobSet,
+}
+
+impl ProjectPatterns {
+    pub fn is_match(&self, path_with_namespace: &str) -> bool {
+        self.glob_set.is_match(path_with_namespace)
+    }
+
+    /// The patterns without any glob syntax, each of which names a single project.
+    pub fn literal_paths(&self) -> impl Iterator<Item = &str> {
+        self.patterns
+            .iter()
+            .map(|pattern| pattern.as_str())
+            .filter(|pattern| !pattern.contains(&['*', '?', '[', '{'][..]))
+    }
+}
+
+impl TryFrom<Vec<String>> for ProjectPatterns {
+    type Error = globset::Error;
+
+    fn try_from(patterns: Vec<String>) -> Result<Self, Self::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+        }
+
+        Ok(Self {
+            patterns,
+            glob_set: builder.build()?,
+        })
+    }
+}
+
+/// Glob patterns over a branch or tag name, e.g. `release/*`.
+///
+/// Like project patterns, a `*` doesn't match across a `/`.
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "Vec<String>")]
+pub struct RefPatterns {
+    glob_set: GlobSet,
+}
+
+impl RefPatterns {
+    pub fn is_match(&self, ref_: &str) -> bool {
+        self.glob_set.is_match(ref_)
+    }
+}
+
+impl TryFrom<Vec<String>> for RefPatterns {
+    type Error = globset::Error;
+
+    fn try_from(patterns: Vec<String>) -> Result<Self, Self::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+        }
+
+        Ok(Self {
+            glob_set: builder.build()?,
+        })
+    }
+}
+
+/// Regexes over a merge request title, compiled when the config is loaded.
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "Vec<String>")]
+pub struct TitlePatterns {
+    regex_set: RegexSet,
+}
+
+impl TitlePatterns {
+    pub fn is_match(&self, title: &str) -> bool {
+        self.regex_set.is_match(title)
+    }
+}
+
+impl TryFrom<Vec<String>> for TitlePatterns {
+    type Error = regex::Error;
+
+    fn try_from(patterns: Vec<String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            regex_set: RegexSet::new(patterns)?,
+        })
+    }
+}
+
+/// Regexes for secrets and personal data, compiled when the config is loaded.
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "Vec<String>")]
+pub struct ScrubPatterns {
+    regexes: Vec<Regex>,
+}
+
+impl ScrubPatterns {
+    pub fn scrub(&self, text: &str) -> String {
+        self.regexes
+            .iter()
+            .fold(text.to_owned(), |text, regex| regex.replace_all(&text, "[redacted]").into_owned())
+    }
+}
+
+impl TryFrom<Vec<String>> for ScrubPatterns {
+    type Error = regex::Error;
+
+    fn try_from(patterns: Vec<String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            regexes: patterns.iter().map(|pattern| Regex::new(pattern)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    pub gitlab: GitlabConfig,
+    pub webex: WebexConfig,
+    pub github: Option<GithubConfig>,
+    pub vault: Option<VaultConfig>,
+    #[serde(default)]
+    pub server: ServerConfig,
+    pub tls: Option<TlsConfig>,
+    pub admin: Option<AdminConfig>,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    pub telemetry: Option<TelemetryConfig>,
+    pub grpc: Option<GrpcConfig>,
+    #[serde(default)]
+    pub merge_requests: MergeRequestsConfig,
+    #[serde(default)]
+    pub pipelines: PipelinesConfig,
+    pub feature_flags: Option<FeatureFlagsConfig>,
+    pub milestones: Option<MilestonesConfig>,
+    pub escalation: Option<EscalationConfig>,
+    pub review_sla: Option<ReviewSlaConfig>,
+    pub weekly_report: Option<WeeklyReportConfig>,
+    pub wiki_pages: Option<WikiPagesConfig>,
+    pub pushes: Option<PushesConfig>,
+    pub releases: Option<ReleasesConfig>,
+    pub deployments: Option<DeploymentsConfig>,
+    pub digest: Option<DigestConfig>,
+    pub queue: Option<QueueConfig>,
+    pub dead_letters: Option<DeadLettersConfig>,
+    pub actions: Option<ActionsConfig>,
+    pub events: Option<EventsConfig>,
+    #[serde(default)]
+    pub mutes: MutesConfig,
+    #[serde(default)]
+    pub preferences: PreferencesConfig,
+    #[serde(default)]
+    pub subscriptions: SubscriptionsConfig,
+    #[serde(default)]
+    pub links: LinksConfig,
+    #[serde(default)]
+    pub quiet_hours: Vec<QuietHoursConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default)]
+    pub team_rooms: Vec<TeamRoomConfig>,
+    #[serde(default)]
+    pub reviewer_pools: Vec<ReviewerPoolConfig>,
+    /// GitLab usernames (in lower case) mapped to Webex emails, for people
+    /// whose GitLab email isn't the one they use on Webex.
+    #[serde(default)]
+    pub identities: HashMap<String, String>,
+    /// Identities people linked themselves with `link`, which `identities`
+    /// take precedence over.
+    #[serde(skip)]
+    pub linked_identities: LinkedIdentities,
+    #[serde(default)]
+    pub projects: ProjectsConfig,
+    pub discovery: Option<DiscoveryConfig>,
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub filters: FiltersConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// Where to find `<name>.hbs` files replacing the built in message
+    /// templates, and `<locale>.toml` catalogs translating them.
+    pub templates_dir: Option<String>,
+    #[serde(skip)]
+    pub templates: Templates,
+    /// The locale messages are sent in, English if it's not set.
+    pub locale: Option<String>,
+    /// Webex emails or room ids (in lower case) mapped to the locale of their
+    /// messages, for those who want another than `locale`.
+    #[serde(default)]
+    pub locales: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn new(filename: &str) -> Result<Self, config::ConfigError> {
+        let mut config = config::Config::default();
+        config.merge(config::File::with_name(filename))?;
+        config.merge(config::Environment::with_prefix("REVBOT").separator("__"))?;
+
+        let mut config: Self = config.try_into()?;
+        let templates_dir = config.templates_dir.as_deref().unwrap_or(DEFAULT_TEMPLATES_DIR);
+        config.templates = Templates::load(templates_dir)
+            .map_err(|err| config::ConfigError::Message(format!("Bad template in {}: {}", templates_dir, err)))?;
+
+        Ok(config)
+    }
+
+    /// Loads the config along with the access tokens kept in files or Vault.
+    pub async fn load(filename: &str) -> Result<Self, config::ConfigError> {
+        let mut config = Self::new(filename)?;
+        if let Some(access_token) = config.secret("gitlab", &config.gitlab.access_token_file, &config.gitlab.access_token_vault).await? {
+            config.gitlab.access_token = access_token;
+        }
+        if let Some(access_token) = config.secret("webex", &config.webex.access_token_file, &config.webex.access_token_vault).await? {
+            config.webex.access_token = access_token;
+        }
+
+        Ok(config)
+    }
+
+    /// The access token from a file, or else Vault, if the section says where it is.
+    async fn secret(&self, section: &str, file: &Option<String>, vault: &Option<String>) -> Result<Option<String>, config::ConfigError> {
+        let secret = match (file, vault, &self.vault) {
+            (Some(path), _, _) => secrets::read_file(path),
+            (None, Some(reference), Some(vault_config)) => secrets::read_vault(vault_config, reference).await,
+            (None, Some(_), None) => Err(format!("{}.access_token_vault is set, but vault isn't", section)),
+            (None, None, _) => return Ok(None),
+        };
+
+        secret.map(Some).map_err(config::ConfigError::Message)
+    }
+
+    /// Renders the template in the recipient's locale.
+    pub fn render<T: Serialize>(&self, recipient: &Recipient, template: &str, context: &T) -> Result<String, Box<RenderError>> {
+        let key = match recipient {
+            Recipient::Person(email) => email.to_lowercase(),
+            Recipient::Room(room_id) => room_id.to_lowercase(),
+        };
+        let locale = self.locales.get(&key).or(self.locale.as_ref());
+        self.templates.render_in(locale.map(String::as_str), template, context)
+    }
+
+    /// Whether the person is in their quiet hours at `now`.
+    pub fn is_quiet(&self, email: &str, now: DateTime<Utc>) -> bool {
+        self.quiet_hours.iter().any(|quiet_hours| quiet_hours.is_quiet(email, now))
+    }
+
+    /// What's wrong with a config which loaded, e.g. tokens left unset.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if is_unset(&self.gitlab.hostname) {
+            problems.push("gitlab.hostname isn't set".to_owned());
+        }
+        if is_unset(&self.gitlab.access_token) {
+            problems.push("gitlab.access_token isn't set".to_owned());
+        }
+        match &self.webex.oauth {
+            Some(oauth) => {
+                for (name, value) in [("client_id", &oauth.client_id), ("client_secret", &oauth.client_secret), ("refresh_token", &oauth.refresh_token)].iter() {
+                    if is_unset(value) {
+                        problems.push(format!("webex.oauth.{} isn't set", name));
+                    }
+                }
+            }
+            None if !self.webex.mock && is_unset(&self.webex.access_token) => {
+                problems.push("webex.access_token isn't set".to_owned());
+            }
+            None => {}
+        }
+        for (name, token) in [
+            ("gitlab.webhook_token", &self.gitlab.webhook_token),
+            ("webex.webhook_token", &self.webex.webhook_token),
+            ("server.admin_token", &self.server.admin_token),
+        ] {
+            if token.as_deref().is_some_and(is_unset) {
+                problems.push(format!("{} is set to a placeholder, set it or leave it out", name));
+            }
+        }
+        for event in self.notifications.0.keys() {
+            if !templates::is_event(event) {
+                problems.push(format!("notifications.{} isn't an event", event));
+            }
+        }
+        if let Some(locale) = self.locale.as_deref().filter(|locale| !self.templates.has_locale(locale)) {
+            problems.push(format!("locale {} has no catalog in the templates directory", locale));
+        }
+        for (recipient, locale) in &self.locales {
+            if !self.templates.has_locale(locale) {
+                problems.push(format!("locales.{} is {}, which has no catalog in the templates directory", recipient, locale));
+            }
+        }
+        if !self.quiet_hours.is_empty() && self.queue.is_none() {
+            problems.push("quiet_hours needs the queue, messages are sent straight away without it".to_owned());
+        }
+        if let Some(grpc) = &self.grpc {
+            if grpc.address.parse::<SocketAddr>().is_err() {
+                problems.push(format!("grpc.address isn't an address: {}", grpc.address));
+            }
+        }
+        if let Some(actions) = &self.actions {
+            for (username, token) in &actions.user_tokens {
+                if is_unset(token) {
+                    problems.push(format!("actions.user_tokens.{} is set to a placeholder", username));
+                }
+            }
+            if actions.encryption_key.is_some() && actions.encryption_key().is_none() {
+                problems.push("actions.encryption_key isn't 32 bytes as hex, so tokens can't be registered".to_owned());
+            }
+        }
+        if let Some(admin) = &self.admin {
+            if admin.email.is_none() && admin.room_id.is_none() {
+                problems.push("admin needs an email or a room_id to send alerts to".to_owned());
+            }
+        }
+        if let Some(tls) = &self.tls {
+            if let Err(err) = crate::tls::acceptor(tls) {
+                problems.push(format!("tls certificate or key can't be used: {}", err));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Whether a value is empty, or still the placeholder from conf/default.toml.
+fn is_unset(value: &str) -> bool {
+    value.trim().is_empty() || value.starts_with("Set $")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        // Madrid is an hour ahead of UTC in January.
+        let quiet_hours = QuietHoursConfig {
+            people: vec!["hds@example.com".to_owned()],
+            start: NaiveTime::from_hms_opt(19, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            timezone: chrono_tz::Europe::Madrid,
+        };
+        assert!(!quiet_hours.is_quiet("hds@example.com", at("2022-01-10T17:59:59Z")));
+        assert!(quiet_hours.is_quiet("HDS@example.com", at("2022-01-10T18:00:00Z")));
+        assert!(quiet_hours.is_quiet("hds@example.com", at("2022-01-10T23:30:00Z")));
+        assert!(quiet_hours.is_quiet("hds@example.com", at("2022-01-11T06:59:59Z")));
+        assert!(!quiet_hours.is_quiet("hds@example.com", at("2022-01-11T07:00:00Z")));
+        assert!(!quiet_hours.is_quiet("someone@example.com", at("2022-01-10T23:30:00Z")));
+
+        let lunch = QuietHoursConfig {
+            start: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+            ..quiet_hours
+        };
+        assert!(lunch.is_quiet("hds@example.com", at("2022-01-10T11:00:00Z")));
+        assert!(!lunch.is_quiet("hds@example.com", at("2022-01-10T12:00:00Z")));
+    }
+
+    #[test]
+    fn test_project_patterns() {
+        let patterns = ProjectPatterns::try_from(vec!["platform/*".to_owned(), "tools/**".to_owned(), "hds-/mr-test".to_owned()]).unwrap();
+        assert!(patterns.is_match("platform/revbot"));
+        assert!(!patterns.is_match("platform/sub/revbot"));
+        assert!(!patterns.is_match("platform"));
+        assert!(patterns.is_match("tools/sub/cli"));
+        assert!(patterns.is_match("hds-/mr-test"));
+        assert!(!patterns.is_match("hds-/mr-test-2"));
+        assert_eq!(vec!["hds-/mr-test"], patterns.literal_paths().collect::<Vec<_>>());
+
+        assert!(ProjectPatterns::try_from(vec!["platform/[".to_owned()]).is_err());
+    }
+}
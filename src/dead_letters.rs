@@ -0,0 +1,114 @@
+use std::convert::TryInto;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::RevbotError;
+use crate::message::Message;
+
+/// A message which couldn't be delivered, and why.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeadLetter {
+    pub message: Message,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Messages which couldn't be delivered, kept until they're sent again.
+///
+/// Keys are ids from the database's monotonic counter, big endian so that
+/// they sort in the order the messages failed.
+pub struct DeadLetters {
+    db: sled::Db,
+}
+
+impl DeadLetters {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    pub async fn add(&self, message: Message, error: String) -> Result<(), RevbotError> {
+        let dead_letter = DeadLetter {
+            message,
+            error,
+            failed_at: Utc::now(),
+        };
+        let id = self.db.generate_id()?;
+        self.db.insert(id.to_be_bytes(), serde_json::to_vec(&dead_letter)?)?;
+        self.db.flush_async().await?;
+
+        Ok(())
+    }
+
+    /// The dead letters by id, oldest first. Unreadable ones are left out.
+    pub fn list(&self) -> sled::Result<Vec<(u64, DeadLetter)>> {
+        let mut dead_letters = Vec::new();
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            let id = match key.as_ref().try_into() {
+                Ok(id) => u64::from_be_bytes(id),
+                Err(_) => continue,
+            };
+            match serde_json::from_slice(&value) {
+                Ok(dead_letter) => dead_letters.push((id, dead_letter)),
+                Err(err) => warn!("Skipping unreadable dead letter {}: {}", id, err),
+            }
+        }
+
+        Ok(dead_letters)
+    }
+
+    /// Removes the dead letter with the id, or all of them without one, and
+    /// returns their messages to be sent again.
+    pub async fn take(&self, id: Option<u64>) -> sled::Result<Vec<Message>> {
+        let taken: Vec<(u64, DeadLetter)> = self.list()?
+            .into_iter()
+            .filter(|(dead_letter_id, _)| id.is_none_or(|id| id == *dead_letter_id))
+            .collect();
+        for (id, _) in &taken {
+            self.db.remove(id.to_be_bytes())?;
+        }
+        self.db.flush_async().await?;
+
+        Ok(taken.into_iter().map(|(_, dead_letter)| dead_letter.message).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::Recipient;
+
+    fn message(email: &str) -> Message {
+        Message {
+            recipient: Recipient::Person(email.to_owned()),
+            message: "Approved".to_owned(),
+            merge_request: None,
+            actions: Vec::new(),
+            thread: None,
+            replaces: None,
+            event: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_take() {
+        let dead_letters = DeadLetters {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+        };
+        dead_letters.add(message("a@example.com"), "Webex is unavailable".to_owned()).await.unwrap();
+        dead_letters.add(message("b@example.com"), "Webex is unavailable".to_owned()).await.unwrap();
+
+        let listed = dead_letters.list().unwrap();
+        assert_eq!(2, listed.len());
+        assert_eq!("Webex is unavailable", listed[0].1.error);
+
+        let taken = dead_letters.take(Some(listed[1].0)).await.unwrap();
+        assert_eq!(vec![Recipient::Person("b@example.com".to_owned())], taken.into_iter().map(|message| message.recipient).collect::<Vec<_>>());
+        assert_eq!(1, dead_letters.take(None).await.unwrap().len());
+        assert!(dead_letters.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::message::{Message, Recipient};
+use crate::AppState;
+
+const DEFAULT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// Messages held back for the people who get a digest.
+#[derive(Default)]
+pub struct Digest {
+    pending: Mutex<HashMap<String, Vec<Message>>>,
+}
+
+impl Digest {
+    /// Holds back the messages for digest recipients, and returns the ones to send now.
+    pub fn hold(&self, messages: Vec<Message>, config: &Config) -> Vec<Message> {
+        let digest_config = match &config.digest {
+            Some(digest_config) => digest_config,
+            None => return messages,
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+        messages
+            .into_iter()
+            .filter_map(|message| match &message.recipient {
+                Recipient::Person(email) if digest_config.wants_digest(email) => {
+                    pending.entry(email.to_lowercase()).or_default().push(message);
+                    None
+                }
+                _ => Some(message),
+            })
+            .collect()
+    }
+
+    fn take(&self) -> HashMap<String, Vec<Message>> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+/// All of one person's held back messages as a single message.
+fn combine(email: String, messages: Vec<Message>) -> Message {
+    let mut message = format!("📬 Digest of {} notifications\n", messages.len());
+    for held in messages {
+        message.push_str(&format!("\n- {}", held.message.replace('\n', "\n  ")));
+    }
+
+    Message {
+        recipient: Recipient::Person(email),
+        message,
+        merge_request: None,
+        actions: Vec::new(),
+        thread: None,
+        replaces: None,
+        event: None,
+    }
+}
+
+/// How long from `now` until the clock next reads `time_of_day`.
+fn until_next(time_of_day: NaiveTime, now: DateTime<Utc>) -> Duration {
+    let now = now.naive_utc();
+    let mut next = now.date().and_time(time_of_day);
+    if next <= now {
+        next += chrono::Duration::days(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
+
+/// Periodically sends everyone with held back messages their digest.
+pub async fn run_flushes(state: Arc<AppState>) {
+    let config = state.config();
+    let digest_config = match &config.digest {
+        Some(digest_config) => digest_config,
+        None => return,
+    };
+
+    loop {
+        let wait = match digest_config.time_of_day {
+            Some(time_of_day) => until_next(time_of_day, Utc::now()),
+            None => Duration::from_secs(digest_config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS)),
+        };
+        tokio::time::sleep(wait).await;
+
+        let digests: Vec<Message> = state.digest
+            .take()
+            .into_iter()
+            .map(|(email, messages)| combine(email, messages))
+            .collect();
+        debug!("Sending {} digests", digests.len());
+        crate::dispatch_messages(digests, &state).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_until_next() {
+        let nine = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+        let before = Utc.with_ymd_and_hms(2021, 9, 6, 8, 30, 0).unwrap();
+        assert_eq!(Duration::from_secs(30 * 60), until_next(nine, before));
+
+        let at = Utc.with_ymd_and_hms(2021, 9, 6, 9, 0, 0).unwrap();
+        assert_eq!(Duration::from_secs(24 * 60 * 60), until_next(nine, at));
+
+        let after = Utc.with_ymd_and_hms(2021, 9, 6, 17, 0, 0).unwrap();
+        assert_eq!(Duration::from_secs(16 * 60 * 60), until_next(nine, after));
+    }
+}
@@ -0,0 +1,43 @@
+use handlebars::RenderError;
+use thiserror::Error;
+
+use crate::gitlab::client::GitlabClientError;
+use crate::gitlab::webhook::WebhookError;
+
+/// What went wrong, with enough context to decide what to do about it.
+#[derive(Debug, Error)]
+pub enum RevbotError {
+    #[error("GitLab {call} failed: {source}")]
+    Gitlab {
+        call: &'static str,
+        #[source]
+        source: GitlabClientError,
+    },
+    #[error("Webex {call} failed: {source}")]
+    Webex {
+        call: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error(transparent)]
+    Webhook(#[from] WebhookError),
+    #[error("Couldn't render message: {0}")]
+    Template(#[from] Box<RenderError>),
+    #[error("Message queue failed: {0}")]
+    Queue(#[from] sled::Error),
+    #[error("Couldn't encode message: {0}")]
+    Encoding(#[from] serde_json::Error),
+}
+
+impl RevbotError {
+    /// Whether trying again later might work, e.g. when GitLab couldn't be reached.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RevbotError::Gitlab { source, .. } => source.is_transient(),
+            RevbotError::Webex { source, .. } => {
+                source.is_timeout() || source.is_connect() || source.status().is_some_and(|status| status.is_server_error())
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::convert::TryInto;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::RevbotError;
+
+/// A webhook as it was received, and what came of it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Event {
+    pub received_at: DateTime<Utc>,
+    pub kind: String,
+    pub project: Option<String>,
+    pub outcome: String,
+    pub payload: String,
+}
+
+/// The most recent webhooks, to find out why somebody didn't get a message
+/// and to process them again once the config is fixed.
+///
+/// Keys are ids from the database's monotonic counter, big endian so that
+/// they sort in the order the webhooks were received. The oldest ones are
+/// dropped once there are more than `max_events`.
+pub struct Events {
+    db: sled::Db,
+    max_events: usize,
+}
+
+fn event_id(key: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(key.try_into().ok()?))
+}
+
+impl Events {
+    pub fn open(path: &str, max_events: usize) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+            max_events,
+        })
+    }
+
+    /// Keeps the webhook, returning its id.
+    pub async fn record(&self, payload: &[u8], kind: &str, project: Option<&str>, outcome: &str) -> Result<u64, RevbotError> {
+        let event = Event {
+            received_at: Utc::now(),
+            kind: kind.to_owned(),
+            project: project.map(str::to_owned),
+            outcome: outcome.to_owned(),
+            payload: String::from_utf8_lossy(payload).into_owned(),
+        };
+        let id = self.db.generate_id()?;
+        self.db.insert(id.to_be_bytes(), serde_json::to_vec(&event)?)?;
+        while self.db.len() > self.max_events {
+            if self.db.pop_min()?.is_none() {
+                break;
+            }
+        }
+        self.db.flush_async().await?;
+
+        Ok(id)
+    }
+
+    pub async fn set_outcome(&self, id: u64, outcome: &str) -> Result<(), RevbotError> {
+        let mut event = match self.get(id)? {
+            Some(event) => event,
+            // Dropped for newer ones in the meantime.
+            None => return Ok(()),
+        };
+        event.outcome = outcome.to_owned();
+        self.db.insert(id.to_be_bytes(), serde_json::to_vec(&event)?)?;
+        self.db.flush_async().await?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Event>, RevbotError> {
+        match self.db.get(id.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The events by id, newest first. Unreadable ones are left out.
+    pub fn list(&self) -> sled::Result<Vec<(u64, Event)>> {
+        let mut events = Vec::new();
+        for entry in self.db.iter().rev() {
+            let (key, value) = entry?;
+            let id = match event_id(&key) {
+                Some(id) => id,
+                None => continue,
+            };
+            match serde_json::from_slice(&value) {
+                Ok(event) => events.push((id, event)),
+                Err(err) => warn!("Skipping unreadable event {}: {}", id, err),
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record() {
+        let events = Events {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+            max_events: 2,
+        };
+        let first = events.record(b"{}", "push", Some("platform/revbot"), "received").await.unwrap();
+        let second = events.record(b"{}", "pipeline", None, "received").await.unwrap();
+        events.set_outcome(second, "created 1 messages").await.unwrap();
+        let third = events.record(b"{}", "note", None, "received").await.unwrap();
+
+        let listed = events.list().unwrap();
+        assert_eq!(vec![third, second], listed.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+        assert_eq!("created 1 messages", listed[1].1.outcome);
+        assert!(events.get(first).unwrap().is_none());
+    }
+}
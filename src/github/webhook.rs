@@ -0,0 +1,411 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, Level};
+
+use crate::cards;
+use crate::config::Config;
+use crate::gitlab::webhook::{note_snippet, WebhookError};
+use crate::identity;
+use crate::message::{Action, MergeRequestRef, Message, Recipient};
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct User {
+    login: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct Repository {
+    id: u64,
+    name: String,
+    full_name: String,
+    html_url: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct PullRequest {
+    number: u64,
+    title: String,
+    html_url: String,
+    user: User,
+    #[serde(default)]
+    merged: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct PullRequestWebhook {
+    action: String,
+    pull_request: PullRequest,
+    repository: Repository,
+    sender: User,
+    /// Only there when the action is `review_requested`, and not for teams.
+    requested_reviewer: Option<User>,
+    /// Only there when the action is `assigned`.
+    assignee: Option<User>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct Review {
+    user: User,
+    state: String,
+    body: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct PullRequestReviewWebhook {
+    action: String,
+    review: Review,
+    pull_request: PullRequest,
+    repository: Repository,
+}
+
+/// How a run's pull requests are referred to, without their details.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct PullRequestNumber {
+    number: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct HeadCommit {
+    message: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct CheckSuite {
+    id: u64,
+    conclusion: Option<String>,
+    pull_requests: Vec<PullRequestNumber>,
+    head_commit: HeadCommit,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct CheckSuiteWebhook {
+    action: String,
+    check_suite: CheckSuite,
+    repository: Repository,
+    sender: User,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct WorkflowRun {
+    id: u64,
+    name: String,
+    html_url: String,
+    /// The pull request title, for runs triggered by a pull request.
+    #[serde(default)]
+    display_title: String,
+    conclusion: Option<String>,
+    actor: User,
+    pull_requests: Vec<PullRequestNumber>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct WorkflowRunWebhook {
+    action: String,
+    workflow_run: WorkflowRun,
+    repository: Repository,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Webhook {
+    CheckSuite(CheckSuiteWebhook),
+    PullRequest(PullRequestWebhook),
+    PullRequestReview(PullRequestReviewWebhook),
+    WorkflowRun(WorkflowRunWebhook),
+}
+
+impl Webhook {
+    fn repository(&self) -> &Repository {
+        match self {
+            Webhook::CheckSuite(webhook) => &webhook.repository,
+            Webhook::PullRequest(webhook) => &webhook.repository,
+            Webhook::PullRequestReview(webhook) => &webhook.repository,
+            Webhook::WorkflowRun(webhook) => &webhook.repository,
+        }
+    }
+}
+
+/// A GitHub webhook which revbot knows how to process.
+pub struct ParsedWebhook(Webhook);
+
+/// Parses a webhook of the kind GitHub names in the `X-GitHub-Event` header.
+pub fn parse_webhook(event: &str, bytes: &[u8], config: &Config) -> Result<ParsedWebhook, WebhookError> {
+    let value: Value = serde_json::from_slice(bytes).map_err(WebhookError::Malformed)?;
+    if tracing::enabled!(Level::DEBUG) {
+        if let Ok(pretty) = serde_json::to_string_pretty(&value) {
+            debug!("Received GitHub Webhook: {}", config.redaction.scrub(&pretty));
+        }
+    }
+
+    let webhook = match event {
+        "check_suite" => Webhook::CheckSuite(CheckSuiteWebhook::deserialize(&value).map_err(WebhookError::Malformed)?),
+        "pull_request" => Webhook::PullRequest(PullRequestWebhook::deserialize(&value).map_err(WebhookError::Malformed)?),
+        "pull_request_review" => Webhook::PullRequestReview(PullRequestReviewWebhook::deserialize(&value).map_err(WebhookError::Malformed)?),
+        "workflow_run" => Webhook::WorkflowRun(WorkflowRunWebhook::deserialize(&value).map_err(WebhookError::Malformed)?),
+        event => return Err(WebhookError::Unsupported(event.to_owned())),
+    };
+
+    Ok(ParsedWebhook(webhook))
+}
+
+/// The Webex recipient for a GitHub login, if it's mapped to an email.
+fn person(user: &User, config: &Config) -> Option<Recipient> {
+    match identity::github_webex_email(&user.login, config) {
+        Some(email) => Some(Recipient::Person(email)),
+        None => {
+            debug!("No Webex email configured for GitHub user {}, not notifying them", user.login);
+            None
+        }
+    }
+}
+
+fn project_context(repository: &Repository) -> Value {
+    json!({
+        "name": repository.name,
+        "url": repository.html_url,
+    })
+}
+
+fn pull_request_url(repository: &Repository, number: u64) -> String {
+    format!("{}/pull/{}", repository.html_url, number)
+}
+
+fn pull_request_message(recipient: Recipient, template: &str, context: &Value, repository: &Repository, pull_request: &PullRequest, config: &Config) -> Option<Message> {
+    if !config.notifications.enabled(template) {
+        return None;
+    }
+    let message = config.render(&recipient, template, context).ok()?;
+
+    Some(Message {
+        recipient,
+        message,
+        merge_request: Some(MergeRequestRef {
+            project_id: repository.id,
+            iid: pull_request.number,
+        }),
+        actions: cards::actions_for(template, vec![Action::open("Open PR", &pull_request.html_url)], config),
+        thread: None,
+        replaces: None,
+        event: Some(template.to_owned()),
+    })
+}
+
+/// The template context for messages about a pull request, the same shape as
+/// for GitLab merge requests so that the templates can be shared.
+fn pull_request_context(repository: &Repository, pull_request: &PullRequest, user: &User) -> Value {
+    json!({
+        "merge_request": {
+            "iid": pull_request.number,
+            "title": pull_request.title,
+            "url": pull_request.html_url,
+        },
+        "project": project_context(repository),
+        "user": user.login,
+    })
+}
+
+fn process_pull_request(webhook: &PullRequestWebhook, config: &Config) -> Vec<Message> {
+    let pull_request = &webhook.pull_request;
+    let (template, user) = match (webhook.action.as_str(), &webhook.requested_reviewer, &webhook.assignee) {
+        ("review_requested", Some(reviewer), _) => ("reviewer_added", reviewer),
+        ("assigned", _, Some(assignee)) => ("assignee_added", assignee),
+        ("closed", _, _) if pull_request.merged => ("merged", &pull_request.user),
+        ("closed", _, _) => ("closed", &pull_request.user),
+        (action, _, _) => {
+            debug!("Ignoring pull request action: {}", action);
+            return Vec::new();
+        }
+    };
+    // Nobody needs telling about what they did themselves.
+    if user == &webhook.sender {
+        return Vec::new();
+    }
+
+    let context = pull_request_context(&webhook.repository, pull_request, &webhook.sender);
+    person(user, config)
+        .and_then(|recipient| pull_request_message(recipient, template, &context, &webhook.repository, pull_request, config))
+        .into_iter()
+        .collect()
+}
+
+fn process_pull_request_review(webhook: &PullRequestReviewWebhook, config: &Config) -> Vec<Message> {
+    let review = &webhook.review;
+    let pull_request = &webhook.pull_request;
+    if review.user == pull_request.user {
+        return Vec::new();
+    }
+
+    let mut context = pull_request_context(&webhook.repository, pull_request, &review.user);
+    let template = match (webhook.action.as_str(), review.state.as_str()) {
+        ("submitted", "approved") => "approved",
+        ("submitted", _) => {
+            context["snippet"] = json!(review.body.as_deref().filter(|body| !body.trim().is_empty()).map(note_snippet));
+            "note"
+        }
+        ("dismissed", _) => "unapproved",
+        (action, _) => {
+            debug!("Ignoring pull request review action: {}", action);
+            return Vec::new();
+        }
+    };
+
+    person(&pull_request.user, config)
+        .and_then(|recipient| pull_request_message(recipient, template, &context, &webhook.repository, pull_request, config))
+        .into_iter()
+        .collect()
+}
+
+/// The template for a finished run, nothing is sent for runs which were
+/// cancelled or skipped.
+fn run_template(conclusion: Option<&str>) -> Option<&'static str> {
+    match conclusion? {
+        "success" => Some("pipeline_success"),
+        "failure" | "timed_out" => Some("pipeline_failed"),
+        _ => None,
+    }
+}
+
+/// Messages about a finished run, one for each of its pull requests.
+fn run_messages(recipient: Recipient, template: &str, title: &str, pipeline: Value, numbers: &[PullRequestNumber], repository: &Repository, config: &Config) -> Vec<Message> {
+    if !config.notifications.enabled(template) {
+        return Vec::new();
+    }
+    numbers
+        .iter()
+        .filter_map(|PullRequestNumber { number }| {
+            let url = pull_request_url(repository, *number);
+            let context = json!({
+                "merge_request": {
+                    "iid": number,
+                    "title": title,
+                    "url": url,
+                },
+                "project": project_context(repository),
+                "pipeline": pipeline,
+            });
+            let message = config.render(&recipient, template, &context).ok()?;
+            let actions = vec![
+                Action::open("Open PR", &url),
+                Action::open("View run", pipeline["url"].as_str().unwrap_or(&url)),
+            ];
+
+            Some(Message {
+                recipient: recipient.clone(),
+                message,
+                merge_request: Some(MergeRequestRef {
+                    project_id: repository.id,
+                    iid: *number,
+                }),
+                actions: cards::actions_for(template, actions, config),
+                thread: None,
+                replaces: None,
+                event: Some(template.to_owned()),
+            })
+        })
+        .collect()
+}
+
+fn process_workflow_run(webhook: &WorkflowRunWebhook, config: &Config) -> Vec<Message> {
+    let run = &webhook.workflow_run;
+    let template = match (webhook.action.as_str(), run_template(run.conclusion.as_deref())) {
+        ("completed", Some(template)) => template,
+        _ => return Vec::new(),
+    };
+    let recipient = match person(&run.actor, config) {
+        Some(recipient) => recipient,
+        None => return Vec::new(),
+    };
+
+    let pipeline = json!({
+        "id": run.id,
+        "url": run.html_url,
+        "kind": run.name,
+    });
+    run_messages(recipient, template, &run.display_title, pipeline, &run.pull_requests, &webhook.repository, config)
+}
+
+/// Check suites are told to whoever GitHub says sent them. Not the author of
+/// the commit they checked, whose email anyone can set.
+fn process_check_suite(webhook: &CheckSuiteWebhook, config: &Config) -> Vec<Message> {
+    let suite = &webhook.check_suite;
+    let template = match (webhook.action.as_str(), run_template(suite.conclusion.as_deref())) {
+        ("completed", Some(template)) => template,
+        _ => return Vec::new(),
+    };
+
+    let recipient = match person(&webhook.sender, config) {
+        Some(recipient) => recipient,
+        None => return Vec::new(),
+    };
+    let title = suite.head_commit.message.lines().next().unwrap_or_default();
+    let pipeline = json!({
+        "id": suite.id,
+        "url": suite.pull_requests.first().map(|pr| format!("{}/checks", pull_request_url(&webhook.repository, pr.number))),
+        "kind": "",
+    });
+    run_messages(recipient, template, title, pipeline, &suite.pull_requests, &webhook.repository, config)
+}
+
+pub fn process_webhook(webhook: &ParsedWebhook, config: &Config) -> Vec<Message> {
+    let repository = webhook.0.repository();
+    if !config.projects.allows(&repository.full_name) {
+        debug!("Skipping webhook for repository: {}", repository.full_name);
+        return Vec::new();
+    }
+
+    match &webhook.0 {
+        Webhook::CheckSuite(webhook) => process_check_suite(webhook, config),
+        Webhook::PullRequest(webhook) => process_pull_request(webhook, config),
+        Webhook::PullRequestReview(webhook) => process_pull_request_review(webhook, config),
+        Webhook::WorkflowRun(webhook) => process_workflow_run(webhook, config),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_review_requested() {
+        let config: Config = serde_json::from_str(r#"
+        {
+          "gitlab": { "access_token": "", "hostname": "gitlab.com" },
+          "webex": { "access_token": "" },
+          "github": { "identities": { "reviewer": "reviewer@example.com" } }
+        }
+        "#).unwrap();
+        let json = r#"
+        {
+          "action": "review_requested",
+          "number": 7,
+          "pull_request": {
+            "number": 7,
+            "title": "Fix it",
+            "html_url": "https://github.com/g/p/pull/7",
+            "user": { "login": "author" },
+            "merged": false
+          },
+          "repository": {
+            "id": 42,
+            "name": "p",
+            "full_name": "g/p",
+            "html_url": "https://github.com/g/p"
+          },
+          "sender": { "login": "author" },
+          "requested_reviewer": { "login": "Reviewer" }
+        }
+        "#;
+
+        let webhook = parse_webhook("pull_request", json.as_bytes(), &config).unwrap();
+        let messages = process_webhook(&webhook, &config);
+        assert_eq!(1, messages.len());
+        assert_eq!(Recipient::Person("reviewer@example.com".to_owned()), messages[0].recipient);
+        assert_eq!(
+            "[!7 Fix it](https://github.com/g/p/pull/7) ([p](https://github.com/g/p)) by @author 👀 Added as reviewer",
+            messages[0].message);
+
+        match parse_webhook("push", b"{}", &config) {
+            Err(WebhookError::Unsupported(event)) => assert_eq!("push", event),
+            _ => panic!("Expected push webhook to be unsupported"),
+        }
+    }
+}
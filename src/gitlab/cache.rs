@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Responses from GitLab kept for a short while, as one pipeline sends
+/// several webhooks in quick succession, each asking for the same details.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    /// Nothing is kept with a `ttl` of zero.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((stored_at, value)) if stored_at.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored_at, _)| now.duration_since(*stored_at) < self.ttl);
+        entries.insert(key, (now, value));
+    }
+
+    /// Forgets the value, e.g. after changing it in GitLab.
+    pub fn remove(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl<K, V> fmt::Debug for TtlCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TtlCache")
+            .field("ttl", &self.ttl)
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ttl_cache() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        assert_eq!(None, cache.get(&(1, 2)));
+        cache.insert((1, 2), "running");
+        assert_eq!(Some("running"), cache.get(&(1, 2)));
+        cache.remove(&(1, 2));
+        assert_eq!(None, cache.get(&(1, 2)));
+        assert_eq!((1, 2), (cache.hits(), cache.misses()));
+
+        let disabled = TtlCache::new(Duration::ZERO);
+        disabled.insert((1, 2), "running");
+        assert_eq!(None, disabled.get(&(1, 2)));
+    }
+}
@@ -1,54 +1,573 @@
-use gitlab::Gitlab;
-use gitlab::api::{projects, Query};
-use tracing::debug;
+use std::cmp::Reverse;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
-use super::common::{Pipeline, MergeRequest};
+use chrono::{DateTime, Utc};
+use gitlab::{AsyncGitlab, GitlabBuilder, RestError};
+use gitlab::api::{self, projects, AsyncQuery};
+use reqwest::header::LINK;
+use serde::de::DeserializeOwned;
+use tracing::{debug, instrument};
+
+use super::cache::TtlCache;
+use super::common::{Approvals, CommitDetails, FeatureFlag, Job, Milestone, Note, Pipeline, MergeRequest, ProjectMember, UserBasic, UserEmails, UserProfile};
+
+#[derive(Debug)]
+pub enum GitlabClientError {
+    /// The endpoint couldn't be built from the given parameters.
+    Builder(String),
+    /// GitLab couldn't be reached, or answered with an error such as a 404.
+    Api(api::ApiError<RestError>),
+    /// A call made without the `gitlab` crate failed.
+    Http(reqwest::Error),
+}
+
+impl GitlabClientError {
+    /// Whether GitLab might answer next time, an error about the request itself won't change.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            GitlabClientError::Builder(_) => false,
+            GitlabClientError::Api(api::ApiError::Client { .. }) => true,
+            // GitLab, or a proxy in front of it, failing without saying why in JSON.
+            GitlabClientError::Api(api::ApiError::GitlabService { status, .. }) => status.is_server_error(),
+            GitlabClientError::Api(_) => false,
+            GitlabClientError::Http(err) => {
+                err.is_timeout() || err.is_connect() || err.status().is_some_and(|status| status.is_server_error())
+            }
+        }
+    }
+}
+
+impl fmt::Display for GitlabClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitlabClientError::Builder(err) => write!(f, "Bad GitLab request: {}", err),
+            GitlabClientError::Api(err) => write!(f, "GitLab request failed: {}", err),
+            GitlabClientError::Http(err) => write!(f, "GitLab request failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GitlabClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GitlabClientError::Builder(_) => None,
+            GitlabClientError::Api(err) => Some(err),
+            GitlabClientError::Http(err) => Some(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for GitlabClientError {
+    fn from(err: reqwest::Error) -> Self {
+        GitlabClientError::Http(err)
+    }
+}
+
+impl From<api::ApiError<RestError>> for GitlabClientError {
+    fn from(err: api::ApiError<RestError>) -> Self {
+        GitlabClientError::Api(err)
+    }
+}
+
+/// The URL of the next page from a `Link` header, if there is one.
+fn next_page(link: &str) -> Option<&str> {
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        let is_next = params.split(';').any(|param| param.trim() == "rel=\"next\"");
+        if is_next { url.trim().strip_prefix('<')?.strip_suffix('>') } else { None }
+    })
+}
 
 #[derive(Clone, Debug)]
 pub struct GitlabClient {
     hostname: String,
     access_token: String,
+    client: AsyncGitlab,
+    /// By project id and pipeline id.
+    pipelines: Arc<TtlCache<(u64, u64), Pipeline>>,
+    /// By project id and merge request iid.
+    merge_requests: Arc<TtlCache<(u64, u64), MergeRequest>>,
 }
 
 impl GitlabClient {
-    pub fn new(hostname: String, access_token: String) -> Self {
-        Self {
+    /// Builds the client which is shared by every request, this already talks to GitLab.
+    ///
+    /// Pipeline and merge request details are kept for `cache_ttl`.
+    pub async fn new(hostname: String, access_token: String, cache_ttl: Duration) -> Result<Self, gitlab::GitlabError> {
+        let client = GitlabBuilder::new(hostname.as_str(), access_token.as_str()).build_async().await?;
+
+        Ok(Self {
             hostname,
             access_token,
-        }
+            client,
+            pipelines: Arc::new(TtlCache::new(cache_ttl)),
+            merge_requests: Arc::new(TtlCache::new(cache_ttl)),
+        })
     }
 
+    /// The pipeline details cache, for its hit and miss counts.
+    pub fn pipeline_cache(&self) -> &TtlCache<(u64, u64), Pipeline> {
+        &self.pipelines
+    }
 
-    fn create_client(&self) -> Gitlab {
-        let hostname = &self.hostname;
-        let access_token = &self.access_token;
-        let client = Gitlab::new(hostname, access_token).unwrap();
+    /// The merge request details cache, for its hit and miss counts.
+    pub fn merge_request_cache(&self) -> &TtlCache<(u64, u64), MergeRequest> {
+        &self.merge_requests
+    }
+
+    /// The user the access token belongs to, which errors if GitLab rejects the token.
+    #[instrument(skip(self))]
+    pub async fn get_current_user(&self) -> Result<UserBasic, GitlabClientError> {
+        let endpoint = api::users::CurrentUser::builder()
+            .build()
+            .map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        let user: UserBasic = endpoint.query_async(&self.client).await?;
+        debug!("Current User: {:?}", user);
 
-        client
+        Ok(user)
     }
 
-    pub async fn get_pipeline_details(&self, project_id: u64, pipeline_id: u64) -> Option<Pipeline> {
-        let client = self.create_client();
+    #[instrument(skip(self))]
+    pub async fn get_pipeline_details(&self, project_id: u64, pipeline_id: u64) -> Result<Pipeline, GitlabClientError> {
+        if let Some(pipeline) = self.pipelines.get(&(project_id, pipeline_id)) {
+            return Ok(pipeline);
+        }
         let endpoint = projects::pipelines::Pipeline::builder()
             .project(project_id)
             .pipeline(pipeline_id)
             .build()
-            .unwrap();
-        let pipeline: Pipeline = endpoint.query(&client).unwrap();
+            .map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        let pipeline: Pipeline = endpoint.query_async(&self.client).await?;
         debug!("Pipeline: {:?}", pipeline);
-        Some(pipeline)
+        self.pipelines.insert((project_id, pipeline_id), pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_commit(&self, project_id: u64, sha: &str) -> Result<CommitDetails, GitlabClientError> {
+        let endpoint = projects::repository::commits::Commit::builder()
+            .project(project_id)
+            .commit(sha)
+            .build()
+            .map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        let commit: CommitDetails = endpoint.query_async(&self.client).await?;
+        debug!("Commit: {:?}", commit);
+
+        Ok(commit)
     }
 
-    pub async fn get_merge_request_details(&self, project_id: u64, merge_request_iid: u64) -> Option<MergeRequest> {
-        let client = self.create_client();
-        let endpoint  = projects::merge_requests::MergeRequest::builder()
+    #[instrument(skip(self))]
+    pub async fn get_pipeline_jobs(&self, project_id: u64, pipeline_id: u64) -> Result<Vec<Job>, GitlabClientError> {
+        let endpoint = projects::pipelines::PipelineJobs::builder()
+            .project(project_id)
+            .pipeline(pipeline_id)
+            .build()
+            .map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        let jobs: Vec<Job> = api::paged(endpoint, api::Pagination::All).query_async(&self.client).await?;
+        debug!("Jobs in pipeline {}: {}", pipeline_id, jobs.len());
+
+        Ok(jobs)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_merge_request_details(&self, project_id: u64, merge_request_iid: u64) -> Result<MergeRequest, GitlabClientError> {
+        if let Some(merge_request) = self.merge_requests.get(&(project_id, merge_request_iid)) {
+            return Ok(merge_request);
+        }
+        let endpoint = projects::merge_requests::MergeRequest::builder()
             .project(project_id)
             .merge_request(merge_request_iid)
             .build()
-            .unwrap();
-        let merge_request: MergeRequest = endpoint.query(&client).unwrap();
+            .map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        let merge_request: MergeRequest = endpoint.query_async(&self.client).await?;
         debug!("Merge Request: {:?}", merge_request);
+        self.merge_requests.insert((project_id, merge_request_iid), merge_request.clone());
+
+        Ok(merge_request)
+    }
+
+    /// The merge request in the project with the path, e.g. `group/project`.
+    #[instrument(skip(self))]
+    pub async fn get_project_merge_request(&self, project: &str, merge_request_iid: u64) -> Result<MergeRequest, GitlabClientError> {
+        let endpoint = projects::merge_requests::MergeRequest::builder()
+            .project(project)
+            .merge_request(merge_request_iid)
+            .build()
+            .map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        let merge_request: MergeRequest = endpoint.query_async(&self.client).await?;
+
+        Ok(merge_request)
+    }
+
+    /// Feature flags aren't covered by the `gitlab` crate, so this goes to the REST API directly.
+    #[instrument(skip(self))]
+    pub async fn get_feature_flag_details(&self, project_id: u64, name: &str) -> Result<FeatureFlag, GitlabClientError> {
+        let url = format!("https://{}/api/v4/projects/{}/feature_flags/{}", self.hostname, project_id, name);
+        let feature_flag: FeatureFlag = reqwest::Client::new()
+            .get(&url)
+            .header("PRIVATE-TOKEN", &self.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        debug!("Feature Flag: {:?}", feature_flag);
+
+        Ok(feature_flag)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_active_milestones(&self, project: &str) -> Option<Vec<Milestone>> {
+        let url = format!(
+            "https://{}/api/v4/projects/{}/milestones?state=active",
+            self.hostname,
+            project.replace('/', "%2F"));
+        let res = reqwest::Client::new()
+            .get(&url)
+            .header("PRIVATE-TOKEN", &self.access_token)
+            .send()
+            .await
+            .ok()?;
+        let milestones: Vec<Milestone> = res.json().await.ok()?;
+        debug!("Milestones: {:?}", milestones);
+
+        Some(milestones)
+    }
+
+    /// The user's access level to the project, including what they inherit
+    /// from its groups, or None if they aren't a member.
+    #[instrument(skip(self))]
+    pub async fn get_project_access_level(&self, project: &str, user_id: u64) -> Result<Option<u64>, GitlabClientError> {
+        let url = format!(
+            "https://{}/api/v4/projects/{}/members/all/{}",
+            self.hostname,
+            project.replace('/', "%2F"),
+            user_id);
+        let res = reqwest::Client::new()
+            .get(&url)
+            .header("PRIVATE-TOKEN", &self.access_token)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let member: ProjectMember = res.error_for_status()?.json().await?;
+
+        Ok(Some(member.access_level))
+    }
+
+    /// The project is given by its path or by its id, e.g. `group/project` or `42`.
+    /// The `gitlab` crate goes through all the pages.
+    #[instrument(skip(self))]
+    pub async fn list_open_merge_requests(&self, project: &str) -> Option<Vec<MergeRequest>> {
+        let endpoint = projects::merge_requests::MergeRequests::builder()
+            .project(project)
+            .state(projects::merge_requests::MergeRequestState::Opened)
+            .build()
+            .ok()?;
+        let merge_requests: Vec<MergeRequest> = api::paged(endpoint, api::Pagination::All).query_async(&self.client).await.ok()?;
+        debug!("Open Merge Requests in {}: {}", project, merge_requests.len());
+
+        Some(merge_requests)
+    }
+
+    /// Merge requests in any state which changed since `updated_after`.
+    #[instrument(skip(self))]
+    pub async fn list_merge_requests_updated_after(&self, project: &str, updated_after: DateTime<Utc>) -> Option<Vec<MergeRequest>> {
+        let endpoint = projects::merge_requests::MergeRequests::builder()
+            .project(project)
+            .updated_after(updated_after)
+            .build()
+            .ok()?;
+        let merge_requests: Vec<MergeRequest> = api::paged(endpoint, api::Pagination::All).query_async(&self.client).await.ok()?;
+        debug!("Merge Requests updated in {} since {}: {}", project, updated_after, merge_requests.len());
+
+        Some(merge_requests)
+    }
+
+    /// Every page of a REST API list, following the `next` link GitLab sends
+    /// with keyset and offset pagination alike.
+    async fn get_all_pages<T: DeserializeOwned>(&self, url: &str, query: &[(&str, String)]) -> Result<Vec<T>, GitlabClientError> {
+        let client = reqwest::Client::new();
+        let mut items = Vec::new();
+        let mut res = client
+            .get(url)
+            .query(query)
+            .query(&[("per_page", "100")])
+            .header("PRIVATE-TOKEN", &self.access_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        loop {
+            let next = res.headers().get(LINK).and_then(|link| link.to_str().ok()).and_then(next_page).map(str::to_owned);
+            let page: Vec<T> = res.json().await?;
+            items.extend(page);
+            let next = match next {
+                Some(next) => next,
+                None => break,
+            };
+            res = client
+                .get(&next)
+                .header("PRIVATE-TOKEN", &self.access_token)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+
+        Ok(items)
+    }
+
+    /// Merge requests across projects aren't covered by the `gitlab` crate, so
+    /// this goes to the REST API directly. Lists the open merge requests the
+    /// user is assigned to, in all projects.
+    #[instrument(skip(self))]
+    pub async fn list_merge_requests_assigned_to(&self, user_id: u64) -> Option<Vec<MergeRequest>> {
+        let url = format!("https://{}/api/v4/merge_requests", self.hostname);
+        let query = [("state", "opened".to_owned()), ("scope", "all".to_owned()), ("assignee_id", user_id.to_string())];
+        let merge_requests: Vec<MergeRequest> = self.get_all_pages(&url, &query).await.ok()?;
+        debug!("Open Merge Requests assigned to user {}: {}", user_id, merge_requests.len());
+
+        Some(merge_requests)
+    }
+
+    /// Lists the open merge requests the user is assigned to or reviewing,
+    /// most recently updated first.
+    #[instrument(skip(self))]
+    pub async fn list_merge_requests_for_user(&self, user_id: u64) -> Option<Vec<MergeRequest>> {
+        let mut merge_requests: Vec<MergeRequest> = Vec::new();
+        for role in &["assignee_id", "reviewer_id"] {
+            let url = format!("https://{}/api/v4/merge_requests", self.hostname);
+            let query = [("state", "opened".to_owned()), ("scope", "all".to_owned()), (*role, user_id.to_string())];
+            let found: Vec<MergeRequest> = self.get_all_pages(&url, &query).await.ok()?;
+            for merge_request in found {
+                if !merge_requests.iter().any(|listed| listed.id == merge_request.id) {
+                    merge_requests.push(merge_request);
+                }
+            }
+        }
+        merge_requests.sort_by_key(|merge_request| Reverse(merge_request.updated_at));
+        debug!("Open Merge Requests for user {}: {}", user_id, merge_requests.len());
+
+        Some(merge_requests)
+    }
+
+    /// Finds users by username, or by email if their email is public or the
+    /// access token belongs to an admin.
+    #[instrument(skip(self))]
+    pub async fn find_user(&self, username: Option<&str>, email: &str) -> Option<UserBasic> {
+        let url = format!("https://{}/api/v4/users", self.hostname);
+        let query = match username {
+            Some(username) => ("username", username),
+            None => ("search", email),
+        };
+        let res = reqwest::Client::new()
+            .get(&url)
+            .query(&[query])
+            .header("PRIVATE-TOKEN", &self.access_token)
+            .send()
+            .await
+            .ok()?;
+        let users: Vec<UserBasic> = res.json().await.ok()?;
+        debug!("Users for {:?}: {:?}", query, users);
+
+        users.into_iter().next()
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_user_emails(&self, user_id: u64) -> Option<UserEmails> {
+        let endpoint = api::users::User::builder()
+            .user(user_id)
+            .build()
+            .ok()?;
+        endpoint.query_async(&self.client).await.ok()
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_user_profile(&self, user_id: u64) -> Option<UserProfile> {
+        let endpoint = api::users::User::builder()
+            .user(user_id)
+            .build()
+            .ok()?;
+        endpoint.query_async(&self.client).await.ok()
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_merge_request_notes(&self, project_id: u64, merge_request_iid: u64) -> Option<Vec<Note>> {
+        let endpoint = projects::merge_requests::notes::MergeRequestNotes::builder()
+            .project(project_id)
+            .merge_request(merge_request_iid)
+            .build()
+            .ok()?;
+        let notes: Vec<Note> = api::paged(endpoint, api::Pagination::All).query_async(&self.client).await.ok()?;
+        debug!("Merge Request Notes: {}", notes.len());
+
+        Some(notes)
+    }
+
+    /// Who approved the merge request so far.
+    #[instrument(skip(self))]
+    pub async fn get_merge_request_approvals(&self, project_id: u64, merge_request_iid: u64) -> Option<Approvals> {
+        let endpoint = projects::merge_requests::approvals::MergeRequestApprovals::builder()
+            .project(project_id)
+            .merge_request(merge_request_iid)
+            .build()
+            .ok()?;
+        let approvals: Approvals = endpoint.query_async(&self.client).await.ok()?;
+        debug!("Merge Request Approvals: {}", approvals.approved_by.len());
+
+        Some(approvals)
+    }
+
+    #[instrument(skip(self, body))]
+    pub async fn create_merge_request_note(&self, project_id: u64, merge_request_iid: u64, body: &str) -> Option<()> {
+        let endpoint = projects::merge_requests::notes::CreateMergeRequestNote::builder()
+            .project(project_id)
+            .merge_request(merge_request_iid)
+            .body(body)
+            .build()
+            .ok()?;
+        api::ignore(endpoint).query_async(&self.client).await.ok()
+    }
+
+    /// Whoever the token belongs to, which errors if GitLab rejects it.
+    #[instrument(skip(self, token))]
+    pub async fn get_token_user(&self, token: &str) -> Result<UserBasic, GitlabClientError> {
+        let url = format!("https://{}/api/v4/user", self.hostname);
+        let user = reqwest::Client::new()
+            .get(&url)
+            .header("PRIVATE-TOKEN", token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(user)
+    }
+
+    /// Adds a webhook for the events revbot works with to the project.
+    #[instrument(skip(self, token))]
+    pub async fn create_project_hook(&self, project_id: u64, url: &str, token: Option<&str>) -> Result<(), GitlabClientError> {
+        let mut builder = projects::hooks::CreateHook::builder();
+        builder
+            .project(project_id)
+            .url(url)
+            .merge_requests_events(true)
+            .pipeline_events(true)
+            .job_events(true)
+            .note_events(true);
+        if let Some(token) = token {
+            builder.token(token);
+        }
+        let endpoint = builder.build().map_err(|err| GitlabClientError::Builder(err.to_string()))?;
+        api::ignore(endpoint).query_async(&self.client).await?;
+
+        Ok(())
+    }
+
+    /// Replaces the reviewers of the merge request.
+    #[instrument(skip(self))]
+    pub async fn set_reviewers(&self, project_id: u64, merge_request_iid: u64, reviewer_ids: &[u64]) -> Result<(), GitlabClientError> {
+        let url = format!("https://{}/api/v4/projects/{}/merge_requests/{}", self.hostname, project_id, merge_request_iid);
+        reqwest::Client::new()
+            .put(&url)
+            .json(&serde_json::json!({ "reviewer_ids": reviewer_ids }))
+            .header("PRIVATE-TOKEN", &self.access_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        self.merge_requests.remove(&(project_id, merge_request_iid));
+
+        Ok(())
+    }
+
+    /// Retries the failed jobs of the pipeline as whoever the token belongs to.
+    #[instrument(skip(self, token))]
+    pub async fn retry_pipeline(&self, project_id: u64, pipeline_id: u64, token: &str) -> Result<Pipeline, GitlabClientError> {
+        let url = format!("https://{}/api/v4/projects/{}/pipelines/{}/retry", self.hostname, project_id, pipeline_id);
+        let pipeline = reqwest::Client::new()
+            .post(&url)
+            .header("PRIVATE-TOKEN", token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.pipelines.remove(&(project_id, pipeline_id));
+
+        Ok(pipeline)
+    }
+
+    /// Approves the merge request as whoever the token belongs to.
+    #[instrument(skip(self, token))]
+    pub async fn approve_merge_request(&self, project_id: u64, merge_request_iid: u64, token: &str) -> Result<(), GitlabClientError> {
+        let url = format!("https://{}/api/v4/projects/{}/merge_requests/{}/approve", self.hostname, project_id, merge_request_iid);
+        reqwest::Client::new()
+            .post(&url)
+            .header("PRIVATE-TOKEN", token)
+            .send()
+            .await?
+            .error_for_status()?;
+        self.merge_requests.remove(&(project_id, merge_request_iid));
+
+        Ok(())
+    }
+
+    /// Merges the merge request once its pipeline succeeds, as whoever the token belongs to.
+    #[instrument(skip(self, token))]
+    pub async fn merge_merge_request(&self, project_id: u64, merge_request_iid: u64, token: &str) -> Result<(), GitlabClientError> {
+        let url = format!("https://{}/api/v4/projects/{}/merge_requests/{}/merge", self.hostname, project_id, merge_request_iid);
+        reqwest::Client::new()
+            .put(&url)
+            .query(&[("merge_when_pipeline_succeeds", "true")])
+            .header("PRIVATE-TOKEN", token)
+            .send()
+            .await?
+            .error_for_status()?;
+        self.merge_requests.remove(&(project_id, merge_request_iid));
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, body))]
+    pub async fn edit_merge_request_note(&self, project_id: u64, merge_request_iid: u64, note_id: u64, body: &str) -> Option<()> {
+        let endpoint = projects::merge_requests::notes::EditMergeRequestNote::builder()
+            .project(project_id)
+            .merge_request(merge_request_iid)
+            .note(note_id)
+            .body(body)
+            .build()
+            .ok()?;
+        api::ignore(endpoint).query_async(&self.client).await.ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_next_page() {
+        let link = "<https://gitlab.example.com/api/v4/merge_requests?id_after=42&per_page=100>; rel=\"next\", \
+            <https://gitlab.example.com/api/v4/merge_requests?per_page=100>; rel=\"first\"";
+        assert_eq!(Some("https://gitlab.example.com/api/v4/merge_requests?id_after=42&per_page=100"), next_page(link));
+        assert_eq!(None, next_page("<https://gitlab.example.com/api/v4/merge_requests?page=1>; rel=\"first\""));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let unavailable = api::ApiError::GitlabService { status: hyper::StatusCode::BAD_GATEWAY, data: Vec::new() };
+        assert!(GitlabClientError::Api(unavailable).is_transient());
+        let unreachable = reqwest::Client::new().get("not a url").build().unwrap_err();
+        assert!(GitlabClientError::Api(api::ApiError::client(RestError::from(unreachable))).is_transient());
 
-        Some(merge_request)
+        let not_found = api::ApiError::GitlabService { status: hyper::StatusCode::NOT_FOUND, data: Vec::new() };
+        assert!(!GitlabClientError::Api(not_found).is_transient());
+        let rejected = api::ApiError::Gitlab { msg: "403 Forbidden".to_owned() };
+        assert!(!GitlabClientError::Api(rejected).is_transient());
+        assert!(!GitlabClientError::Builder("project is required".to_owned()).is_transient());
     }
 }
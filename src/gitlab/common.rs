@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 pub use gitlab::types::{MergeStatus, StatusState};
 use serde::Deserialize;
 
@@ -17,6 +17,42 @@ impl PartialEq for User {
     }
 }
 
+/// A user's public profile.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UserProfile {
+    pub username: String,
+    #[serde(default)]
+    pub bio: Option<String>,
+}
+
+/// Someone's membership of a project, directly or through its groups.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProjectMember {
+    pub access_level: u64,
+}
+
+/// The email addresses of a user which are visible to the access token.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UserEmails {
+    pub email: Option<String>,
+    pub public_email: Option<String>,
+    pub username: String,
+}
+
+impl UserEmails {
+    /// The user's email, if it's visible to us, or their public email otherwise.
+    pub fn best_email(self) -> Option<String> {
+        self.email.or(self.public_email).filter(|email| !email.is_empty())
+    }
+}
+
+/// Someone as deployment webhooks describe them, without their id.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Deployer {
+    pub email: String,
+    pub username: String,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct UserBasic {
     pub id: u64,
@@ -24,34 +60,194 @@ pub struct UserBasic {
     pub web_url: String,
 }
 
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Commit {
+    pub id: String,
+    pub message: String,
+    pub url: String,
+}
+
+/// A commit as the commits API describes it.
+#[derive(Debug, Deserialize)]
+pub struct CommitDetails {
+    pub id: String,
+    pub short_id: String,
+    pub title: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub web_url: String,
+}
+
+/// The commit a job ran on, as included in job webhooks.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct JobCommit {
+    pub message: String,
+    pub sha: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Label {
+    pub id: u64,
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct IssueAttributes {
+    pub action: Option<String>,
+    pub author_id: u64,
+    #[serde(default)]
+    pub confidential: bool,
+    pub iid: u64,
+    pub title: String,
+    pub url: String,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct MergeRequestAttributes {
     pub action: Option<String>,
+    pub author_id: Option<u64>,
+    pub description: Option<String>,
+    /// Called `work_in_progress` by older versions of GitLab.
+    #[serde(alias = "work_in_progress")]
+    pub draft: Option<bool>,
     pub iid: u64,
+    #[serde(default)]
+    pub labels: Vec<Label>,
     pub merge_status: MergeStatus,
+    pub source_branch: Option<String>,
+    pub target_branch: Option<String>,
     pub title: String,
     pub url: String,
 }
 
+impl MergeRequestAttributes {
+    pub fn is_draft(&self) -> bool {
+        self.draft.unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FeatureFlagAttributes {
+    pub active: bool,
+    pub description: Option<String>,
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct MilestoneAttributes {
+    pub due_date: Option<NaiveDate>,
+    pub id: u64,
+    pub iid: u64,
+    pub state: String,
+    pub title: String,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct PipelineAttributes {
+    pub created_at: Option<String>,
+    /// How long the pipeline ran, in seconds.
+    pub duration: Option<u64>,
     pub finished_at: Option<String>,
     pub id: u64,
+    /// How long the pipeline waited for runners, in seconds.
+    pub queued_duration: Option<u64>,
     #[serde(rename = "ref")]
     pub ref_: String,
+    pub source: Option<String>,
     pub status: StatusState,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PipelineKind {
+    /// A pipeline for a branch or tag.
+    Branch,
+    /// A merge request pipeline, run on the source branch alone.
+    Detached,
+    /// A merge request pipeline, run on the result of merging into the target branch.
+    MergedResult,
+}
+
+impl PipelineAttributes {
+    /// Merge request pipelines run on `refs/merge-requests/:iid/head` when
+    /// detached and on `refs/merge-requests/:iid/merge` (or `/train` for merge
+    /// trains) for merged results.
+    pub fn kind(&self) -> PipelineKind {
+        match self.ref_.rsplit('/').next() {
+            Some("merge") | Some("train") if self.merge_request_iid_from_ref().is_some() => PipelineKind::MergedResult,
+            Some("head") if self.merge_request_iid_from_ref().is_some() => PipelineKind::Detached,
+            _ if self.source.as_deref() == Some("merge_request_event") => PipelineKind::Detached,
+            _ => PipelineKind::Branch,
+        }
+    }
+
+    pub fn merge_request_iid_from_ref(&self) -> Option<u64> {
+        let rest = self.ref_.strip_prefix("refs/merge-requests/")?;
+        let (iid, _) = rest.split_once('/')?;
+        iid.parse().ok()
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct NoteAttributes {
+    pub id: u64,
+    pub note: String,
+    pub noteable_type: String,
+    /// Notes GitLab adds itself, e.g. "added 1 commit".
+    #[serde(default)]
+    pub system: bool,
+    pub url: String,
+}
+
+/// The merge request a note was made on, as included in note webhooks.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct NoteMergeRequestAttributes {
+    #[serde(default)]
+    pub assignee_ids: Vec<u64>,
+    pub author_id: u64,
+    pub iid: u64,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct WikiPageAttributes {
+    pub action: String,
+    pub diff_url: Option<String>,
+    pub message: Option<String>,
+    pub slug: String,
+    pub title: String,
+    pub url: String,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Project {
     pub id: u64,
     pub name: String,
     pub path_with_namespace: String,
+    pub visibility_level: Option<u8>,
     pub web_url: String,
 }
 
-#[derive(Debug, Deserialize)]
+impl Project {
+    /// Projects of unknown visibility are assumed to be private.
+    pub fn is_private(&self) -> bool {
+        self.visibility_level.unwrap_or(0) == 0
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct Pipeline {
+    pub id: u64,
+    pub created_at: Option<DateTime<Utc>>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// How long the pipeline ran, in seconds.
+    pub duration: Option<u64>,
+    /// How long the pipeline waited for runners, in seconds.
+    pub queued_duration: Option<f64>,
     #[serde(rename = "ref")]
     pub ref_: String,
     pub status: StatusState,
@@ -59,19 +255,101 @@ pub struct Pipeline {
 }
 
 #[derive(Debug, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub name: String,
+    pub stage: String,
+    pub status: StatusState,
+    pub allow_failure: bool,
+    pub web_url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct MergeRequest {
     pub title: String,
     //    description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub merged_at: Option<DateTime<Utc>>,
     pub author: UserBasic,
     pub assignees: Option<Vec<UserBasic>>,
     pub reviewers: Option<Vec<UserBasic>>,
     pub id: u64,
     pub iid: u64,
+    pub project_id: u64,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// `opened`, `closed`, `locked` or `merged`.
+    pub state: String,
     pub merge_status: String,
     pub work_in_progress: bool,
     pub web_url: String,
     pub pipeline: Option<Pipeline>,
 }
 
+
+#[derive(Debug, Deserialize)]
+pub struct Note {
+    pub id: u64,
+    pub body: String,
+    pub author: UserBasic,
+    pub created_at: DateTime<Utc>,
+    /// Notes GitLab adds itself, e.g. about new commits.
+    pub system: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Approval {
+    pub user: UserBasic,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Approvals {
+    pub approved_by: Vec<Approval>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Milestone {
+    pub id: u64,
+    pub title: String,
+    pub due_date: Option<NaiveDate>,
+    pub web_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeatureFlagScope {
+    pub environment_scope: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeatureFlagStrategy {
+    pub scopes: Vec<FeatureFlagScope>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeatureFlag {
+    pub strategies: Vec<FeatureFlagStrategy>,
+}
+
+impl FeatureFlag {
+    /// Whether any strategy of this flag is scoped to `environment`.
+    ///
+    /// Environment scopes may contain `*` wildcards, e.g. `review/*`.
+    pub fn applies_to(&self, environment: &str) -> bool {
+        self.strategies
+            .iter()
+            .flat_map(|strategy| strategy.scopes.iter())
+            .any(|scope| environment_scope_matches(&scope.environment_scope, environment))
+    }
+}
+
+fn environment_scope_matches(scope: &str, environment: &str) -> bool {
+    match scope.split_once('*') {
+        Some((prefix, suffix)) => {
+            environment.len() >= prefix.len() + suffix.len()
+                && environment.starts_with(prefix)
+                && environment.ends_with(suffix)
+        }
+        None => scope == environment,
+    }
+}
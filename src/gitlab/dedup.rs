@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Pipeline statuses notified about recently, so that retried deliveries and
+/// resent webhooks don't notify twice.
+#[derive(Default)]
+pub struct PipelineStatusCache {
+    seen: Mutex<HashMap<(u64, u64, &'static str), Instant>>,
+}
+
+impl PipelineStatusCache {
+    /// Whether this status of the pipeline hasn't been seen within the `ttl`,
+    /// which then counts as seeing it.
+    pub fn first_seen(&self, project_id: u64, pipeline_id: u64, status: &'static str, ttl: Duration) -> bool {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, seen_at| now.duration_since(*seen_at) < ttl);
+
+        seen.insert((project_id, pipeline_id, status), now).is_none()
+    }
+
+    /// Forgets that the status was seen, e.g. when it couldn't be notified about.
+    pub fn forget(&self, project_id: u64, pipeline_id: u64, status: &'static str) {
+        self.seen.lock().unwrap().remove(&(project_id, pipeline_id, status));
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::message::{MergeRequestRef, Message};
+use super::client::GitlabClient;
+
+/// Identifies revbot's own comment, GitLab doesn't render HTML comments.
+const MARKER: &str = "<!-- revbot notifications -->";
+
+fn notification_line(message: &Message, config: &Config) -> String {
+    format!("- {}: {}", message.recipient, config.redaction.scrub(&message.message))
+}
+
+/// Records the sent messages in a single comment on each merge request they're
+/// about, so that there's an audit trail in GitLab itself.
+///
+/// The comment is created the first time and appended to afterwards.
+pub async fn mirror_notifications(messages: &[Message], gitlab_client: &GitlabClient, config: &Config) {
+    let mut by_merge_request = BTreeMap::<MergeRequestRef, Vec<&Message>>::new();
+    for message in messages {
+        if let Some(merge_request) = message.merge_request {
+            by_merge_request.entry(merge_request).or_default().push(message);
+        }
+    }
+
+    for (merge_request, messages) in by_merge_request {
+        let lines: Vec<String> = messages.iter().map(|message| notification_line(message, config)).collect();
+        let lines = lines.join("\n");
+
+        let notes = match gitlab_client.get_merge_request_notes(merge_request.project_id, merge_request.iid).await {
+            Some(notes) => notes,
+            None => {
+                warn!("Couldn't fetch notes for !{} in project {}", merge_request.iid, merge_request.project_id);
+                continue;
+            }
+        };
+
+        let result = match notes.iter().find(|note| note.body.starts_with(MARKER)) {
+            Some(note) => {
+                let body = format!("{}\n{}", note.body, lines);
+                gitlab_client.edit_merge_request_note(merge_request.project_id, merge_request.iid, note.id, &body).await
+            }
+            None => {
+                let body = format!("{}\n🤖 **revbot** sent these notifications:\n\n{}", MARKER, lines);
+                gitlab_client.create_merge_request_note(merge_request.project_id, merge_request.iid, &body).await
+            }
+        };
+
+        match result {
+            Some(_) => info!("Mirrored notifications to !{} in project {}", merge_request.iid, merge_request.project_id),
+            None => warn!("Couldn't mirror notifications to !{} in project {}", merge_request.iid, merge_request.project_id),
+        }
+    }
+}
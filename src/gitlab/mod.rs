@@ -1,3 +1,6 @@
+pub mod cache;
 pub mod client;
 pub mod common;
+pub mod dedup;
+pub mod mirror;
 pub mod webhook;
@@ -1,35 +1,42 @@
 use std::fmt;
+use std::time::Duration;
 
-use bytes::Bytes;
+use chrono::Utc;
 use serde::Deserialize;
-use serde_json::Value;
-use tracing::debug;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn, Level};
 
-use crate::message::Message;
+use crate::cards;
+use crate::config::Config;
+use crate::config::{ReviewerStrategy, RuleTarget};
+use crate::error::RevbotError;
+use crate::identity;
+use crate::review_sla::ReviewRequest;
+use crate::rules::{self, Event};
+use crate::message::{Action, MergeRequestAction, MergeRequestRef, Message, Recipient};
 use super::client::GitlabClient;
-use super::common::{MergeRequestAttributes, PipelineAttributes, Project, StatusState, User};
+use super::dedup::PipelineStatusCache;
+use super::common::{Commit, Deployer, FeatureFlagAttributes, IssueAttributes, JobCommit, Label, MergeRequestAttributes, MergeStatus, MilestoneAttributes, NoteAttributes, NoteMergeRequestAttributes, PipelineAttributes, PipelineKind, Project, StatusState, User, UserBasic, WikiPageAttributes};
 
-#[derive(Clone, Debug)]
-struct NotFound;
-
-impl fmt::Display for NotFound {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Details Not Found")
-    }
+/// Why a webhook is turned away before it's processed.
+#[derive(Debug)]
+pub enum WebhookError {
+    /// Not JSON, or missing what its `object_kind` needs.
+    Malformed(serde_json::Error),
+    /// An `object_kind` (or system hook `event_name`) which revbot doesn't handle.
+    Unsupported(String),
 }
 
-impl std::error::Error for NotFound {}
-
-#[derive(Clone, Debug)]
-struct UnsupportedWebhook;
-
-impl fmt::Display for UnsupportedWebhook {
+impl fmt::Display for WebhookError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Unsupported Webhook")
+        match self {
+            WebhookError::Malformed(err) => write!(f, "Malformed Webhook: {}", err),
+            WebhookError::Unsupported(object_kind) => write!(f, "Unsupported Webhook: {}", object_kind),
+        }
     }
 }
 
-impl std::error::Error for UnsupportedWebhook {}
+impl std::error::Error for WebhookError {}
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 struct AssigneeChanges {
@@ -37,18 +44,66 @@ struct AssigneeChanges {
     previous: Vec<User>,
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct ReviewerChanges {
+    current: Vec<User>,
+    previous: Vec<User>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct LabelChanges {
+    current: Vec<Label>,
+    previous: Vec<Label>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+struct FlagChanges {
+    current: Option<bool>,
+    previous: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct MergeStatusChanges {
+    current: Option<MergeStatus>,
+    previous: Option<MergeStatus>,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 struct Changes {
     assignees: Option<AssigneeChanges>,
+    /// Draft status, called `work_in_progress` by older versions of GitLab.
+    draft: Option<FlagChanges>,
+    labels: Option<LabelChanges>,
+    merge_status: Option<MergeStatusChanges>,
+    reviewers: Option<ReviewerChanges>,
+    work_in_progress: Option<FlagChanges>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct IssueChanges {
+    assignees: Option<AssigneeChanges>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct IssueWebhook {
+    assignees: Option<Vec<User>>,
+    changes: Option<IssueChanges>,
+    #[serde(rename = "object_attributes")]
+    issue: IssueAttributes,
+    labels: Option<Vec<Label>>,
+    project: Project,
+    user: User,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 struct MergeRequestWebhook {
     assignees: Option<Vec<User>>,
     changes: Option<Changes>,
+    labels: Option<Vec<Label>>,
     #[serde(rename = "object_attributes")]
     merge_request: MergeRequestAttributes,
     project: Project,
+    reviewers: Option<Vec<User>>,
     user: User,
 }
 
@@ -62,11 +117,49 @@ impl MergeRequestWebhook {
             None => return None,
         }
     }
+
+    fn get_reviewer_changes(&self) -> Option<&ReviewerChanges> {
+        self.changes.as_ref()?.reviewers.as_ref()
+    }
+
+    /// Whether the merge request stopped being a draft with this event.
+    fn became_ready(&self) -> bool {
+        let changes = match &self.changes {
+            Some(changes) => changes,
+            None => return false,
+        };
+        matches!(changes.draft.or(changes.work_in_progress), Some(FlagChanges { previous: Some(true), current: Some(false) }))
+    }
+
+    /// Whether the merge request started conflicting with its target branch with this event.
+    fn became_unmergeable(&self) -> bool {
+        match &self.changes {
+            Some(Changes { merge_status: Some(MergeStatusChanges { current: Some(MergeStatus::CannotBeMerged), previous }), .. }) => {
+                previous.as_ref() != Some(&MergeStatus::CannotBeMerged)
+            }
+            _ => false,
+        }
+    }
+
+    /// The labels on the merge request after this event.
+    ///
+    /// For label change events, the changes are taken as the authority.
+    fn get_labels(&self) -> &[Label] {
+        if let Some(Changes { labels: Some(label_changes), .. }) = &self.changes {
+            return &label_changes.current;
+        }
+
+        match &self.labels {
+            Some(labels) => labels,
+            None => &self.merge_request.labels,
+        }
+    }
 }
 
 
 #[derive(Debug, Deserialize, PartialEq)]
 struct PipelineWebhook {
+    commit: Option<Commit>,
     merge_request: Option<MergeRequestAttributes>,
     #[serde(rename = "object_attributes")]
     pipeline: PipelineAttributes,
@@ -75,11 +168,416 @@ struct PipelineWebhook {
 }
 
 
+/// GitLab still calls jobs builds in their webhooks, which aren't nested
+/// under `object_attributes` either.
+#[derive(Debug, Deserialize, PartialEq)]
+struct JobWebhook {
+    #[serde(default)]
+    build_allow_failure: bool,
+    build_failure_reason: Option<String>,
+    build_id: u64,
+    build_name: String,
+    build_stage: String,
+    build_status: StatusState,
+    commit: Option<JobCommit>,
+    pipeline_id: u64,
+    project: Project,
+    #[serde(rename = "ref")]
+    ref_: String,
+    user: User,
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct DeploymentWebhook {
+    commit_url: Option<String>,
+    deployable_url: String,
+    deployment_id: u64,
+    environment: String,
+    environment_external_url: Option<String>,
+    project: Project,
+    #[serde(rename = "ref")]
+    ref_: String,
+    short_sha: String,
+    status: String,
+    user: Deployer,
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct FeatureFlagWebhook {
+    #[serde(rename = "object_attributes")]
+    feature_flag: FeatureFlagAttributes,
+    project: Project,
+    user: User,
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct MilestoneWebhook {
+    action: String,
+    #[serde(rename = "object_attributes")]
+    milestone: MilestoneAttributes,
+    project: Project,
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct NoteWebhook {
+    /// Only set for notes on merge requests.
+    merge_request: Option<NoteMergeRequestAttributes>,
+    #[serde(rename = "object_attributes")]
+    note: NoteAttributes,
+    project: Project,
+    user: User,
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct PushWebhook {
+    /// Missing when the branch was deleted.
+    checkout_sha: Option<String>,
+    #[serde(default)]
+    commits: Vec<Commit>,
+    project: Project,
+    #[serde(rename = "ref")]
+    ref_: String,
+    total_commits_count: usize,
+    user_username: String,
+}
+
+impl PushWebhook {
+    fn branch(&self) -> &str {
+        self.ref_.strip_prefix("refs/heads/").unwrap_or(&self.ref_)
+    }
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct ReleaseWebhook {
+    action: String,
+    description: Option<String>,
+    name: String,
+    project: Project,
+    tag: String,
+    url: String,
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct TagPushWebhook {
+    /// Missing when the tag was deleted.
+    checkout_sha: Option<String>,
+    project: Project,
+    #[serde(rename = "ref")]
+    ref_: String,
+    user_username: String,
+}
+
+impl TagPushWebhook {
+    fn tag(&self) -> &str {
+        self.ref_.strip_prefix("refs/tags/").unwrap_or(&self.ref_)
+    }
+}
+
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct WikiPageWebhook {
+    #[serde(rename = "object_attributes")]
+    wiki_page: WikiPageAttributes,
+    project: Project,
+    user: User,
+}
+
+
+/// The system hook events about a project showing up at a path.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+enum ProjectEventName {
+    #[serde(rename = "project_create")]
+    Create,
+    #[serde(rename = "project_rename")]
+    Rename,
+    #[serde(rename = "project_transfer")]
+    Transfer,
+}
+
+/// A project event from a system hook. Unlike the rest, these have an
+/// `event_name` instead of an `object_kind`, and the project's fields at the top.
+#[derive(Debug, Deserialize, PartialEq)]
+struct ProjectWebhook {
+    event_name: ProjectEventName,
+    project_id: u64,
+    path_with_namespace: String,
+    /// Where a renamed or transferred project was before.
+    old_path_with_namespace: Option<String>,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(tag = "object_kind", rename_all = "snake_case")]
 enum Webhook {
+    Deployment(DeploymentWebhook),
+    FeatureFlag(FeatureFlagWebhook),
+    Issue(IssueWebhook),
+    #[serde(rename = "build")]
+    Job(JobWebhook),
     MergeRequest(MergeRequestWebhook),
+    Milestone(MilestoneWebhook),
+    Note(NoteWebhook),
     Pipeline(PipelineWebhook),
+    Push(PushWebhook),
+    Release(ReleaseWebhook),
+    TagPush(TagPushWebhook),
+    WikiPage(WikiPageWebhook),
+    /// Parsed by `event_name`, as it has no `object_kind`.
+    #[serde(skip)]
+    Project(ProjectWebhook),
+    #[serde(other)]
+    Unsupported,
+}
+
+impl Webhook {
+    fn project(&self) -> Option<&Project> {
+        match self {
+            Webhook::Deployment(webhook) => Some(&webhook.project),
+            Webhook::FeatureFlag(webhook) => Some(&webhook.project),
+            Webhook::Issue(webhook) => Some(&webhook.project),
+            Webhook::Job(webhook) => Some(&webhook.project),
+            Webhook::MergeRequest(webhook) => Some(&webhook.project),
+            Webhook::Milestone(webhook) => Some(&webhook.project),
+            Webhook::Note(webhook) => Some(&webhook.project),
+            Webhook::Pipeline(webhook) => Some(&webhook.project),
+            Webhook::Push(webhook) => Some(&webhook.project),
+            Webhook::Release(webhook) => Some(&webhook.project),
+            Webhook::TagPush(webhook) => Some(&webhook.project),
+            Webhook::WikiPage(webhook) => Some(&webhook.project),
+            Webhook::Project(_) | Webhook::Unsupported => None,
+        }
+    }
+}
+
+impl Webhook {
+    /// What the webhook is about, for matching rules.
+    fn event(&self) -> Option<Event<'_>> {
+        let event = match self {
+            Webhook::Deployment(webhook) => Event {
+                kind: "deployment",
+                project: &webhook.project.path_with_namespace,
+                branch: Some(&webhook.ref_),
+                labels: Vec::new(),
+                user: Some(&webhook.user.username),
+                status: Some(&webhook.status),
+            },
+            Webhook::FeatureFlag(webhook) => Event {
+                kind: "feature_flag",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: Vec::new(),
+                user: Some(&webhook.user.username),
+                status: Some(if webhook.feature_flag.active { "enabled" } else { "disabled" }),
+            },
+            Webhook::Issue(webhook) => Event {
+                kind: "issue",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: webhook.labels.iter().flatten().map(|label| label.title.as_str()).collect(),
+                user: Some(&webhook.user.username),
+                status: webhook.issue.action.as_deref(),
+            },
+            Webhook::Job(webhook) => Event {
+                kind: "build",
+                project: &webhook.project.path_with_namespace,
+                branch: Some(&webhook.ref_),
+                labels: Vec::new(),
+                user: Some(&webhook.user.username),
+                status: Some(webhook.build_status.as_str()),
+            },
+            Webhook::MergeRequest(webhook) => Event {
+                kind: "merge_request",
+                project: &webhook.project.path_with_namespace,
+                branch: webhook.merge_request.source_branch.as_deref(),
+                labels: webhook.get_labels().iter().map(|label| label.title.as_str()).collect(),
+                user: Some(&webhook.user.username),
+                status: webhook.merge_request.action.as_deref(),
+            },
+            Webhook::Milestone(webhook) => Event {
+                kind: "milestone",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: Vec::new(),
+                user: None,
+                status: Some(&webhook.action),
+            },
+            Webhook::Note(webhook) => Event {
+                kind: "note",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: webhook.merge_request.iter().flat_map(|merge_request| &merge_request.labels).map(|label| label.title.as_str()).collect(),
+                user: Some(&webhook.user.username),
+                status: None,
+            },
+            Webhook::Pipeline(webhook) => Event {
+                kind: "pipeline",
+                project: &webhook.project.path_with_namespace,
+                branch: Some(&webhook.pipeline.ref_),
+                labels: Vec::new(),
+                user: Some(&webhook.user.username),
+                status: Some(webhook.pipeline.status.as_str()),
+            },
+            Webhook::Push(webhook) => Event {
+                kind: "push",
+                project: &webhook.project.path_with_namespace,
+                branch: Some(webhook.branch()),
+                labels: Vec::new(),
+                user: Some(&webhook.user_username),
+                status: None,
+            },
+            Webhook::Release(webhook) => Event {
+                kind: "release",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: Vec::new(),
+                user: None,
+                status: Some(&webhook.action),
+            },
+            Webhook::TagPush(webhook) => Event {
+                kind: "tag_push",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: Vec::new(),
+                user: Some(&webhook.user_username),
+                status: None,
+            },
+            Webhook::WikiPage(webhook) => Event {
+                kind: "wiki_page",
+                project: &webhook.project.path_with_namespace,
+                branch: None,
+                labels: Vec::new(),
+                user: Some(&webhook.user.username),
+                status: Some(&webhook.wiki_page.action),
+            },
+            Webhook::Project(_) | Webhook::Unsupported => return None,
+        };
+
+        Some(event)
+    }
+}
+
+/// A webhook which revbot knows how to process.
+pub struct ParsedWebhook(Webhook);
+
+impl ParsedWebhook {
+    /// The webhook's `object_kind`, or `event_name` for system hooks.
+    pub fn kind(&self) -> &'static str {
+        match &self.0 {
+            Webhook::Project(webhook) => match webhook.event_name {
+                ProjectEventName::Create => "project_create",
+                ProjectEventName::Rename => "project_rename",
+                ProjectEventName::Transfer => "project_transfer",
+            },
+            webhook => webhook.event().map_or("unsupported", |event| event.kind),
+        }
+    }
+
+    pub fn project_path(&self) -> Option<&str> {
+        match &self.0 {
+            Webhook::Project(webhook) => Some(webhook.path_with_namespace.as_str()),
+            webhook => webhook.project().map(|project| project.path_with_namespace.as_str()),
+        }
+    }
+
+    /// The pipeline the webhook is about, if it's about one.
+    pub fn pipeline_id(&self) -> Option<u64> {
+        match &self.0 {
+            Webhook::Pipeline(webhook) => Some(webhook.pipeline.id),
+            _ => None,
+        }
+    }
+
+    /// The merge request the webhook is about, if it says.
+    pub fn merge_request_iid(&self) -> Option<u64> {
+        match &self.0 {
+            Webhook::MergeRequest(webhook) => Some(webhook.merge_request.iid),
+            Webhook::Note(webhook) => webhook.merge_request.as_ref().map(|merge_request| merge_request.iid),
+            Webhook::Pipeline(webhook) => match &webhook.merge_request {
+                Some(merge_request) => Some(merge_request.iid),
+                None => webhook.pipeline.merge_request_iid_from_ref(),
+            },
+            _ => None,
+        }
+    }
+
+    /// The reviewers this webhook asks to review a merge request which isn't
+    /// a draft, i.e. those just added and, when the draft status was dropped,
+    /// all of them.
+    pub fn review_requests(&self) -> Vec<ReviewRequest> {
+        let webhook = match &self.0 {
+            Webhook::MergeRequest(webhook) if !webhook.merge_request.is_draft() => webhook,
+            _ => return Vec::new(),
+        };
+        let reviewers = if webhook.became_ready() {
+            webhook.reviewers.iter().flatten().filter(|reviewer| reviewer.id != webhook.user.id).cloned().collect()
+        } else {
+            webhook.get_reviewer_changes().map(get_new_reviewers).unwrap_or_default()
+        };
+
+        reviewers
+            .into_iter()
+            .map(|reviewer| ReviewRequest {
+                project_id: webhook.project.id,
+                project: webhook.project.path_with_namespace.clone(),
+                iid: webhook.merge_request.iid,
+                reviewer_id: reviewer.id,
+                reviewer: reviewer.username,
+                asked_at: Utc::now(),
+                escalations: 0,
+            })
+            .collect()
+    }
+}
+
+/// Whether the commit message ends with a `Notify: none` or `Revbot-Silence: true` trailer.
+fn has_silence_trailer(commit_message: &str) -> bool {
+    let trailers = commit_message.trim_end().rsplit("\n\n").next().unwrap_or("");
+    trailers.lines().any(|line| match line.split_once(':') {
+        Some((key, value)) => {
+            let (key, value) = (key.trim(), value.trim());
+            (key.eq_ignore_ascii_case("Notify") && value.eq_ignore_ascii_case("none"))
+                || (key.eq_ignore_ascii_case("Revbot-Silence") && value.eq_ignore_ascii_case("true"))
+        }
+        None => false,
+    })
+}
+
+/// Whether the content of a private project configured as sensitive must be kept out of messages.
+fn is_confidential(project: &Project, config: &Config) -> bool {
+    project.is_private() && config.redaction.is_sensitive(&project.path_with_namespace)
+}
+
+/// The merge request title to show in a message, which is redacted for
+/// private projects configured as sensitive.
+fn displayed_title<'a>(title: &'a str, project: &Project, config: &Config) -> &'a str {
+    if is_confidential(project, config) {
+        "[confidential]"
+    } else {
+        title
+    }
+}
+
+const NOTE_SNIPPET_CHARS: usize = 200;
+
+/// The start of a comment, quoted so that it stands out from the rest of the message.
+pub(crate) fn note_snippet(note: &str) -> String {
+    let note = note.trim();
+    let mut snippet: String = note.chars().take(NOTE_SNIPPET_CHARS).collect();
+    if snippet.len() < note.len() {
+        snippet.push('…');
+    }
+
+    snippet
+        .lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 fn get_new_assignees(assignee_changes: &AssigneeChanges) -> Vec<User> {
@@ -91,148 +589,1553 @@ fn get_new_assignees(assignee_changes: &AssigneeChanges) -> Vec<User> {
         .collect()
 }
 
-fn process_new_assignee(new_assignee: &User, webhook: &MergeRequestWebhook) -> Option<Message> {
+fn get_new_reviewers(reviewer_changes: &ReviewerChanges) -> Vec<User> {
+    reviewer_changes.current
+        .iter()
+        .filter(|reviewer| !reviewer_changes.previous.contains(reviewer))
+        .cloned()
+        .collect()
+}
+
+/// The project as template context, for the `project` partial.
+fn project_context(project: &Project) -> Value {
+    json!({
+        "name": project.name,
+        "url": project.web_url,
+    })
+}
+
+/// The template context for messages about a merge request.
+fn merge_request_context(webhook: &MergeRequestWebhook, config: &Config) -> Value {
     let merge_request = &webhook.merge_request;
     let project = &webhook.project;
-    let user = &webhook.user;
 
-    let recipient_email = new_assignee.email.to_owned();
-    let message = format!(
-        "[!{mr_iid} {mr_title}]({mr_url}) \
-        ([{project_name}]({project_url})) \
-        by @{user} \
-        🤩 Added as assignee",
-        mr_iid=merge_request.iid, mr_title=merge_request.title, mr_url=merge_request.url,
-        project_name=project.name, project_url=project.web_url, user=user.username);
+    let description = if is_confidential(project, config) { None } else { merge_request.description.as_deref() };
 
-    Some(Message {
-        recipient_email,
-        message,
+    json!({
+        "merge_request": {
+            "iid": merge_request.iid,
+            "title": displayed_title(&merge_request.title, project, config),
+            "url": merge_request.url,
+            "description": description,
+            "draft": merge_request.is_draft(),
+            "labels": webhook.get_labels().iter().map(|label| &label.title).collect::<Vec<_>>(),
+            "source_branch": merge_request.source_branch,
+            "target_branch": merge_request.target_branch,
+        },
+        "project": project_context(project),
+        "user": webhook.user.username,
     })
 }
 
-async fn process_pipeline_status(webhook: &PipelineWebhook, gitlab_client: &GitlabClient) -> Option<Message> {
+/// Posts a copy of each message into the team rooms for the project. The same
+/// text sent to several people is only posted once.
+fn copy_to_team_rooms(messages: &mut Vec<Message>, project: &Project, config: &Config) {
+    let mut copies = Vec::new();
+    for team_room in config.team_rooms.iter().filter(|team_room| team_room.projects.is_match(&project.path_with_namespace)) {
+        for message in messages.iter() {
+            let recipient = Recipient::Room(team_room.room_id.to_owned());
+            if !copies.iter().any(|copy: &Message| copy.recipient == recipient && copy.message == message.message) {
+                copies.push(Message {
+                    recipient,
+                    ..message.clone()
+                });
+            }
+        }
+    }
+    messages.extend(copies);
+}
+
+fn process_added_user(added_user: &User, template: &str, webhook: &MergeRequestWebhook, config: &Config) -> Option<Message> {
+    let recipient = Recipient::Person(identity::webex_email(added_user, config));
+    merge_request_message(recipient, template, webhook, config)
+}
+
+async fn process_pipeline_status(webhook: &PipelineWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
     let pipeline = &webhook.pipeline;
     let project = &webhook.project;
     let user = &webhook.user;
 
-    let recipient_email = user.email.to_owned();
-    let status_text = match pipeline.status {
-        StatusState::Success => Some("🌞 Success"),
-        StatusState::Failed => Some("⛈️ Failed"),
-        StatusState::Running => Some("⏳ Running"),
-        _ => None,
-    }?;
+    let recipient = Recipient::Person(identity::webex_email(user, config));
+    let template = match pipeline.status {
+        StatusState::Success => "pipeline_success",
+        StatusState::Failed => "pipeline_failed",
+        StatusState::Running => "pipeline_running",
+        _ => return Ok(Vec::new()),
+    };
+    // Merge request pipelines don't always carry the merge request, but the ref names it.
+    let merge_request_iid = match webhook.merge_request.as_ref().map(|merge_request| merge_request.iid) {
+        Some(merge_request_iid) => merge_request_iid,
+        None => match pipeline.merge_request_iid_from_ref() {
+            Some(merge_request_iid) => merge_request_iid,
+            // Other pipelines are only notified about when they break a watched branch.
+            None => return process_broken_branch(webhook, gitlab_client, config).await,
+        },
+    };
+    if !config.notifications.enabled(template) {
+        return Ok(Vec::new());
+    }
+
+    // GitLab being unavailable is worth another try, anything else isn't.
+    let pipeline_details = match gitlab_client.get_pipeline_details(project.id, pipeline.id).await {
+        Ok(pipeline_details) => pipeline_details,
+        Err(err) if !err.is_transient() => {
+            warn!("Skipping pipeline {} without details: {}", pipeline.id, err);
+            return Ok(Vec::new());
+        }
+        Err(source) => return Err(RevbotError::Gitlab { call: "get_pipeline_details", source }),
+    };
+    let merge_request = match gitlab_client.get_merge_request_details(project.id, merge_request_iid).await {
+        Ok(merge_request) => merge_request,
+        Err(err) if !err.is_transient() => {
+            warn!("Skipping pipeline {} without merge request !{}: {}", pipeline.id, merge_request_iid, err);
+            return Ok(Vec::new());
+        }
+        Err(source) => return Err(RevbotError::Gitlab { call: "get_merge_request_details", source }),
+    };
+    // Pipeline webhooks don't carry the merge request labels, and merge request
+    // pipelines may not carry the title either, so we check the details.
+    if config.filters.skips_title(&merge_request.title)
+        || config.filters.silences_labels(merge_request.labels.iter().map(|label| label.as_str())) {
+        debug!("Skipping pipeline for silenced merge request: !{}", merge_request.iid);
+        return Ok(Vec::new());
+    }
 
+    let failed_jobs = if pipeline.status == StatusState::Failed {
+        failed_jobs(project, pipeline, gitlab_client).await
+    } else {
+        Vec::new()
+    };
+    let kind_text = match pipeline.kind() {
+        PipelineKind::Branch => "",
+        PipelineKind::Detached => "detached",
+        PipelineKind::MergedResult => "merged result",
+    };
+    let context = json!({
+        "merge_request": {
+            "iid": merge_request.iid,
+            "title": displayed_title(&merge_request.title, project, config),
+            "url": merge_request.web_url,
+        },
+        "project": project_context(project),
+        "pipeline": {
+            "id": pipeline.id,
+            "url": pipeline_details.web_url,
+            "kind": kind_text,
+            // Details fetched while the pipeline ran may have been cached, the webhook is fresh.
+            "duration": pipeline.duration.or(pipeline_details.duration).map(format_duration),
+            "queued": pipeline.queued_duration.or(pipeline_details.queued_duration.map(|queued| queued as u64)).map(format_duration),
+        },
+        "failed_jobs": failed_jobs,
+        "user": user.username,
+    });
+    let mut actions = vec![
+        Action::open("Open MR", &merge_request.web_url),
+        Action::open("View pipeline", &pipeline_details.web_url),
+    ];
+    if config.actions.is_some() && template == "pipeline_failed" {
+        let merge_request_ref = MergeRequestRef {
+            project_id: project.id,
+            iid: merge_request.iid,
+        };
+        actions.push(Action::submit("Retry pipeline", &pipeline_details.web_url, MergeRequestAction::RetryPipeline, merge_request_ref));
+    }
 
-    let gitlab_client = gitlab_client.clone();
-    let pipeline_details = gitlab_client.get_pipeline_details(webhook.project.id, webhook.pipeline.id).await?;
-    // We intentionally skip pipelines that don't have a merge request attached.
-    let merge_request_iid = webhook.merge_request.as_ref()?.iid;
-    let merge_request = gitlab_client.get_merge_request_details(webhook.project.id, merge_request_iid).await?;
+    // Reviewers can hold off on reviewing a merge request whose pipeline failed.
+    let mut recipients = vec![recipient];
+    if config.pipelines.notifies_reviewers(&project.path_with_namespace, pipeline.status.as_str()) {
+        for reviewer in merge_request.reviewers.iter().flatten().filter(|reviewer| reviewer.id != user.id) {
+            match identity::webex_email_by_id(reviewer.id, Some(&reviewer.username), gitlab_client, config).await {
+                Some(email) => recipients.push(Recipient::Person(email)),
+                None => debug!("No email visible for reviewer @{}, not notifying them", reviewer.username),
+            }
+        }
+    }
+    recipients.dedup();
+    let thread = config.webex.thread_pipelines.then(|| format!("pipelines {}!{}", project.id, merge_request.iid));
+    let replaces = config.webex.edit_pipeline_messages.then(|| format!("pipeline {}", pipeline.id));
 
-    let message = format!(
-        "[!{mr_iid} {mr_title}]({mr_url}) \
-        ([{project_name}]({project_url})) \
-        [#{pipeline_id}]({pipeline_url}) \
-        {pipeline_status}",
-        mr_iid=merge_request.iid, mr_title=merge_request.title, mr_url=merge_request.web_url,
-        project_name=project.name, project_url=project.web_url,
-        pipeline_id=pipeline.id, pipeline_url=pipeline_details.web_url, pipeline_status=status_text);
+    let messages = recipients
+        .into_iter()
+        .filter_map(|recipient| Some(Message {
+            message: config.render(&recipient, template, &context).ok()?,
+            recipient,
+            merge_request: Some(MergeRequestRef {
+                project_id: project.id,
+                iid: merge_request.iid,
+            }),
+            actions: cards::actions_for(template, actions.clone(), config),
+            thread: thread.clone(),
+            replaces: replaces.clone(),
+            event: Some(template.to_owned()),
+        }))
+        .collect();
 
-    Some(Message {
-        recipient_email,
-        message,
-    })
+    Ok(messages)
 }
 
-fn process_merge_request(webhook: &MergeRequestWebhook) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
-    let mut messages = Vec::<Message>::new();
-    if let Some(assignee_changes) = webhook.get_assignee_changes() {
-        for new_assignee in get_new_assignees(assignee_changes) {
-            if let Some(msg) = process_new_assignee(&new_assignee, &webhook) {
-                messages.push(msg);
-            }
+/// The jobs which failed the pipeline as template context. Knowing which jobs
+/// failed tells a lint error from a failed deploy.
+async fn failed_jobs(project: &Project, pipeline: &PipelineAttributes, gitlab_client: &GitlabClient) -> Vec<Value> {
+    match gitlab_client.get_pipeline_jobs(project.id, pipeline.id).await {
+        Ok(jobs) => jobs
+            .into_iter()
+            .filter(|job| job.status == StatusState::Failed && !job.allow_failure)
+            .map(|job| json!({
+                "name": job.name,
+                "stage": job.stage,
+                "url": job.web_url,
+            }))
+            .collect(),
+        Err(err) => {
+            debug!("Couldn't fetch the jobs of pipeline {}: {}", pipeline.id, err);
+            Vec::new()
         }
     }
+}
+
+/// Tells whoever triggered a pipeline which failed outside of a merge request
+/// that they broke the branch, if it's one which is watched. Not the commit's
+/// author, whose email anyone can set.
+async fn process_broken_branch(webhook: &PipelineWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let pipeline = &webhook.pipeline;
+    let project = &webhook.project;
+    let (broken_branches, commit) = match (&config.pipelines.broken_branches, &webhook.commit) {
+        (Some(broken_branches), Some(commit)) => (broken_branches, commit),
+        _ => return Ok(Vec::new()),
+    };
+    if pipeline.status != StatusState::Failed
+        || !broken_branches.watches(&project.path_with_namespace, &pipeline.ref_)
+        || !config.notifications.enabled("branch_broken") {
+        debug!("Skipping pipeline {} without a merge request", pipeline.id);
+        return Ok(Vec::new());
+    }
+
+    let commit = match gitlab_client.get_commit(project.id, &commit.id).await {
+        Ok(commit) => commit,
+        Err(err) if !err.is_transient() => {
+            warn!("Skipping pipeline {} without its commit: {}", pipeline.id, err);
+            return Ok(Vec::new());
+        }
+        Err(source) => return Err(RevbotError::Gitlab { call: "get_commit", source }),
+    };
+    let pipeline_url = format!("{}/-/pipelines/{}", project.web_url, pipeline.id);
+    let context = json!({
+        "project": project_context(project),
+        "pipeline": {
+            "id": pipeline.id,
+            "url": pipeline_url,
+        },
+        "ref": pipeline.ref_,
+        "commit": {
+            "short_id": commit.short_id,
+            "title": commit.title,
+            "url": commit.web_url,
+            "author": commit.author_name,
+        },
+        "failed_jobs": failed_jobs(project, pipeline, gitlab_client).await,
+    });
+    let actions = vec![
+        Action::open("View pipeline", &pipeline_url),
+        Action::open("View commit", &commit.web_url),
+    ];
+
+    let recipients = std::iter::once(Recipient::Person(identity::webex_email(&webhook.user, config)))
+        .chain(broken_branches.room_id.iter().map(|room_id| Recipient::Room(room_id.to_owned())));
+    let messages = recipients
+        .filter_map(|recipient| Some(Message {
+            message: config.render(&recipient, "branch_broken", &context).ok()?,
+            recipient,
+            merge_request: None,
+            actions: cards::actions_for("branch_broken", actions.clone(), config),
+            thread: None,
+            replaces: None,
+            event: Some("branch_broken".to_owned()),
+        }))
+        .collect();
 
     Ok(messages)
 }
 
-async fn process_pipeline(webhook: &PipelineWebhook, gitlab_client: &GitlabClient) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
+/// A duration in seconds as e.g. `1h 2m 3s` or `14m 32s`.
+fn format_duration(secs: u64) -> String {
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", secs),
+        (0, _) => format!("{}m {}s", minutes, secs),
+        _ => format!("{}h {}m {}s", hours, minutes, secs),
+    }
+}
 
-    match process_pipeline_status(webhook, gitlab_client).await {
-        Some(message) => Ok(vec![message]),
-        None => Ok(Vec::new()),
+/// The email of the merge request author, who isn't necessarily the user who triggered the webhook.
+async fn get_author_email(merge_request: &MergeRequestAttributes, project: &Project, gitlab_client: &GitlabClient, config: &Config) -> Option<String> {
+    match merge_request.author_id {
+        Some(author_id) => identity::webex_email_by_id(author_id, None, gitlab_client, config).await,
+        None => {
+            let author = gitlab_client.get_merge_request_details(project.id, merge_request.iid).await.ok()?.author;
+            identity::webex_email_by_id(author.id, Some(&author.username), gitlab_client, config).await
+        }
     }
 }
 
-pub async fn process_webhook(bytes: Bytes, gitlab_client: GitlabClient) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
-    let string = String::from_utf8(bytes.to_vec())?;
-    let webhook: Webhook = serde_json::from_str(&string).map_err(|_| UnsupportedWebhook)?;
-    let v: Value = serde_json::from_str(&string).unwrap();
-    debug!("Received Webhook: {}", serde_json::to_string_pretty(&v).unwrap());
+async fn process_approval(webhook: &MergeRequestWebhook, gitlab_client: &GitlabClient, config: &Config) -> Option<Message> {
+    let merge_request = &webhook.merge_request;
+    let project = &webhook.project;
+    let user = &webhook.user;
 
-    let response = match webhook {
-        Webhook::MergeRequest(webhook) => process_merge_request(&webhook),
-        Webhook::Pipeline(webhook) => process_pipeline(&webhook, &gitlab_client).await,
+    let template = match merge_request.action.as_deref() {
+        Some("approved") => "approved",
+        Some("unapproved") => "unapproved",
+        _ => return None,
     };
+    if merge_request.author_id == Some(user.id) {
+        return None;
+    }
 
-    response
+    let recipient = Recipient::Person(get_author_email(merge_request, project, gitlab_client, config).await?);
+    merge_request_message(recipient, template, webhook, config)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use gitlab::types::MergeStatus;
+/// Tells the author when their branch can't be merged into the target branch any more.
+async fn process_merge_conflict(webhook: &MergeRequestWebhook, gitlab_client: &GitlabClient, config: &Config) -> Option<Message> {
+    if !webhook.became_unmergeable() {
+        return None;
+    }
 
-    #[test]
-    fn test_deserialize_merge_request() {
-        let json = r#"
-        {
-          "object_attributes": {
-            "created_at": "2021-09-06 10:54:57 -0500",
-            "description": "",
-            "id": 289144,
-            "iid": 3,
-            "merge_error": null,
-            "merge_status": "unchecked",
-            "merge_when_pipeline_succeeds": false,
-            "state": "opened",
-            "state_id": 1,
-            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3",
-            "title": "Fail pipeline"
-          },
-          "object_kind": "merge_request",
-          "project": {
-            "id": 17898,
-            "name": "mr-test",
-            "path_with_namespace": "hds-/mr-test",
-            "web_url": "https://gitlab.com/hds-/mr-test"
-          },
-          "user": {
-            "email": "hds@example.com",
-            "id": 1069,
-            "name": "Hayden Stainsby",
-            "username": "hds-"
-          }
+    let recipient = Recipient::Person(get_author_email(&webhook.merge_request, &webhook.project, gitlab_client, config).await?);
+    merge_request_message(recipient, "merge_conflict", webhook, config)
+}
+
+fn merge_request_message(recipient: Recipient, template: &str, webhook: &MergeRequestWebhook, config: &Config) -> Option<Message> {
+    if !config.notifications.enabled(template) {
+        return None;
+    }
+    let message = config.render(&recipient, template, &merge_request_context(webhook, config)).ok()?;
+    let merge_request = MergeRequestRef {
+        project_id: webhook.project.id,
+        iid: webhook.merge_request.iid,
+    };
+    let url = &webhook.merge_request.url;
+    let mut actions = vec![Action::open("Open MR", url)];
+    if config.actions.is_some() {
+        match template {
+            "reviewer_added" | "ready_for_review" => actions.push(Action::submit("Approve", url, MergeRequestAction::Approve, merge_request)),
+            "approved" => actions.push(Action::submit("Merge when pipeline succeeds", url, MergeRequestAction::MergeWhenPipelineSucceeds, merge_request)),
+            _ => {}
+        }
+    }
+
+    Some(Message {
+        recipient,
+        message,
+        merge_request: Some(merge_request),
+        actions: cards::actions_for(template, actions, config),
+        thread: None,
+        replaces: None,
+        event: Some(template.to_owned()),
+    })
+}
+
+async fn process_merged_or_closed(webhook: &MergeRequestWebhook, gitlab_client: &GitlabClient, config: &Config) -> Vec<Message> {
+    let user = &webhook.user;
+
+    match webhook.merge_request.action.as_deref() {
+        Some("merge") if config.merge_requests.merged => {
+            // Whoever merged it knows already.
+            let mut recipients: Vec<&User> = Vec::new();
+            for recipient in webhook.assignees.iter().chain(&webhook.reviewers).flatten() {
+                if recipient != user && !recipients.contains(&recipient) {
+                    recipients.push(recipient);
+                }
+            }
+            recipients
+                .into_iter()
+                .filter_map(|recipient| merge_request_message(Recipient::Person(identity::webex_email(recipient, config)), "merged", webhook, config))
+                .collect()
+        }
+        Some("close") if config.merge_requests.closed => {
+            if webhook.merge_request.author_id == Some(user.id) {
+                return Vec::new();
+            }
+            get_author_email(&webhook.merge_request, &webhook.project, gitlab_client, config)
+                .await
+                .and_then(|email| merge_request_message(Recipient::Person(email), "closed", webhook, config))
+                .into_iter()
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The reviewer whose turn it is for the merge request.
+fn round_robin<'a>(candidates: &[&'a str], merge_request_iid: u64) -> &'a str {
+    candidates[(merge_request_iid % candidates.len() as u64) as usize]
+}
+
+/// The reviewer with the fewest open merge requests to work on or review.
+async fn least_loaded(candidates: &[&str], gitlab_client: &GitlabClient) -> Option<UserBasic> {
+    let mut least_loaded: Option<(usize, UserBasic)> = None;
+    for username in candidates {
+        let user = match gitlab_client.find_user(Some(username), "").await {
+            Some(user) => user,
+            None => continue,
+        };
+        let load = match gitlab_client.list_merge_requests_for_user(user.id).await {
+            Some(merge_requests) => merge_requests.len(),
+            None => continue,
+        };
+        if least_loaded.as_ref().is_none_or(|(least, _)| load < *least) {
+            least_loaded = Some((load, user));
+        }
+    }
+
+    least_loaded.map(|(_, user)| user)
+}
+
+/// Picks a reviewer from the project's pool for a merge request opened
+/// without any. They hear about it from the webhook for the change.
+async fn assign_reviewer(webhook: &MergeRequestWebhook, gitlab_client: &GitlabClient, config: &Config) {
+    let merge_request = &webhook.merge_request;
+    if merge_request.action.as_deref() != Some("open") || merge_request.is_draft() || webhook.reviewers.iter().flatten().next().is_some() {
+        return;
+    }
+    let pool = match config.reviewer_pools.iter().find(|pool| pool.projects.is_match(&webhook.project.path_with_namespace)) {
+        Some(pool) => pool,
+        None => return,
+    };
+    let candidates: Vec<&str> = pool.reviewers
+        .iter()
+        .map(|username| username.as_str())
+        .filter(|username| !username.eq_ignore_ascii_case(&webhook.user.username))
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+
+    let reviewer = match pool.strategy {
+        ReviewerStrategy::RoundRobin => gitlab_client.find_user(Some(round_robin(&candidates, merge_request.iid)), "").await,
+        ReviewerStrategy::LeastLoaded => least_loaded(&candidates, gitlab_client).await,
+    };
+    let reviewer = match reviewer {
+        Some(reviewer) => reviewer,
+        None => {
+            warn!("Couldn't find a reviewer for !{} in GitLab", merge_request.iid);
+            return;
+        }
+    };
+    match gitlab_client.set_reviewers(webhook.project.id, merge_request.iid, &[reviewer.id]).await {
+        Ok(_) => info!("Picked @{} to review !{}", reviewer.username, merge_request.iid),
+        Err(err) => warn!("Couldn't make @{} the reviewer of !{}: {}", reviewer.username, merge_request.iid, err),
+    }
+}
+
+async fn process_merge_request(webhook: &MergeRequestWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let mut messages = Vec::<Message>::new();
+    if config.filters.skips_title(&webhook.merge_request.title) {
+        debug!("Skipping merge request with filtered title: {}", webhook.merge_request.title);
+        return Ok(messages);
+    }
+    if config.filters.silences_labels(webhook.get_labels().iter().map(|label| label.title.as_str())) {
+        debug!("Skipping silenced merge request: !{}", webhook.merge_request.iid);
+        return Ok(messages);
+    }
+    assign_reviewer(webhook, gitlab_client, config).await;
+
+    if let Some(assignee_changes) = webhook.get_assignee_changes() {
+        for new_assignee in get_new_assignees(assignee_changes) {
+            if let Some(msg) = process_added_user(&new_assignee, "assignee_added", webhook, config) {
+                messages.push(msg);
+            }
+        }
+    }
+
+    let new_reviewers = webhook.get_reviewer_changes().map(get_new_reviewers).unwrap_or_default();
+    for new_reviewer in &new_reviewers {
+        if let Some(msg) = process_added_user(new_reviewer, "reviewer_added", webhook, config) {
+            messages.push(msg);
+        }
+    }
+
+    // Reviewers who were only just added have heard about the merge request already.
+    if webhook.became_ready() {
+        let reviewers = webhook.reviewers.iter().flatten()
+            .filter(|reviewer| reviewer.id != webhook.user.id && !new_reviewers.contains(reviewer));
+        for reviewer in reviewers {
+            if let Some(msg) = process_added_user(reviewer, "ready_for_review", webhook, config) {
+                messages.push(msg);
+            }
+        }
+    }
+
+    if let Some(msg) = process_approval(webhook, gitlab_client, config).await {
+        messages.push(msg);
+    }
+    if let Some(msg) = process_merge_conflict(webhook, gitlab_client, config).await {
+        messages.push(msg);
+    }
+    messages.extend(process_merged_or_closed(webhook, gitlab_client, config).await);
+    copy_to_team_rooms(&mut messages, &webhook.project, config);
+
+    Ok(messages)
+}
+
+async fn process_pipeline(webhook: &PipelineWebhook, gitlab_client: &GitlabClient, config: &Config, pipeline_statuses: &PipelineStatusCache) -> Result<Vec<Message>, RevbotError> {
+    if let Some(commit) = &webhook.commit {
+        if has_silence_trailer(&commit.message) {
+            debug!("Skipping pipeline for commit with silence trailer: {}", commit.id);
+            return Ok(Vec::new());
+        }
+    }
+    if let Some(merge_request) = &webhook.merge_request {
+        if config.filters.skips_title(&merge_request.title) {
+            debug!("Skipping pipeline for merge request with filtered title: {}", merge_request.title);
+            return Ok(Vec::new());
+        }
+    }
+    let pipeline = &webhook.pipeline;
+    let merge_request_pipeline = pipeline.kind() != PipelineKind::Branch;
+    if !config.pipelines.notifies_ref(&webhook.project.path_with_namespace, &pipeline.ref_, merge_request_pipeline) {
+        debug!("Skipping pipeline for ref: {}", pipeline.ref_);
+        return Ok(Vec::new());
+    }
+    let dedup_ttl = Duration::from_secs(config.pipelines.dedup_ttl_secs);
+    if !dedup_ttl.is_zero()
+        && !pipeline_statuses.first_seen(webhook.project.id, webhook.pipeline.id, webhook.pipeline.status.as_str(), dedup_ttl) {
+        debug!("Skipping repeated {} status for pipeline: {}", webhook.pipeline.status.as_str(), webhook.pipeline.id);
+        return Ok(Vec::new());
+    }
+
+    // A webhook which fails here is tried again, which mustn't count as a repeat.
+    let mut messages = match process_pipeline_status(webhook, gitlab_client, config).await {
+        Ok(messages) => messages,
+        Err(err) => {
+            pipeline_statuses.forget(webhook.project.id, webhook.pipeline.id, webhook.pipeline.status.as_str());
+            return Err(err);
+        }
+    };
+    copy_to_team_rooms(&mut messages, &webhook.project, config);
+
+    Ok(messages)
+}
+
+/// Tells whoever triggered the pipeline about a failed job straight away,
+/// instead of waiting for the whole pipeline to finish.
+fn process_job(webhook: &JobWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    if webhook.build_status != StatusState::Failed || webhook.build_allow_failure || !config.notifications.enabled("job_failed") {
+        return Ok(Vec::new());
+    }
+    if let Some(commit) = &webhook.commit {
+        if has_silence_trailer(&commit.message) {
+            debug!("Skipping job for commit with silence trailer: {}", commit.sha);
+            return Ok(Vec::new());
+        }
+    }
+
+    let project = &webhook.project;
+    let recipient = Recipient::Person(identity::webex_email(&webhook.user, config));
+    let message = config.render(&recipient, "job_failed", &json!({
+        "job": {
+            "name": webhook.build_name,
+            "url": format!("{}/-/jobs/{}", project.web_url, webhook.build_id),
+            "stage": webhook.build_stage,
+            "failure_reason": webhook.build_failure_reason.as_ref().map(|reason| reason.replace('_', " ")),
+        },
+        "pipeline": {
+            "id": webhook.pipeline_id,
+            "url": format!("{}/-/pipelines/{}", project.web_url, webhook.pipeline_id),
+        },
+        "ref": webhook.ref_,
+        "project": project_context(project),
+        "user": webhook.user.username,
+    }))?;
+    let actions = vec![
+        Action::open("View job", &format!("{}/-/jobs/{}", project.web_url, webhook.build_id)),
+        Action::open("View pipeline", &format!("{}/-/pipelines/{}", project.web_url, webhook.pipeline_id)),
+    ];
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for("job_failed", actions, config),
+        thread: None,
+        replaces: None,
+        event: Some("job_failed".to_owned()),
+    }])
+}
+
+async fn process_feature_flag(webhook: &FeatureFlagWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let feature_flags_config = match &config.feature_flags {
+        Some(feature_flags_config) => feature_flags_config,
+        None => return Ok(Vec::new()),
+    };
+
+    let feature_flag = &webhook.feature_flag;
+    let project = &webhook.project;
+    let user = &webhook.user;
+
+    let template = if feature_flag.active { "feature_flag_enabled" } else { "feature_flag_disabled" };
+    if !feature_flags_config.watches_project(&project.path_with_namespace) || !config.notifications.enabled(template) {
+        return Ok(Vec::new());
+    }
+
+    if let Some(environments) = &feature_flags_config.environments {
+        let details = gitlab_client
+            .get_feature_flag_details(project.id, &feature_flag.name)
+            .await
+            .map_err(|source| RevbotError::Gitlab { call: "get_feature_flag_details", source })?;
+        if !environments.iter().any(|environment| details.applies_to(environment)) {
+            return Ok(Vec::new());
+        }
+    }
+
+    let recipient = Recipient::Room(feature_flags_config.room_id.to_owned());
+    let message = config.render(&recipient, template, &json!({
+        "feature_flag": {
+            "name": feature_flag.name,
+            "url": format!("{}/-/feature_flags", project.web_url),
+        },
+        "project": project_context(project),
+        "user": user.username,
+    }))?;
+    let actions = vec![Action::open("View feature flags", &format!("{}/-/feature_flags", project.web_url))];
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for(template, actions, config),
+        thread: None,
+        replaces: None,
+        event: Some(template.to_owned()),
+    }])
+}
+
+fn process_milestone(webhook: &MilestoneWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let milestones_config = match &config.milestones {
+        Some(milestones_config) => milestones_config,
+        None => return Ok(Vec::new()),
+    };
+
+    let milestone = &webhook.milestone;
+    let project = &webhook.project;
+
+    if !milestones_config.watches_project(&project.path_with_namespace) {
+        return Ok(Vec::new());
+    }
+
+    let template = match webhook.action.as_str() {
+        "create" => "milestone_created",
+        "close" => "milestone_closed",
+        "reopen" => "milestone_reopened",
+        _ => return Ok(Vec::new()),
+    };
+    if !config.notifications.enabled(template) {
+        return Ok(Vec::new());
+    }
+
+    let recipient = Recipient::Room(milestones_config.room_id.to_owned());
+    let message = config.render(&recipient, template, &json!({
+        "milestone": {
+            "title": milestone.title,
+            "url": format!("{}/-/milestones/{}", project.web_url, milestone.iid),
+            "due_date": milestone.due_date.map(|due_date| due_date.to_string()),
+        },
+        "project": project_context(project),
+    }))?;
+    let actions = vec![Action::open("Open milestone", &format!("{}/-/milestones/{}", project.web_url, milestone.iid))];
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for(template, actions, config),
+        thread: None,
+        replaces: None,
+        event: Some(template.to_owned()),
+    }])
+}
+
+async fn process_note(webhook: &NoteWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let merge_request = match &webhook.merge_request {
+        Some(merge_request) => merge_request,
+        None => return Ok(Vec::new()),
+    };
+    let note = &webhook.note;
+    let project = &webhook.project;
+    let user = &webhook.user;
+
+    if note.system || !config.notifications.enabled("note") {
+        return Ok(Vec::new());
+    }
+    if config.filters.skips_title(&merge_request.title) {
+        debug!("Skipping note on merge request with filtered title: {}", merge_request.title);
+        return Ok(Vec::new());
+    }
+    if config.filters.silences_labels(merge_request.labels.iter().map(|label| label.title.as_str())) {
+        debug!("Skipping note on silenced merge request: !{}", merge_request.iid);
+        return Ok(Vec::new());
+    }
+
+    let snippet = if is_confidential(project, config) { None } else { Some(note_snippet(&note.note)) };
+    let context = json!({
+        "merge_request": {
+            "iid": merge_request.iid,
+            "title": displayed_title(&merge_request.title, project, config),
+            "url": note.url,
+        },
+        "project": project_context(project),
+        "user": user.username,
+        "snippet": snippet,
+    });
+    let actions = cards::actions_for("note", vec![Action::open("Open comment", &note.url)], config);
+
+    // The author and the assignees hear about comments, except on their own.
+    let mut user_ids = vec![merge_request.author_id];
+    user_ids.extend(&merge_request.assignee_ids);
+    user_ids.sort_unstable();
+    user_ids.dedup();
+
+    let mut messages = Vec::new();
+    for user_id in user_ids.into_iter().filter(|&user_id| user_id != user.id) {
+        let email = match identity::webex_email_by_id(user_id, None, gitlab_client, config).await {
+            Some(email) => email,
+            None => {
+                debug!("No email visible for user {}, not notifying them", user_id);
+                continue;
+            }
+        };
+        let recipient = Recipient::Person(email);
+        messages.push(Message {
+            message: config.render(&recipient, "note", &context)?,
+            recipient,
+            merge_request: Some(MergeRequestRef {
+                project_id: project.id,
+                iid: merge_request.iid,
+            }),
+            actions: actions.clone(),
+            thread: None,
+            replaces: None,
+            event: Some("note".to_owned()),
+        });
+    }
+
+    Ok(messages)
+}
+
+fn process_wiki_page(webhook: &WikiPageWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let wiki_pages_config = match &config.wiki_pages {
+        Some(wiki_pages_config) => wiki_pages_config,
+        None => return Ok(Vec::new()),
+    };
+
+    let wiki_page = &webhook.wiki_page;
+    let project = &webhook.project;
+    let user = &webhook.user;
+
+    if !wiki_pages_config.watches_project(&project.path_with_namespace) {
+        return Ok(Vec::new());
+    }
+
+    let template = match wiki_page.action.as_str() {
+        "create" => "wiki_page_created",
+        "update" => "wiki_page_updated",
+        "delete" => "wiki_page_deleted",
+        _ => return Ok(Vec::new()),
+    };
+    if !config.notifications.enabled(template) {
+        return Ok(Vec::new());
+    }
+
+    let recipient = Recipient::Room(wiki_pages_config.room_id.to_owned());
+    let message = config.render(&recipient, template, &json!({
+        "wiki_page": {
+            "title": wiki_page.title,
+            "url": wiki_page.url,
+            "diff_url": wiki_page.diff_url,
+        },
+        "project": project_context(project),
+        "user": user.username,
+    }))?;
+    let actions = vec![Action::open("Open page", &wiki_page.url)];
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for(template, actions, config),
+        thread: None,
+        replaces: None,
+        event: Some(template.to_owned()),
+    }])
+}
+
+/// Tells everyone newly assigned to an issue about it. The titles of
+/// confidential issues are left out, like those in sensitive projects.
+async fn process_issue(webhook: &IssueWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let new_assignees = match webhook.changes.as_ref().and_then(|changes| changes.assignees.as_ref()) {
+        Some(assignee_changes) => get_new_assignees(assignee_changes),
+        None => return Ok(Vec::new()),
+    };
+    if new_assignees.is_empty() || !config.notifications.enabled("issue_assignee_added") {
+        return Ok(Vec::new());
+    }
+
+    let issue = &webhook.issue;
+    let title = if issue.confidential { "[confidential]" } else { displayed_title(&issue.title, &webhook.project, config) };
+    let author = gitlab_client.get_user_emails(issue.author_id).await.map(|author| author.username);
+    let context = json!({
+        "issue": {
+            "iid": issue.iid,
+            "title": title,
+            "url": issue.url,
+            "author": author,
+        },
+        "project": project_context(&webhook.project),
+        "user": webhook.user.username,
+    });
+    let actions = cards::actions_for("issue_assignee_added", vec![Action::open("Open issue", &issue.url)], config);
+
+    Ok(new_assignees
+        .iter()
+        .map(|assignee| {
+            let recipient = Recipient::Person(identity::webex_email(assignee, config));
+            config.render(&recipient, "issue_assignee_added", &context).map(|message| Message {
+                recipient,
+                message,
+                merge_request: None,
+                actions: actions.clone(),
+                thread: None,
+                replaces: None,
+                event: Some("issue_assignee_added".to_owned()),
+            })
+        })
+        .collect::<Result<_, _>>()?)
+}
+
+/// Tells the deployer how their deployment is going, and the environment's room too.
+fn process_deployment(webhook: &DeploymentWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let deployments_config = match &config.deployments {
+        Some(deployments_config) if deployments_config.watches(&webhook.project.path_with_namespace, &webhook.environment) => deployments_config,
+        _ => return Ok(Vec::new()),
+    };
+    let template = match webhook.status.as_str() {
+        "running" => "deployment_running",
+        "success" => "deployment_success",
+        "failed" => "deployment_failed",
+        _ => return Ok(Vec::new()),
+    };
+    if !config.notifications.enabled(template) {
+        return Ok(Vec::new());
+    }
+
+    let context = json!({
+        "deployment": {
+            "id": webhook.deployment_id,
+            "status": webhook.status,
+        },
+        "environment": {
+            "name": webhook.environment,
+            "url": webhook.environment_external_url,
+        },
+        "deployable_url": webhook.deployable_url,
+        "commit_url": webhook.commit_url,
+        "short_sha": webhook.short_sha,
+        "ref": webhook.ref_,
+        "project": project_context(&webhook.project),
+        "user": webhook.user.username,
+    });
+
+    let mut recipients = vec![Recipient::Person(identity::webex_email_by_username(&webhook.user.username, &webhook.user.email, config))];
+    if let Some(room_id) = deployments_config.rooms.get(&webhook.environment) {
+        recipients.push(Recipient::Room(room_id.to_owned()));
+    }
+    let actions = cards::actions_for(template, vec![Action::open("Open job", &webhook.deployable_url)], config);
+
+    Ok(recipients
+        .into_iter()
+        .map(|recipient| config.render(&recipient, template, &context).map(|message| Message {
+            recipient,
+            message,
+            merge_request: None,
+            actions: actions.clone(),
+            thread: None,
+            replaces: None,
+            event: Some(template.to_owned()),
+        }))
+        .collect::<Result<_, _>>()?)
+}
+
+/// How many of a push's commits are listed, the rest are only counted.
+const PUSH_COMMITS_LISTED: usize = 5;
+
+/// Announces pushes to the configured branches. The commits aren't listed for
+/// sensitive projects, as their titles could give them away.
+fn process_push(webhook: &PushWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let pushes_config = match &config.pushes {
+        Some(pushes_config) if pushes_config.watches(&webhook.project.path_with_namespace, webhook.branch()) => pushes_config,
+        _ => return Ok(Vec::new()),
+    };
+    if webhook.checkout_sha.is_none() || webhook.total_commits_count == 0 || !config.notifications.enabled("push") {
+        return Ok(Vec::new());
+    }
+
+    let commits: Vec<Value> = if is_confidential(&webhook.project, config) {
+        Vec::new()
+    } else {
+        // GitLab lists the oldest commit first.
+        webhook.commits.iter().rev().take(PUSH_COMMITS_LISTED).map(|commit| json!({
+            "short_id": commit.id.chars().take(8).collect::<String>(),
+            "title": commit.message.lines().next().unwrap_or_default(),
+            "url": commit.url,
+        })).collect()
+    };
+    let branch_url = format!("{}/-/commits/{}", webhook.project.web_url, webhook.branch());
+    let recipient = Recipient::Room(pushes_config.room_id.to_owned());
+    let message = config.render(&recipient, "push", &json!({
+        "branch": webhook.branch(),
+        "branch_url": branch_url,
+        "commits": commits,
+        "commits_count": webhook.total_commits_count,
+        "more": webhook.total_commits_count.saturating_sub(commits.len()),
+        "project": project_context(&webhook.project),
+        "user": webhook.user_username,
+    }))?;
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for("push", vec![Action::open("Open commits", &branch_url)], config),
+        thread: None,
+        replaces: None,
+        event: Some("push".to_owned()),
+    }])
+}
+
+/// Announces new releases in the releases room, with the start of their notes.
+fn process_release(webhook: &ReleaseWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let releases_config = match &config.releases {
+        Some(releases_config) if releases_config.watches_project(&webhook.project.path_with_namespace) => releases_config,
+        _ => return Ok(Vec::new()),
+    };
+    if webhook.action != "create" || !config.notifications.enabled("release_created") {
+        return Ok(Vec::new());
+    }
+
+    let notes = match &webhook.description {
+        Some(description) if !description.trim().is_empty() && !is_confidential(&webhook.project, config) => Some(note_snippet(description)),
+        _ => None,
+    };
+    let recipient = Recipient::Room(releases_config.room_id.to_owned());
+    let message = config.render(&recipient, "release_created", &json!({
+        "release": {
+            "name": webhook.name,
+            "tag": webhook.tag,
+            "url": webhook.url,
+            "notes": notes,
+        },
+        "project": project_context(&webhook.project),
+    }))?;
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for("release_created", vec![Action::open("Open release", &webhook.url)], config),
+        thread: None,
+        replaces: None,
+        event: Some("release_created".to_owned()),
+    }])
+}
+
+/// Announces new tags in the releases room, if it's configured to.
+fn process_tag_push(webhook: &TagPushWebhook, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let releases_config = match &config.releases {
+        Some(releases_config) if releases_config.tags && releases_config.watches_project(&webhook.project.path_with_namespace) => releases_config,
+        _ => return Ok(Vec::new()),
+    };
+    if webhook.checkout_sha.is_none() || !config.notifications.enabled("tag_pushed") {
+        return Ok(Vec::new());
+    }
+
+    let url = format!("{}/-/tags/{}", webhook.project.web_url, webhook.tag());
+    let recipient = Recipient::Room(releases_config.room_id.to_owned());
+    let message = config.render(&recipient, "tag_pushed", &json!({
+        "tag": {
+            "name": webhook.tag(),
+            "url": url,
+        },
+        "project": project_context(&webhook.project),
+        "user": webhook.user_username,
+    }))?;
+
+    Ok(vec![Message {
+        recipient,
+        message,
+        merge_request: None,
+        actions: cards::actions_for("tag_pushed", vec![Action::open("Open tag", &url)], config),
+        thread: None,
+        replaces: None,
+        event: Some("tag_pushed".to_owned()),
+    }])
+}
+
+pub fn parse_webhook(bytes: &[u8], config: &Config) -> Result<ParsedWebhook, WebhookError> {
+    let value: Value = serde_json::from_slice(bytes).map_err(WebhookError::Malformed)?;
+    if tracing::enabled!(Level::DEBUG) {
+        if let Ok(pretty) = serde_json::to_string_pretty(&value) {
+            debug!("Received Webhook: {}", config.redaction.scrub(&pretty));
+        }
+    }
+
+    // System and group hooks send some events without an `object_kind`.
+    if value.get("object_kind").is_none() {
+        if let Some(event_name) = value["event_name"].as_str() {
+            return match event_name {
+                "project_create" | "project_rename" | "project_transfer" => {
+                    let webhook = ProjectWebhook::deserialize(&value).map_err(WebhookError::Malformed)?;
+                    Ok(ParsedWebhook(Webhook::Project(webhook)))
+                }
+                _ => Err(WebhookError::Unsupported(event_name.to_owned())),
+            };
+        }
+    }
+
+    match Webhook::deserialize(&value).map_err(WebhookError::Malformed)? {
+        Webhook::Unsupported => {
+            let object_kind = value["object_kind"].as_str().unwrap_or_default();
+            Err(WebhookError::Unsupported(object_kind.to_owned()))
+        }
+        webhook => Ok(ParsedWebhook(webhook)),
+    }
+}
+
+/// The assignees of the merge request or issue the webhook is about, if it's about one.
+async fn rule_assignees(webhook: &Webhook, gitlab_client: &GitlabClient, config: &Config) -> Vec<String> {
+    let mut emails = Vec::new();
+    match webhook {
+        Webhook::Issue(webhook) => {
+            emails.extend(webhook.assignees.iter().flatten().map(|assignee| identity::webex_email(assignee, config)));
+        }
+        Webhook::MergeRequest(webhook) => {
+            emails.extend(webhook.assignees.iter().flatten().map(|assignee| identity::webex_email(assignee, config)));
+        }
+        Webhook::Note(NoteWebhook { merge_request: Some(merge_request), .. }) => {
+            for &assignee_id in &merge_request.assignee_ids {
+                emails.extend(identity::webex_email_by_id(assignee_id, None, gitlab_client, config).await);
+            }
+        }
+        Webhook::Pipeline(webhook) => {
+            let merge_request_iid = match &webhook.merge_request {
+                Some(merge_request) => Some(merge_request.iid),
+                None => webhook.pipeline.merge_request_iid_from_ref(),
+            };
+            if let Some(merge_request_iid) = merge_request_iid {
+                if let Ok(merge_request) = gitlab_client.get_merge_request_details(webhook.project.id, merge_request_iid).await {
+                    for assignee in merge_request.assignees.iter().flatten() {
+                        emails.extend(identity::webex_email_by_id(assignee.id, Some(&assignee.username), gitlab_client, config).await);
+                    }
+                }
+            }
+        }
+        _ => (),
+    }
+    emails
+}
+
+/// The merge request or issue author, or whoever triggered the webhook for anything else.
+async fn rule_author(webhook: &Webhook, gitlab_client: &GitlabClient, config: &Config) -> Option<String> {
+    match webhook {
+        Webhook::Deployment(webhook) => Some(identity::webex_email_by_username(&webhook.user.username, &webhook.user.email, config)),
+        Webhook::FeatureFlag(webhook) => Some(identity::webex_email(&webhook.user, config)),
+        Webhook::Issue(webhook) => identity::webex_email_by_id(webhook.issue.author_id, None, gitlab_client, config).await,
+        Webhook::Job(webhook) => Some(identity::webex_email(&webhook.user, config)),
+        Webhook::MergeRequest(webhook) => get_author_email(&webhook.merge_request, &webhook.project, gitlab_client, config).await,
+        Webhook::Note(NoteWebhook { merge_request: Some(merge_request), .. }) => {
+            identity::webex_email_by_id(merge_request.author_id, None, gitlab_client, config).await
+        }
+        Webhook::Pipeline(webhook) => Some(identity::webex_email(&webhook.user, config)),
+        Webhook::WikiPage(webhook) => Some(identity::webex_email(&webhook.user, config)),
+        _ => None,
+    }
+}
+
+/// Sends the messages where the first matching rule says, if there is one.
+async fn apply_rules(messages: Vec<Message>, webhook: &Webhook, gitlab_client: &GitlabClient, config: &Config) -> Vec<Message> {
+    let event = match webhook.event() {
+        Some(event) => event,
+        None => return messages,
+    };
+    let rule = match rules::find(&config.rules, &event) {
+        Some(rule) => rule,
+        None => return messages,
+    };
+    debug!("Applying rule to {:?}: {:?}", event, rule);
+    if rule.drop {
+        return Vec::new();
+    }
+
+    let mut recipients = Vec::new();
+    let mut keep_default = false;
+    for target in &rule.notify {
+        match target {
+            RuleTarget::Default => keep_default = true,
+            RuleTarget::Assignees => recipients.extend(rule_assignees(webhook, gitlab_client, config).await.into_iter().map(Recipient::Person)),
+            RuleTarget::Author => recipients.extend(rule_author(webhook, gitlab_client, config).await.map(Recipient::Person)),
+            RuleTarget::Room(room_id) => recipients.push(Recipient::Room(room_id.to_owned())),
+            RuleTarget::Email(email) => recipients.push(Recipient::Person(email.to_owned())),
+        }
+    }
+
+    rules::reroute(messages, &recipients, keep_default)
+}
+
+/// Adds a webhook to a project which showed up in one of the discovery groups,
+/// so that its events reach revbot like any other project's. Projects moved
+/// within the groups already have one.
+async fn discover_project(webhook: &ProjectWebhook, gitlab_client: &GitlabClient, config: &Config) -> Result<Vec<Message>, RevbotError> {
+    let discovery_config = match &config.discovery {
+        Some(discovery_config) => discovery_config,
+        None => return Ok(Vec::new()),
+    };
+
+    let path = &webhook.path_with_namespace;
+    if !discovery_config.covers(path) || !config.projects.allows(path) {
+        debug!("Not discovering project: {}", path);
+        return Ok(Vec::new());
+    }
+    if webhook.old_path_with_namespace.as_deref().is_some_and(|old_path| discovery_config.covers(old_path) && config.projects.allows(old_path)) {
+        debug!("Project {} was discovered before it moved", path);
+        return Ok(Vec::new());
+    }
+
+    gitlab_client.create_project_hook(webhook.project_id, &discovery_config.webhook_url, config.gitlab.webhook_token.as_deref())
+        .await
+        .map_err(|source| RevbotError::Gitlab { call: "create_project_hook", source })?;
+    info!("Added a webhook to discovered project: {}", path);
+
+    Ok(Vec::new())
+}
+
+pub async fn process_webhook(webhook: &ParsedWebhook, gitlab_client: &GitlabClient, config: &Config, pipeline_statuses: &PipelineStatusCache) -> Result<Vec<Message>, RevbotError> {
+    if let Some(project) = webhook.0.project() {
+        if !config.projects.allows(&project.path_with_namespace) {
+            debug!("Skipping webhook for project: {}", project.path_with_namespace);
+            return Ok(Vec::new());
+        }
+    }
+
+    let messages = match &webhook.0 {
+        Webhook::Deployment(webhook) => process_deployment(webhook, config),
+        Webhook::FeatureFlag(webhook) => process_feature_flag(webhook, gitlab_client, config).await,
+        Webhook::Issue(webhook) => process_issue(webhook, gitlab_client, config).await,
+        Webhook::Job(webhook) => process_job(webhook, config),
+        Webhook::MergeRequest(webhook) => process_merge_request(webhook, gitlab_client, config).await,
+        Webhook::Milestone(webhook) => process_milestone(webhook, config),
+        Webhook::Note(webhook) => process_note(webhook, gitlab_client, config).await,
+        Webhook::Pipeline(webhook) => process_pipeline(webhook, gitlab_client, config, pipeline_statuses).await,
+        Webhook::Push(webhook) => process_push(webhook, config),
+        Webhook::Release(webhook) => process_release(webhook, config),
+        Webhook::TagPush(webhook) => process_tag_push(webhook, config),
+        Webhook::WikiPage(webhook) => process_wiki_page(webhook, config),
+        Webhook::Project(webhook) => discover_project(webhook, gitlab_client, config).await,
+        Webhook::Unsupported => Ok(Vec::new()),
+    }?;
+
+    Ok(apply_rules(messages, &webhook.0, gitlab_client, config).await)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gitlab::types::MergeStatus;
+    use crate::config::DiscoveryConfig;
+
+    #[test]
+    fn test_deserialize_merge_request() {
+        let json = r#"
+        {
+          "object_attributes": {
+            "author_id": 1069,
+            "created_at": "2021-09-06 10:54:57 -0500",
+            "description": "",
+            "id": 289144,
+            "iid": 3,
+            "merge_error": null,
+            "merge_status": "unchecked",
+            "merge_when_pipeline_succeeds": false,
+            "state": "opened",
+            "state_id": 1,
+            "labels": [
+              { "id": 206, "title": "backend" }
+            ],
+            "source_branch": "fail-pipeline",
+            "target_branch": "main",
+            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3",
+            "title": "Fail pipeline",
+            "work_in_progress": false
+          },
+          "object_kind": "merge_request",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "user": {
+            "email": "hds@example.com",
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-"
+          }
+        }
+        "#;
+
+      let expected = Webhook::MergeRequest(MergeRequestWebhook {
+          assignees: None,
+          changes: None,
+          labels: None,
+          merge_request: MergeRequestAttributes {
+              action: None,
+              author_id: Some(1069),
+              description: Some("".to_owned()),
+              draft: Some(false),
+              iid: 3,
+              labels: vec![Label { id: 206, title: "backend".to_owned() }],
+              merge_status: MergeStatus::Unchecked,
+              source_branch: Some("fail-pipeline".to_owned()),
+              target_branch: Some("main".to_owned()),
+              title: "Fail pipeline".to_owned(),
+              url: "https://gitlab.com/hds-/mr-test/-/merge_requests/3".to_owned(),
+          },
+          project: Project {
+              id: 17898,
+              name: "mr-test".to_owned(),
+              path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
+              web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
+          },
+          reviewers: None,
+          user: User {
+              email: "hds@example.com".to_owned(),
+              id: 1069,
+              name: "Hayden Stainsby".to_owned(),
+              username: "hds-".to_owned(),
+          },
+      });
+
+      println!("{}", json);
+      let webhook: Webhook = serde_json::from_str(&json).unwrap();
+      assert_eq!(expected, webhook);
+    }
+
+    #[test]
+    fn test_deserialize_pipeline() {
+        let json = r#"
+        {
+          "object_attributes": {
+            "created_at": "2021-09-26 09:48:13 UTC",
+            "duration": null,
+            "finished_at": null,
+            "id": 4038106,
+            "queued_duration": 12,
+            "ref": "fail-pipeline",
+            "status": "running"
+          },
+          "object_kind": "pipeline",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "user": {
+            "email": "hds@example.com",
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-"
+          }
+        }
+      "#;
+
+      let expected = Webhook::Pipeline(PipelineWebhook {
+          commit: None,
+          merge_request: None,
+          pipeline: PipelineAttributes {
+              created_at: Some("2021-09-26 09:48:13 UTC".to_owned()),
+              duration: None,
+              finished_at: None,
+              id: 4038106,
+              queued_duration: Some(12),
+              ref_: "fail-pipeline".to_owned(),
+              source: None,
+              status: StatusState::Running,
+          },
+          project: Project {
+              id: 17898,
+              name: "mr-test".to_owned(),
+              path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
+              web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
+          },
+          user: User {
+              email: "hds@example.com".to_owned(),
+              id: 1069,
+              name: "Hayden Stainsby".to_owned(),
+              username: "hds-".to_owned(),
+          },
+      });
+
+      println!("{}", json);
+      let webhook: Webhook = serde_json::from_str(&json).unwrap();
+      assert_eq!(expected, webhook);
+    }
+
+    #[test]
+    fn test_deserialize_feature_flag() {
+        let json = r#"
+        {
+          "object_kind": "feature_flag",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "user": {
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-",
+            "avatar_url": "https://www.gravatar.com/avatar/d22738dc40839e3d95fca77ca3eac067?s=80&d=identicon",
+            "email": "hds@example.com"
+          },
+          "user_url": "https://gitlab.com/hds-",
+          "object_attributes": {
+            "id": 6,
+            "name": "new-checkout",
+            "description": "Roll out the new checkout flow",
+            "active": true
+          }
+        }
+      "#;
+
+      let expected = Webhook::FeatureFlag(FeatureFlagWebhook {
+          feature_flag: FeatureFlagAttributes {
+              active: true,
+              description: Some("Roll out the new checkout flow".to_owned()),
+              id: 6,
+              name: "new-checkout".to_owned(),
+          },
+          project: Project {
+              id: 17898,
+              name: "mr-test".to_owned(),
+              path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
+              web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
+          },
+          user: User {
+              email: "hds@example.com".to_owned(),
+              id: 1069,
+              name: "Hayden Stainsby".to_owned(),
+              username: "hds-".to_owned(),
+          },
+      });
+
+      let webhook: Webhook = serde_json::from_str(json).unwrap();
+      assert_eq!(expected, webhook);
+    }
+
+    #[test]
+    fn test_deserialize_job() {
+        let json = r#"
+        {
+          "object_kind": "build",
+          "ref": "fail-pipeline",
+          "tag": false,
+          "before_sha": "0000000000000000000000000000000000000000",
+          "sha": "2293ada6b400935a1378653304eaf6221e0fdb8f",
+          "build_id": 1977,
+          "build_name": "test",
+          "build_stage": "test",
+          "build_status": "failed",
+          "build_started_at": "2021-09-06 10:55:03 UTC",
+          "build_finished_at": "2021-09-06 10:56:12 UTC",
+          "build_duration": 69.3,
+          "build_allow_failure": false,
+          "build_failure_reason": "script_failure",
+          "pipeline_id": 4038106,
+          "project_id": 17898,
+          "project_name": "hds- / mr-test",
+          "user": {
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-",
+            "avatar_url": "https://www.gravatar.com/avatar/d22738dc40839e3d95fca77ca3eac067?s=80&d=identicon",
+            "email": "hds@example.com"
+          },
+          "commit": {
+            "id": 4038106,
+            "sha": "2293ada6b400935a1378653304eaf6221e0fdb8f",
+            "message": "Fail pipeline",
+            "author_name": "Hayden Stainsby",
+            "author_email": "hds@example.com",
+            "status": "failed"
+          },
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          }
+        }
+      "#;
+
+      let expected = Webhook::Job(JobWebhook {
+          build_allow_failure: false,
+          build_failure_reason: Some("script_failure".to_owned()),
+          build_id: 1977,
+          build_name: "test".to_owned(),
+          build_stage: "test".to_owned(),
+          build_status: StatusState::Failed,
+          commit: Some(JobCommit {
+              message: "Fail pipeline".to_owned(),
+              sha: "2293ada6b400935a1378653304eaf6221e0fdb8f".to_owned(),
+          }),
+          pipeline_id: 4038106,
+          project: Project {
+              id: 17898,
+              name: "mr-test".to_owned(),
+              path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
+              web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
+          },
+          ref_: "fail-pipeline".to_owned(),
+          user: User {
+              email: "hds@example.com".to_owned(),
+              id: 1069,
+              name: "Hayden Stainsby".to_owned(),
+              username: "hds-".to_owned(),
+          },
+      });
+
+      let webhook: Webhook = serde_json::from_str(json).unwrap();
+      assert_eq!(expected, webhook);
+    }
+
+    #[test]
+    fn test_deserialize_milestone() {
+        let json = r#"
+        {
+          "object_kind": "milestone",
+          "event_type": "milestone",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "object_attributes": {
+            "id": 61,
+            "iid": 10,
+            "title": "v1.0",
+            "description": "First stable release",
+            "state": "active",
+            "created_at": "2021-09-06 10:54:57 UTC",
+            "updated_at": "2021-09-06 10:54:57 UTC",
+            "due_date": "2021-10-01",
+            "start_date": "2021-09-06",
+            "project_id": 17898
+          },
+          "action": "create"
+        }
+      "#;
+
+      let expected = Webhook::Milestone(MilestoneWebhook {
+          action: "create".to_owned(),
+          milestone: MilestoneAttributes {
+              due_date: chrono::NaiveDate::from_ymd_opt(2021, 10, 1),
+              id: 61,
+              iid: 10,
+              state: "active".to_owned(),
+              title: "v1.0".to_owned(),
+          },
+          project: Project {
+              id: 17898,
+              name: "mr-test".to_owned(),
+              path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
+              web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
+          },
+      });
+
+      let webhook: Webhook = serde_json::from_str(json).unwrap();
+      assert_eq!(expected, webhook);
+    }
+
+    #[test]
+    fn test_deserialize_note() {
+        let json = r#"
+        {
+          "object_kind": "note",
+          "event_type": "note",
+          "user": {
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-",
+            "avatar_url": "https://www.gravatar.com/avatar/d22738dc40839e3d95fca77ca3eac067?s=80&d=identicon",
+            "email": "hds@example.com"
+          },
+          "project_id": 17898,
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "object_attributes": {
+            "id": 1244,
+            "note": "Shouldn't this fail the pipeline?",
+            "noteable_type": "MergeRequest",
+            "author_id": 1069,
+            "system": false,
+            "noteable_id": 289144,
+            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3#note_1244"
+          },
+          "merge_request": {
+            "assignee_ids": [1070],
+            "author_id": 1071,
+            "id": 289144,
+            "iid": 3,
+            "labels": [],
+            "merge_status": "can_be_merged",
+            "title": "Fail pipeline",
+            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3"
+          }
         }
-        "#;
+      "#;
 
-      let expected = Webhook::MergeRequest(MergeRequestWebhook {
-          assignees: None,
-          changes: None,
-          merge_request: MergeRequestAttributes {
-              action: None,
+      let expected = Webhook::Note(NoteWebhook {
+          merge_request: Some(NoteMergeRequestAttributes {
+              assignee_ids: vec![1070],
+              author_id: 1071,
               iid: 3,
-              merge_status: MergeStatus::Unchecked,
+              labels: Vec::new(),
               title: "Fail pipeline".to_owned(),
               url: "https://gitlab.com/hds-/mr-test/-/merge_requests/3".to_owned(),
+          }),
+          note: NoteAttributes {
+              id: 1244,
+              note: "Shouldn't this fail the pipeline?".to_owned(),
+              noteable_type: "MergeRequest".to_owned(),
+              system: false,
+              url: "https://gitlab.com/hds-/mr-test/-/merge_requests/3#note_1244".to_owned(),
           },
           project: Project {
               id: 17898,
               name: "mr-test".to_owned(),
               path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
               web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
           },
           user: User {
@@ -243,49 +2146,68 @@ mod test {
           },
       });
 
-      println!("{}", json);
-      let webhook: Webhook = serde_json::from_str(&json).unwrap();
+      let webhook: Webhook = serde_json::from_str(json).unwrap();
       assert_eq!(expected, webhook);
     }
 
     #[test]
-    fn test_deserialize_pipeline() {
+    fn test_note_snippet() {
+        assert_eq!("> Looks good\n> \n> Just one nit", note_snippet("Looks good\n\nJust one nit\n"));
+        let long = "a".repeat(NOTE_SNIPPET_CHARS + 1);
+        assert_eq!(format!("> {}…", "a".repeat(NOTE_SNIPPET_CHARS)), note_snippet(&long));
+    }
+
+    #[test]
+    fn test_deserialize_wiki_page() {
         let json = r#"
         {
-          "object_attributes": {
-            "finished_at": null,
-            "id": 4038106,
-            "ref": "fail-pipeline",
-            "status": "running"
+          "object_kind": "wiki_page",
+          "user": {
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-",
+            "avatar_url": "https://www.gravatar.com/avatar/d22738dc40839e3d95fca77ca3eac067?s=80&d=identicon",
+            "email": "hds@example.com"
           },
-          "object_kind": "pipeline",
           "project": {
             "id": 17898,
             "name": "mr-test",
             "path_with_namespace": "hds-/mr-test",
             "web_url": "https://gitlab.com/hds-/mr-test"
           },
-          "user": {
-            "email": "hds@example.com",
-            "id": 1069,
-            "name": "Hayden Stainsby",
-            "username": "hds-"
+          "wiki": {
+            "web_url": "https://gitlab.com/hds-/mr-test/-/wikis/home",
+            "path_with_namespace": "hds-/mr-test.wiki",
+            "default_branch": "main"
+          },
+          "object_attributes": {
+            "title": "Runbook",
+            "content": "Restart it.",
+            "format": "markdown",
+            "message": "Add runbook",
+            "slug": "runbook",
+            "url": "https://gitlab.com/hds-/mr-test/-/wikis/runbook",
+            "action": "update",
+            "diff_url": "https://gitlab.com/hds-/mr-test/-/wikis/runbook/diff?version_id=b2d4e1c",
+            "version_id": "b2d4e1c"
           }
         }
       "#;
 
-      let expected = Webhook::Pipeline(PipelineWebhook {
-          merge_request: None,
-          pipeline: PipelineAttributes {
-              finished_at: None,
-              id: 4038106,
-              ref_: "fail-pipeline".to_owned(),
-              status: StatusState::Running,
+      let expected = Webhook::WikiPage(WikiPageWebhook {
+          wiki_page: WikiPageAttributes {
+              action: "update".to_owned(),
+              diff_url: Some("https://gitlab.com/hds-/mr-test/-/wikis/runbook/diff?version_id=b2d4e1c".to_owned()),
+              message: Some("Add runbook".to_owned()),
+              slug: "runbook".to_owned(),
+              title: "Runbook".to_owned(),
+              url: "https://gitlab.com/hds-/mr-test/-/wikis/runbook".to_owned(),
           },
           project: Project {
               id: 17898,
               name: "mr-test".to_owned(),
               path_with_namespace: "hds-/mr-test".to_owned(),
+              visibility_level: None,
               web_url: "https://gitlab.com/hds-/mr-test".to_owned(),
           },
           user: User {
@@ -296,9 +2218,466 @@ mod test {
           },
       });
 
-      println!("{}", json);
-      let webhook: Webhook = serde_json::from_str(&json).unwrap();
+      let webhook: Webhook = serde_json::from_str(json).unwrap();
       assert_eq!(expected, webhook);
     }
 
+    #[test]
+    fn test_issue_new_assignees() {
+        let json = r#"
+        {
+          "object_kind": "issue",
+          "event_type": "issue",
+          "user": {
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-",
+            "email": "hds@example.com"
+          },
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "object_attributes": {
+            "action": "update",
+            "author_id": 1069,
+            "confidential": false,
+            "iid": 23,
+            "title": "Pipeline fails on main",
+            "url": "https://gitlab.com/hds-/mr-test/-/issues/23"
+          },
+          "assignees": [
+            { "id": 1070, "name": "Someone Else", "username": "someone", "email": "someone@example.com" }
+          ],
+          "changes": {
+            "assignees": {
+              "previous": [],
+              "current": [
+                { "id": 1070, "name": "Someone Else", "username": "someone", "email": "someone@example.com" }
+              ]
+            }
+          }
+        }
+      "#;
+
+      let webhook = match serde_json::from_str(json).unwrap() {
+          Webhook::Issue(webhook) => webhook,
+          other => panic!("Expected issue webhook, got: {:?}", other),
+      };
+      let assignee_changes = webhook.changes.as_ref().and_then(|changes| changes.assignees.as_ref()).unwrap();
+      let new_assignees: Vec<_> = get_new_assignees(assignee_changes).into_iter().map(|assignee| assignee.username).collect();
+      assert_eq!(vec!["someone".to_owned()], new_assignees);
+      assert!(!webhook.issue.confidential);
+    }
+
+    #[test]
+    fn test_deployment_webhook() {
+        let config: Config = serde_json::from_str(r#"
+        {
+          "gitlab": { "access_token": "", "hostname": "gitlab.com" },
+          "webex": { "access_token": "" },
+          "deployments": { "rooms": { "production": "prod-room" } }
+        }
+        "#).unwrap();
+        let json = r#"
+        {
+          "object_kind": "deployment",
+          "status": "success",
+          "status_changed_at": "2021-11-29 10:14:03 +0100",
+          "deployment_id": 15,
+          "deployable_id": 796,
+          "deployable_url": "https://gitlab.com/hds-/mr-test/-/jobs/796",
+          "environment": "production",
+          "environment_external_url": "https://mr-test.example.com",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "short_sha": "279484c0",
+          "user": {
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-",
+            "avatar_url": "https://www.gravatar.com/avatar/d22738dc40839e3d95fca77ca3eac067?s=80&d=identicon",
+            "email": "hds@example.com"
+          },
+          "user_url": "https://gitlab.com/hds-",
+          "commit_url": "https://gitlab.com/hds-/mr-test/-/commit/279484c09fbe69ededfced8c1bb6e6d24616b468",
+          "commit_title": "Fix pipeline",
+          "ref": "main"
+        }
+      "#;
+
+      let webhook = match serde_json::from_str(json).unwrap() {
+          Webhook::Deployment(webhook) => webhook,
+          other => panic!("Expected deployment webhook, got: {:?}", other),
+      };
+      let messages = process_deployment(&webhook, &config).unwrap();
+      let recipients: Vec<_> = messages.iter().map(|message| &message.recipient).collect();
+      assert_eq!(vec![&Recipient::Person("hds@example.com".to_owned()), &Recipient::Room("prod-room".to_owned())], recipients);
+      assert_eq!(
+          "🚀 [production](https://mr-test.example.com) ([mr-test](https://gitlab.com/hds-/mr-test)) main by @hds- 🌞 [Deployed](https://gitlab.com/hds-/mr-test/-/jobs/796)",
+          messages[0].message);
+
+      let review = DeploymentWebhook { environment: "review/fix".to_owned(), ..webhook };
+      assert!(process_deployment(&review, &config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_push_webhook() {
+        let config: Config = serde_json::from_str(r#"
+        {
+          "gitlab": { "access_token": "", "hostname": "gitlab.com" },
+          "webex": { "access_token": "" },
+          "pushes": { "room_id": "pushes", "branches": ["main"] }
+        }
+        "#).unwrap();
+        let json = r#"
+        {
+          "object_kind": "push",
+          "event_name": "push",
+          "before": "95790bf891e76fee5e1747ab589903a6a1f80f22",
+          "after": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+          "ref": "refs/heads/main",
+          "checkout_sha": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+          "user_id": 1069,
+          "user_name": "Hayden Stainsby",
+          "user_username": "hds-",
+          "user_email": "hds@example.com",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "commits": [
+            {
+              "id": "b6568db1bc1dcd7f8b4d5a946b0b91f9dacd7327",
+              "message": "Fix the pipeline\n\nIt was failing.",
+              "url": "https://gitlab.com/hds-/mr-test/-/commit/b6568db1bc1dcd7f8b4d5a946b0b91f9dacd7327"
+            },
+            {
+              "id": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+              "message": "Bump version",
+              "url": "https://gitlab.com/hds-/mr-test/-/commit/da1560886d4f094c3e6c9ef40349f7d38b5d27d7"
+            }
+          ],
+          "total_commits_count": 2
+        }
+      "#;
+
+      let webhook = match serde_json::from_str(json).unwrap() {
+          Webhook::Push(webhook) => webhook,
+          other => panic!("Expected push webhook, got: {:?}", other),
+      };
+      let messages = process_push(&webhook, &config).unwrap();
+      assert_eq!(1, messages.len());
+      assert_eq!(
+          "⬆️ [main](https://gitlab.com/hds-/mr-test/-/commits/main) ([mr-test](https://gitlab.com/hds-/mr-test)) by @hds- Pushed\n\
+           - [da156088](https://gitlab.com/hds-/mr-test/-/commit/da1560886d4f094c3e6c9ef40349f7d38b5d27d7) Bump version\n\
+           - [b6568db1](https://gitlab.com/hds-/mr-test/-/commit/b6568db1bc1dcd7f8b4d5a946b0b91f9dacd7327) Fix the pipeline",
+          messages[0].message);
+
+      let feature = PushWebhook { ref_: "refs/heads/feature".to_owned(), ..webhook };
+      assert!(process_push(&feature, &config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_release_webhook() {
+        let config: Config = serde_json::from_str(r#"
+        {
+          "gitlab": { "access_token": "", "hostname": "gitlab.com" },
+          "webex": { "access_token": "" },
+          "releases": { "room_id": "releases" }
+        }
+        "#).unwrap();
+        let json = r#"
+        {
+          "object_kind": "release",
+          "id": 1,
+          "created_at": "2021-11-29 10:14:03 UTC",
+          "description": "Fixes the pipeline.",
+          "name": "Release 1.2.0",
+          "released_at": "2021-11-29 10:14:03 UTC",
+          "tag": "v1.2.0",
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "url": "https://gitlab.com/hds-/mr-test/-/releases/v1.2.0",
+          "action": "create"
+        }
+      "#;
+
+      let webhook = match serde_json::from_str(json).unwrap() {
+          Webhook::Release(webhook) => webhook,
+          other => panic!("Expected release webhook, got: {:?}", other),
+      };
+      let messages = process_release(&webhook, &config).unwrap();
+      assert_eq!(1, messages.len());
+      assert_eq!(Recipient::Room("releases".to_owned()), messages[0].recipient);
+      assert_eq!(
+          "📦 [Release 1.2.0](https://gitlab.com/hds-/mr-test/-/releases/v1.2.0) (v1.2.0) ([mr-test](https://gitlab.com/hds-/mr-test)) 🚢 Released\n\n> Fixes the pipeline.",
+          messages[0].message);
+    }
+
+    #[test]
+    fn test_parse_unsupported_webhook() {
+        let config: Config = serde_json::from_str(r#"
+        {
+          "gitlab": { "access_token": "", "hostname": "gitlab.com" },
+          "webex": { "access_token": "" }
+        }
+        "#).unwrap();
+
+        match parse_webhook(br#"{ "object_kind": "emoji", "event_type": "award" }"#, &config) {
+            Err(WebhookError::Unsupported(object_kind)) => assert_eq!("emoji", object_kind),
+            _ => panic!("Expected emoji webhook to be unsupported"),
+        }
+        match parse_webhook(br#"{ "ref": "refs/heads/main" }"#, &config) {
+            Err(WebhookError::Malformed(_)) => {}
+            _ => panic!("Expected webhook without object_kind to be malformed"),
+        }
+    }
+
+    #[test]
+    fn test_parse_system_hook() {
+        let config: Config = serde_json::from_str(r#"
+        {
+          "gitlab": { "access_token": "", "hostname": "gitlab.com" },
+          "webex": { "access_token": "" }
+        }
+        "#).unwrap();
+
+        let webhook = parse_webhook(br#"
+        {
+          "created_at": "2026-10-16T09:00:00Z",
+          "event_name": "project_transfer",
+          "name": "mr-test",
+          "path": "mr-test",
+          "path_with_namespace": "platform/mr-test",
+          "project_id": 17898,
+          "project_visibility": "internal",
+          "old_path_with_namespace": "hds-/mr-test"
+        }
+        "#, &config).unwrap();
+        assert_eq!("project_transfer", webhook.kind());
+        assert_eq!(Some("platform/mr-test"), webhook.project_path());
+
+        match parse_webhook(br#"{ "event_name": "user_add_to_group", "group_path": "platform" }"#, &config) {
+            Err(WebhookError::Unsupported(event_name)) => assert_eq!("user_add_to_group", event_name),
+            _ => panic!("Expected group member webhook to be unsupported"),
+        }
+
+        let discovery: DiscoveryConfig = serde_json::from_str(r#"{ "groups": ["Platform/"], "webhook_url": "https://revbot.example.com/gitlab" }"#).unwrap();
+        assert!(discovery.covers("platform/mr-test") && discovery.covers("platform/infra/mr-test"));
+        assert!(!discovery.covers("platform-old/mr-test"));
+    }
+
+    #[test]
+    fn test_merge_request_labels_from_changes() {
+        let json = r#"
+        {
+          "object_kind": "merge_request",
+          "object_attributes": {
+            "iid": 3,
+            "merge_status": "can_be_merged",
+            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3",
+            "title": "Fail pipeline"
+          },
+          "labels": [
+            { "id": 206, "title": "revbot::silence" }
+          ],
+          "changes": {
+            "labels": {
+              "previous": [
+                { "id": 206, "title": "revbot::silence" }
+              ],
+              "current": [
+                { "id": 207, "title": "backend" }
+              ]
+            }
+          },
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "user": {
+            "email": "hds@example.com",
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-"
+          }
+        }
+      "#;
+
+      let webhook: Webhook = serde_json::from_str(json).unwrap();
+      let webhook = match webhook {
+          Webhook::MergeRequest(webhook) => webhook,
+          other => panic!("Expected merge request webhook, got: {:?}", other),
+      };
+      let labels: Vec<&str> = webhook.get_labels().iter().map(|label| label.title.as_str()).collect();
+      assert_eq!(vec!["backend"], labels);
+    }
+
+    #[test]
+    fn test_merge_request_became_ready() {
+        let json = r#"
+        {
+          "object_kind": "merge_request",
+          "object_attributes": {
+            "iid": 3,
+            "merge_status": "can_be_merged",
+            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3",
+            "title": "Fail pipeline"
+          },
+          "changes": {
+            "draft": { "previous": true, "current": false }
+          },
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "user": {
+            "email": "hds@example.com",
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-"
+          }
+        }
+      "#;
+
+      let mut webhook = match serde_json::from_str(json).unwrap() {
+          Webhook::MergeRequest(webhook) => webhook,
+          other => panic!("Expected merge request webhook, got: {:?}", other),
+      };
+      assert!(webhook.became_ready());
+
+      let changes = webhook.changes.as_mut().unwrap();
+      changes.work_in_progress = changes.draft.take().map(|draft| FlagChanges { previous: draft.current, current: draft.previous });
+      assert!(!webhook.became_ready());
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let candidates = ["alice", "bob", "carol"];
+        assert_eq!("bob", round_robin(&candidates, 7));
+        assert_eq!("carol", round_robin(&candidates, 8));
+        assert_eq!("alice", round_robin(&candidates, 9));
+    }
+
+    #[test]
+    fn test_merge_request_became_unmergeable() {
+        let json = r#"
+        {
+          "object_kind": "merge_request",
+          "object_attributes": {
+            "iid": 3,
+            "merge_status": "cannot_be_merged",
+            "url": "https://gitlab.com/hds-/mr-test/-/merge_requests/3",
+            "title": "Fail pipeline"
+          },
+          "changes": {
+            "merge_status": { "previous": "unchecked", "current": "cannot_be_merged" }
+          },
+          "project": {
+            "id": 17898,
+            "name": "mr-test",
+            "path_with_namespace": "hds-/mr-test",
+            "web_url": "https://gitlab.com/hds-/mr-test"
+          },
+          "user": {
+            "email": "hds@example.com",
+            "id": 1069,
+            "name": "Hayden Stainsby",
+            "username": "hds-"
+          }
+        }
+      "#;
+
+      let mut webhook = match serde_json::from_str(json).unwrap() {
+          Webhook::MergeRequest(webhook) => webhook,
+          other => panic!("Expected merge request webhook, got: {:?}", other),
+      };
+      assert!(webhook.became_unmergeable());
+
+      webhook.changes.as_mut().unwrap().merge_status = Some(MergeStatusChanges {
+          current: Some(MergeStatus::CannotBeMerged),
+          previous: Some(MergeStatus::CannotBeMerged),
+      });
+      assert!(!webhook.became_unmergeable());
+    }
+
+    #[test]
+    fn test_new_reviewers() {
+        let user = |id: u64| User {
+            email: format!("user-{}@example.com", id),
+            id,
+            name: format!("User {}", id),
+            username: format!("user-{}", id),
+        };
+        let reviewer_changes = ReviewerChanges {
+            current: vec![user(1), user(2), user(3)],
+            previous: vec![user(2), user(4)],
+        };
+
+        let new_reviewer_ids: Vec<u64> = get_new_reviewers(&reviewer_changes).iter().map(|reviewer| reviewer.id).collect();
+        assert_eq!(vec![1, 3], new_reviewer_ids);
+    }
+
+    #[test]
+    fn test_has_silence_trailer() {
+        assert!(has_silence_trailer("Bump version\n\nNotify: none\n"));
+        assert!(has_silence_trailer("Bump version\n\nSome context.\n\nSigned-off-by: Hayden <hds@example.com>\nrevbot-silence: TRUE"));
+        assert!(!has_silence_trailer("Bump version\n\nNotify: none\n\nAfter all, please do notify."));
+        assert!(!has_silence_trailer("Notify: everyone"));
+        assert!(!has_silence_trailer(""));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!("45s", format_duration(45));
+        assert_eq!("14m 32s", format_duration(872));
+        assert_eq!("2h 0m 5s", format_duration(7205));
+    }
+
+    #[test]
+    fn test_pipeline_kind() {
+        let pipeline = |ref_: &str, source: Option<&str>| PipelineAttributes {
+            created_at: None,
+            duration: None,
+            finished_at: None,
+            id: 4038106,
+            queued_duration: None,
+            ref_: ref_.to_owned(),
+            source: source.map(|source| source.to_owned()),
+            status: StatusState::Running,
+        };
+
+        let branch = pipeline("fail-pipeline", Some("push"));
+        assert_eq!(PipelineKind::Branch, branch.kind());
+        assert_eq!(None, branch.merge_request_iid_from_ref());
+
+        let detached = pipeline("refs/merge-requests/3/head", Some("merge_request_event"));
+        assert_eq!(PipelineKind::Detached, detached.kind());
+        assert_eq!(Some(3), detached.merge_request_iid_from_ref());
+
+        let merged_result = pipeline("refs/merge-requests/3/merge", Some("merge_request_event"));
+        assert_eq!(PipelineKind::MergedResult, merged_result.kind());
+        assert_eq!(Some(3), merged_result.merge_request_iid_from_ref());
+
+        let merge_train = pipeline("refs/merge-requests/3/train", Some("merge_request_event"));
+        assert_eq!(PipelineKind::MergedResult, merge_train.kind());
+    }
+
 }
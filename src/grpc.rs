@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use subtle::ConstantTimeEq;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{error, info};
+
+use crate::message::{Message, Recipient};
+use crate::AppState;
+
+pub mod proto {
+    tonic::include_proto!("revbot");
+}
+
+use proto::notifications_server::{Notifications, NotificationsServer};
+use proto::submit_request::Recipient as ProtoRecipient;
+use proto::{Priority, SubmitRequest, SubmitResponse};
+
+struct NotificationsService {
+    state: Arc<AppState>,
+}
+
+impl NotificationsService {
+    fn is_authorized<T>(&self, request: &Request<T>) -> bool {
+        let config = self.state.config();
+        let token = match config.grpc.as_ref().and_then(|grpc| grpc.token.as_ref()) {
+            Some(token) => token,
+            None => return true,
+        };
+
+        let authorization = request.metadata().get("authorization").and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+        authorization.is_some_and(|authorization| authorization.ct_eq(token.as_bytes()).into())
+    }
+}
+
+#[tonic::async_trait]
+impl Notifications for NotificationsService {
+    async fn submit(&self, request: Request<SubmitRequest>) -> Result<Response<SubmitResponse>, Status> {
+        if !self.is_authorized(&request) {
+            return Err(Status::unauthenticated("Missing or wrong token"));
+        }
+        let event = request.into_inner();
+
+        let recipient = match event.recipient {
+            Some(ProtoRecipient::PersonEmail(email)) if !email.is_empty() => Recipient::Person(email),
+            Some(ProtoRecipient::RoomId(room_id)) if !room_id.is_empty() => Recipient::Room(room_id),
+            _ => return Err(Status::invalid_argument("A person_email or room_id is required")),
+        };
+        if event.markdown.trim().is_empty() {
+            return Err(Status::invalid_argument("The markdown can't be empty"));
+        }
+        // Everything is delivered straight away for now, the priority is
+        // only there so that submitters don't need to change later.
+        let priority = Priority::from_i32(event.priority).unwrap_or(Priority::Normal);
+        info!("Accepted {:?} priority event for {}", priority, recipient);
+
+        let message = Message {
+            recipient,
+            message: event.markdown,
+            merge_request: None,
+            actions: Vec::new(),
+            thread: None,
+            replaces: None,
+            event: None,
+        };
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let _in_flight = state.in_flight.start();
+            crate::send_messages(vec![message], &state).await;
+        });
+
+        Ok(Response::new(SubmitResponse { accepted: true }))
+    }
+}
+
+/// Serves the gRPC ingestion API, if it's configured.
+pub async fn serve(state: Arc<AppState>) {
+    let config = state.config();
+    let grpc_config = match &config.grpc {
+        Some(grpc_config) => grpc_config,
+        None => return,
+    };
+    let addr: SocketAddr = match grpc_config.address.parse() {
+        Ok(addr) => addr,
+        Err(err) => {
+            error!("Bad gRPC address {}: {}", grpc_config.address, err);
+            return;
+        }
+    };
+
+    info!("Serving gRPC on: {}", addr);
+    let service = NotificationsService { state: state.clone() };
+    if let Err(err) = Server::builder()
+        .add_service(NotificationsServer::new(service))
+        .serve(addr)
+        .await {
+        error!("gRPC server error: {}", err);
+    }
+}
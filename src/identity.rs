@@ -0,0 +1,48 @@
+use crate::config::Config;
+use crate::gitlab::client::GitlabClient;
+use crate::gitlab::common::{User, UserBasic};
+
+/// The Webex email configured, or linked, for a GitLab username, if there is one.
+fn mapped_email(username: &str, config: &Config) -> Option<String> {
+    let username = username.to_lowercase();
+    config.identities.get(&username).cloned().or_else(|| config.linked_identities.read().unwrap().get(&username).cloned())
+}
+
+/// The Webex email for a user from a webhook, which falls back to the email in the webhook.
+pub fn webex_email(user: &User, config: &Config) -> String {
+    webex_email_by_username(&user.username, &user.email, config)
+}
+
+/// The Webex email for someone a webhook only gives the username and email of.
+pub fn webex_email_by_username(username: &str, email: &str, config: &Config) -> String {
+    mapped_email(username, config).unwrap_or_else(|| email.to_owned())
+}
+
+/// The Webex email for a user known by id, which falls back to the email
+/// GitLab shows us. Passing the `username`, if it's known, saves looking the
+/// user up when they're mapped.
+pub async fn webex_email_by_id(user_id: u64, username: Option<&str>, gitlab_client: &GitlabClient, config: &Config) -> Option<String> {
+    if let Some(email) = username.and_then(|username| mapped_email(username, config)) {
+        return Some(email);
+    }
+
+    let user = gitlab_client.get_user_emails(user_id).await?;
+    mapped_email(&user.username, config).or_else(|| user.best_email())
+}
+
+/// The Webex email configured for a GitHub login, if there is one.
+pub fn github_webex_email(login: &str, config: &Config) -> Option<String> {
+    let github = config.github.as_ref()?;
+    github.identities.get(&login.to_lowercase()).cloned()
+}
+
+/// The GitLab user someone on Webex is, going by the mapped usernames first.
+pub async fn gitlab_user(webex_email: &str, gitlab_client: &GitlabClient, config: &Config) -> Option<UserBasic> {
+    let linked = config.linked_identities.read().unwrap().clone();
+    let username = config.identities
+        .iter()
+        .chain(&linked)
+        .find(|(_, email)| email.eq_ignore_ascii_case(webex_email))
+        .map(|(username, _)| username.as_str());
+    gitlab_client.find_user(username, webex_email).await
+}
@@ -0,0 +1,256 @@
+//! Turns GitLab webhooks into Webex notifications.
+//!
+//! The `revbot` binary runs all of this as a service, the pieces are public
+//! for embedding the processing in another service.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tokio::sync::Semaphore;
+use tracing::{error, info, warn};
+
+pub mod alerts;
+pub mod cards;
+pub mod commands;
+pub mod config;
+pub mod dead_letters;
+pub mod digest;
+pub mod events;
+pub mod error;
+pub mod message;
+pub mod mutes;
+pub mod preferences;
+pub mod github;
+pub mod gitlab;
+pub mod grpc;
+pub mod identity;
+pub mod links;
+pub mod loadtest;
+pub mod markdown;
+pub mod queue;
+pub mod ratelimit;
+pub mod reload;
+pub mod replay;
+pub mod report;
+pub mod review_sla;
+pub mod rules;
+pub mod scheduler;
+pub mod secrets;
+pub mod send_test;
+pub mod sent;
+pub mod server;
+pub mod shutdown;
+pub mod subscriptions;
+pub mod telemetry;
+pub mod templates;
+pub mod tls;
+pub mod user_tokens;
+pub mod webex;
+pub mod webex_tokens;
+
+use crate::dead_letters::DeadLetters;
+use crate::digest::Digest;
+use crate::events::Events;
+use crate::review_sla::ReviewRequests;
+use crate::gitlab::dedup::PipelineStatusCache;
+use crate::links::Links;
+use crate::mutes::Mutes;
+use crate::preferences::Preferences;
+use crate::subscriptions::Subscriptions;
+use crate::queue::Queue;
+use crate::ratelimit::RateLimiter;
+use crate::sent::SentMessages;
+use crate::shutdown::InFlight;
+use crate::user_tokens::UserTokens;
+use crate::webex_tokens::WebexTokens;
+
+pub use crate::config::Config;
+pub use crate::error::RevbotError;
+pub use crate::gitlab::client::GitlabClient;
+pub use crate::gitlab::webhook::{parse_webhook, process_webhook, ParsedWebhook, WebhookError};
+pub use crate::message::{Message, Recipient};
+pub use crate::webex::WebexClient;
+
+/// State shared by every request handler and background task.
+///
+/// The config and the clients built from it are swapped when the config file
+/// is reloaded.
+pub struct AppState {
+    pub config: ArcSwap<Config>,
+    pub gitlab_client: ArcSwap<GitlabClient>,
+    pub webex_client: ArcSwap<WebexClient>,
+    /// The Webex integration's tokens, kept across Webex client changes.
+    pub webex_tokens: Option<Arc<WebexTokens>>,
+    pub digest: Digest,
+    pub pipeline_statuses: PipelineStatusCache,
+    pub queue: Option<Queue>,
+    pub dead_letters: Option<DeadLetters>,
+    /// The most recent GitLab webhooks.
+    pub events: Option<Events>,
+    pub mutes: Mutes,
+    pub preferences: Preferences,
+    pub subscriptions: Subscriptions,
+    pub links: Links,
+    /// Reviewers who were asked and haven't reviewed yet.
+    pub review_requests: Option<ReviewRequests>,
+    /// GitLab tokens people registered to have revbot act as them.
+    pub user_tokens: Option<UserTokens>,
+    pub rate_limiter: RateLimiter,
+    /// Messages which later ones are replies to or edits of.
+    pub sent: SentMessages,
+    /// Webhooks and submissions whose messages are still being worked on.
+    pub in_flight: InFlight,
+    /// A permit for each webhook which may be worked on at once.
+    pub webhook_tasks: Arc<Semaphore>,
+}
+
+impl AppState {
+    /// The current config, which doesn't change under whoever holds it.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    pub fn gitlab_client(&self) -> Arc<GitlabClient> {
+        self.gitlab_client.load_full()
+    }
+
+    pub fn webex_client(&self) -> Arc<WebexClient> {
+        self.webex_client.load_full()
+    }
+}
+
+/// Sends the messages, except for those to muted people and those held back
+/// for quiet hours, a digest or the rate limit.
+pub async fn send_messages(messages: Vec<message::Message>, state: &AppState) {
+    let config = state.config();
+    let messages = state.mutes.filter(messages);
+    let messages = state.preferences.filter(messages);
+    let messages = match &state.queue {
+        Some(queue) => queue.hold_quiet(messages, &config).await,
+        None => messages,
+    };
+    let messages = state.digest.hold(messages, &config);
+    let messages = state.rate_limiter.limit(messages, config.rate_limit.as_ref());
+    dispatch_messages(messages, state).await;
+}
+
+/// Queues the messages for delivery, or delivers them straight away without a queue.
+pub async fn dispatch_messages(messages: Vec<message::Message>, state: &AppState) {
+    let config = state.config();
+    match &state.queue {
+        Some(queue) => {
+            // Secrets shouldn't end up on disk either.
+            let messages = messages
+                .into_iter()
+                .map(|mut message| {
+                    message.message = config.redaction.scrub(&message.message);
+                    message
+                })
+                .collect();
+            if let Err(err) = queue.push(messages).await {
+                error!("Couldn't queue messages: {}", err);
+            }
+        }
+        None => {
+            let webex_client = state.webex_client();
+            for message in messages {
+                let recipient = message.recipient.clone();
+                match deliver_message(message.clone(), &webex_client, &config, &state.sent).await {
+                    Ok(Some(created)) => info!(%recipient, outcome = "sent", "Sent message {} to: {}", created.id, recipient),
+                    Ok(None) => info!(%recipient, outcome = "sent", "Sent message to: {}", recipient),
+                    Err(err) => {
+                        match &err {
+                            webex::SendError::InvalidToken => {
+                                error!(%recipient, outcome = "failed", "Error sending message to {}: {}, check webex.access_token", recipient, err);
+                            }
+                            _ => warn!(%recipient, outcome = "failed", "Error sending message to {}: {}", recipient, err),
+                        }
+                        dead_letter(message, &err, state).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Keeps a message which couldn't be delivered, so that it can be sent again
+/// later, if dead letters are kept.
+pub async fn dead_letter(mut message: message::Message, err: &webex::SendError, state: &AppState) {
+    let dead_letters = match &state.dead_letters {
+        Some(dead_letters) => dead_letters,
+        None => return,
+    };
+    message.message = state.config().redaction.scrub(&message.message);
+    let recipient = message.recipient.clone();
+    if let Err(err) = dead_letters.add(message, err.to_string()).await {
+        error!("Couldn't keep undelivered message to {}: {}", recipient, err);
+    }
+}
+
+/// Sends the message, as a reply if its thread was started already, or as an
+/// edit of the message it replaces. Returns what Webex created, unless the
+/// message was mocked.
+pub async fn deliver_message(message: message::Message, webex_client: &WebexClient, config: &Config, sent: &SentMessages) -> Result<Option<webex::CreatedMessage>, webex::SendError> {
+    let markdown = config.redaction.scrub(&message.message);
+    let replaces = message.replaces.as_ref().map(|key| format!("replaces {}", key));
+    // Cards can't be edited, so those are sent anew.
+    let previous = replaces.as_ref().filter(|_| message.actions.is_empty()).and_then(|key| sent.get(&message.recipient, key));
+    if let Some(previous) = previous {
+        match webex_client.update_message(&previous, markdown.clone()).await {
+            Ok(updated) => return Ok(Some(updated)),
+            Err(err) => warn!("Couldn't edit message {}, sending a new one: {}", previous.id, err),
+        }
+    }
+
+    let thread = message.thread.as_ref().map(|key| format!("thread {}", key));
+    let parent = thread.as_ref().and_then(|key| sent.get(&message.recipient, key));
+    let starts_thread = thread.is_some() && parent.is_none();
+    let recipient = message.recipient.clone();
+    let mut webex_msg = match (parent, message.recipient) {
+        (Some(parent), _) => webex::Message::to_room(parent.room_id, markdown.clone()).in_thread(parent.id),
+        (None, message::Recipient::Person(email)) => webex::Message::to_person(email, markdown.clone()),
+        (None, message::Recipient::Room(room_id)) => webex::Message::to_room(room_id, markdown.clone()),
+    };
+    if !message.actions.is_empty() {
+        webex_msg = webex_msg.with_attachment(cards::adaptive_card(&markdown, &message.actions));
+    }
+
+    let created = webex_client.send_message(webex_msg).await?;
+    if let Some(created) = &created {
+        if let Some(thread) = thread.filter(|_| starts_thread) {
+            sent.insert(recipient.clone(), thread, created.clone());
+        }
+        if let Some(replaces) = replaces {
+            sent.insert(recipient, replaces, created.clone());
+        }
+    }
+
+    Ok(created)
+}
+
+/// Checks that both access tokens are accepted, and logs who revbot acts as.
+pub async fn verify_credentials(config: &Config, gitlab_client: &GitlabClient, webex_client: &WebexClient) -> Result<(), Box<dyn std::error::Error>> {
+    let gitlab = &config.gitlab;
+    match gitlab_client.get_current_user().await {
+        Ok(user) => info!("Acting on GitLab ({}) as: @{}", gitlab.hostname, user.username),
+        Err(err) => return Err(format!(
+            "GitLab ({}) rejected the access token: {}. \
+            Check gitlab.access_token or $REVBOT_GITLAB__ACCESS_TOKEN.",
+            gitlab.hostname, err).into()),
+    }
+
+    if config.webex.mock {
+        info!("Not checking Webex access token, messages are mocked");
+        return Ok(());
+    }
+    match webex_client.get_me().await {
+        Ok(person) => info!("Acting on Webex as: {} ({})", person.display_name, person.emails.join(", ")),
+        Err(err) => return Err(format!(
+            "Webex rejected the access token: {}. \
+            Check webex.access_token or $REVBOT_WEBEX__ACCESS_TOKEN.",
+            err).into()),
+    }
+
+    Ok(())
+}
+
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::RevbotError;
+
+/// How long someone has to put the code on their GitLab profile.
+const PENDING_TTL_HOURS: i64 = 1;
+
+/// GitLab usernames (in lower case) linked to Webex emails with `link`,
+/// shared with the config so that identity lookups find them.
+pub type LinkedIdentities = Arc<RwLock<HashMap<String, String>>>;
+
+/// A `link` someone started, waiting for the code to show up on the GitLab
+/// profile they claim.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingLink {
+    pub username: String,
+    pub code: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// The identities people linked themselves, by GitLab username in lower case,
+/// and the links they started, by Webex email in lower case.
+pub struct Links {
+    linked: sled::Tree,
+    pending: sled::Tree,
+    identities: LinkedIdentities,
+}
+
+impl Links {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        let db = sled::open(path)?;
+        Self::from_db(&db)
+    }
+
+    fn from_db(db: &sled::Db) -> sled::Result<Self> {
+        let linked = db.open_tree("linked")?;
+        let mut identities = HashMap::new();
+        for entry in linked.iter() {
+            let (username, email) = entry?;
+            identities.insert(String::from_utf8_lossy(&username).into_owned(), String::from_utf8_lossy(&email).into_owned());
+        }
+
+        Ok(Self {
+            linked,
+            pending: db.open_tree("pending")?,
+            identities: Arc::new(RwLock::new(identities)),
+        })
+    }
+
+    pub fn identities(&self) -> LinkedIdentities {
+        self.identities.clone()
+    }
+
+    /// Starts linking the person to the GitLab user, returning the code to
+    /// put on the user's profile. A link started earlier is replaced.
+    pub async fn start(&self, email: &str, username: &str) -> Result<String, RevbotError> {
+        let mut code = [0u8; 6];
+        OsRng.fill_bytes(&mut code);
+        let pending = PendingLink {
+            username: username.to_lowercase(),
+            code: format!("revbot-{}", hex::encode(code)),
+            started_at: Utc::now(),
+        };
+        self.pending.insert(email.to_lowercase(), serde_json::to_vec(&pending)?)?;
+        self.pending.flush_async().await?;
+
+        Ok(pending.code)
+    }
+
+    /// The link the person started, unless it's expired.
+    pub fn pending(&self, email: &str, now: DateTime<Utc>) -> Option<PendingLink> {
+        let value = match self.pending.get(email.to_lowercase()) {
+            Ok(value) => value?,
+            Err(err) => {
+                warn!("Couldn't read pending link for {}: {}", email, err);
+                return None;
+            }
+        };
+        let pending: PendingLink = serde_json::from_slice(&value).ok()?;
+        if now - pending.started_at < Duration::hours(PENDING_TTL_HOURS) { Some(pending) } else { None }
+    }
+
+    /// Links the GitLab user to the person, for good.
+    pub async fn link(&self, email: &str, username: &str) -> sled::Result<()> {
+        let (username, email) = (username.to_lowercase(), email.to_lowercase());
+        self.linked.insert(&username, email.as_bytes())?;
+        self.pending.remove(&email)?;
+        self.linked.flush_async().await?;
+        self.identities.write().unwrap().insert(username, email);
+        Ok(())
+    }
+
+    /// Forgets the GitLab users linked to the person, returning them.
+    pub async fn unlink(&self, email: &str) -> sled::Result<Vec<String>> {
+        let usernames: Vec<String> = self.identities
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, linked_email)| linked_email.eq_ignore_ascii_case(email))
+            .map(|(username, _)| username.to_owned())
+            .collect();
+        for username in &usernames {
+            self.linked.remove(username)?;
+            self.identities.write().unwrap().remove(username);
+        }
+        self.linked.flush_async().await?;
+        Ok(usernames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_link() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let links = Links::from_db(&db).unwrap();
+        let code = links.start("Someone@example.com", "Hds-").await.unwrap();
+        assert!(code.starts_with("revbot-"));
+
+        let now = Utc::now();
+        let pending = links.pending("someone@example.com", now).unwrap();
+        assert_eq!(("hds-", code.as_str()), (pending.username.as_str(), pending.code.as_str()));
+        assert!(links.pending("someone@example.com", now + Duration::hours(2)).is_none());
+
+        links.link("someone@example.com", "hds-").await.unwrap();
+        assert!(links.pending("someone@example.com", now).is_none());
+        assert_eq!(Some("someone@example.com"), links.identities().read().unwrap().get("hds-").map(String::as_str));
+        // Links survive a restart.
+        assert_eq!(1, Links::from_db(&db).unwrap().identities().read().unwrap().len());
+
+        assert_eq!(vec!["hds-".to_owned()], links.unlink("someone@example.com").await.unwrap());
+        assert!(links.identities().read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+/// A merge request webhook which adds a new assignee, so it generates a message.
+fn merge_request_webhook(n: u64) -> Value {
+    let user = |id: u64| json!({
+        "email": format!("loadtest-{}@example.com", id),
+        "id": id,
+        "name": format!("Load Test {}", id),
+        "username": format!("loadtest-{}", id),
+    });
+
+    json!({
+        "object_kind": "merge_request",
+        "object_attributes": {
+            "iid": n,
+            "merge_status": "can_be_merged",
+            "title": format!("Load test {}", n),
+            "url": format!("https://gitlab.example.com/loadtest/revbot/-/merge_requests/{}", n),
+        },
+        "changes": {
+            "assignees": {
+                "previous": [],
+                "current": [user(n % 50)],
+            },
+        },
+        "project": {
+            "id": 1,
+            "name": "revbot",
+            "path_with_namespace": "loadtest/revbot",
+            "web_url": "https://gitlab.example.com/loadtest/revbot",
+        },
+        "user": user(1_000_000),
+    })
+}
+
+/// A pipeline webhook without a merge request, which is parsed but doesn't
+/// need the GitLab API.
+fn pipeline_webhook(n: u64) -> Value {
+    json!({
+        "object_kind": "pipeline",
+        "object_attributes": {
+            "finished_at": null,
+            "id": n,
+            "ref": "loadtest",
+            "status": "running",
+        },
+        "project": {
+            "id": 1,
+            "name": "revbot",
+            "path_with_namespace": "loadtest/revbot",
+            "web_url": "https://gitlab.example.com/loadtest/revbot",
+        },
+        "user": {
+            "email": "loadtest@example.com",
+            "id": 1_000_000,
+            "name": "Load Test",
+            "username": "loadtest",
+        },
+    })
+}
+
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let index = (sorted.len() * percentile / 100).min(sorted.len() - 1);
+    sorted[index]
+}
+
+/// Fires synthetic webhooks at `target` at a fixed `rate` per second and
+/// reports the throughput and latency percentiles.
+///
+/// Run the target with `webex.mock = true`, otherwise it will try to deliver
+/// every message it generates.
+pub async fn run(target: String, token: Option<String>, rate: u32, duration: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / rate.max(1));
+    let mut requests = Vec::new();
+
+    info!("Sending {} webhooks per second to {} for {:?}", rate, target, duration);
+    let start = Instant::now();
+    let mut n = 0;
+    while start.elapsed() < duration {
+        interval.tick().await;
+        n += 1;
+        let webhook = if n % 2 == 0 { merge_request_webhook(n) } else { pipeline_webhook(n) };
+        let mut request = client.post(&target).json(&webhook);
+        if let Some(token) = &token {
+            request = request.header("X-Gitlab-Token", token);
+        }
+        requests.push(tokio::spawn(async move {
+            let sent = Instant::now();
+            let result = request.send().await.and_then(|res| res.error_for_status());
+            (sent.elapsed(), result.is_ok())
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(requests.len());
+    let mut errors = 0;
+    for request in requests {
+        match request.await {
+            Ok((latency, true)) => latencies.push(latency),
+            Ok((_, false)) => errors += 1,
+            Err(err) => {
+                warn!("Load test request panicked: {}", err);
+                errors += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    latencies.sort();
+
+    println!("requests:   {} ({} errors)", n, errors);
+    println!("throughput: {:.1} req/s", latencies.len() as f64 / elapsed.as_secs_f64());
+    println!("latency:    p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(&latencies, 50), percentile(&latencies, 90), percentile(&latencies, 99),
+        latencies.last().copied().unwrap_or_default());
+
+    Ok(())
+}
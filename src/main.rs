@@ -1,149 +1,324 @@
-use std::{convert::Infallible, net::SocketAddr};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use bytes::Bytes;
-use hyper::body;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{self, Body, Error, Request, Response, Server};
-use serde::Deserialize;
+use arc_swap::ArcSwap;
+use futures::future::{join_all, BoxFuture, FutureExt};
+use hyper::{self, Server};
 use structopt::StructOpt;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{prelude::*, EnvFilter};
 
-mod message;
-mod gitlab;
-mod webex;
+use revbot::config::{Config, LogFormat, TelemetryConfig};
+use revbot::dead_letters::DeadLetters;
+use revbot::digest::Digest;
+use revbot::events::Events;
+use revbot::gitlab::client::GitlabClient;
+use revbot::gitlab::dedup::PipelineStatusCache;
+use revbot::links::Links;
+use revbot::message::Recipient;
+use revbot::mutes::Mutes;
+use revbot::preferences::Preferences;
+use revbot::subscriptions::Subscriptions;
+use revbot::queue::Queue;
+use revbot::review_sla::ReviewRequests;
+use revbot::ratelimit::{self, RateLimiter};
+use revbot::shutdown::InFlight;
+use revbot::sent::SentMessages;
+use revbot::user_tokens::UserTokens;
+use revbot::webex::WebexClient;
+use revbot::webex_tokens::WebexTokens;
+use revbot::{alerts, digest, grpc, loadtest, queue, reload, replay, report, scheduler, send_test, server, shutdown, telemetry, tls, verify_credentials, AppState};
 
-use crate::gitlab::client::GitlabClient;
-use crate::gitlab::webhook::process_webhook;
-use crate::webex::WebexClient;
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The config file, without its extension
+    #[structopt(short, long, default_value = "conf/default")]
+    config: String,
 
-async fn send_messages(messages: Vec<message::Message>, webex_client: WebexClient) {
+    /// The IP address to listen on, instead of `server.address` from the config
+    #[structopt(long)]
+    address: Option<String>,
 
-        for message in messages {
+    /// The port to listen on, instead of `server.port` from the config
+    #[structopt(short, long)]
+    port: Option<u16>,
 
-            let recipient_email = message.recipient_email.clone();
-            let webex_msg = webex::Message::new(message.recipient_email, message.message);
-            let webex_client = webex_client.clone();
-            match webex_client.send_message(webex_msg).await {
-                Ok(_) => info!("Sent assignee message to: {}", recipient_email),
-                Err(err) => warn!("Error sending assignee message to {}: {:?}", recipient_email, err),
-            }
-        }
-}
+    /// Start without checking the GitLab and Webex access tokens
+    #[structopt(long)]
+    skip_startup_checks: bool,
 
-fn handle_webhook(bytes: Bytes, gitlab_client: GitlabClient, webex_client: WebexClient) {
-
-    tokio::spawn(async move {
-        let gitlab_client = gitlab_client.clone();
-        let messages = match process_webhook(bytes, gitlab_client).await {
-            Ok(messages) => messages,
-            Err(error) => {
-                warn!("Error creating messages from webhook: {}", error);
-                return;
-            }
-        };
-        let webex_client = webex_client.clone();
-        send_messages(messages, webex_client.clone()).await;
-    });
+    /// `text` or `json`, instead of `log_format` from the config
+    #[structopt(long)]
+    log_format: Option<LogFormat>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-async fn handle(request: Request<Body>, gitlab_client: GitlabClient, webex_client: WebexClient) -> Result<Response<Body>, Infallible> {
-    let response = Response::new(Body::empty());
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Fire synthetic webhooks at a running instance and report latencies
+    Loadtest {
+        #[structopt(long, default_value = "http://127.0.0.1:4001/gitlab")]
+        target: String,
 
-    match body::to_bytes(request.into_body()).await {
-        Ok(bytes) => handle_webhook(bytes, gitlab_client, webex_client),
-        Err(error) => warn!("Error getting request body: {}", error),
-    }
+        /// Sent as the X-Gitlab-Token header, set to the target's gitlab.webhook_token
+        #[structopt(long, env = "REVBOT_GITLAB__WEBHOOK_TOKEN", hide_env_values = true)]
+        token: Option<String>,
 
-    Ok(response)
-}
+        /// Webhooks per second
+        #[structopt(long, default_value = "100")]
+        rate: u32,
 
-#[derive(Debug, StructOpt)]
-struct Opt {
-    #[structopt(short, long, default_value = "config/default")]
-    config: String,
+        #[structopt(long, default_value = "30s", parse(try_from_str = humantime::parse_duration))]
+        duration: Duration,
+    },
+    /// Load the config and report any problems with it, exiting non-zero if there are some
+    CheckConfig {
+        /// Also check that GitLab and Webex accept the access tokens
+        #[structopt(long)]
+        ping: bool,
+    },
+    /// Render a sample message and send it, to check the Webex token and who gets messages
+    SendTest {
+        /// The person's Webex email
+        #[structopt(long)]
+        to: String,
 
-    #[structopt(long, default_value = "127.0.0.1")]
-    address: String,
+        /// A room to send it to as well
+        #[structopt(long)]
+        room: Option<String>,
+    },
+    /// Work through a GitLab webhook saved to a file, printing the messages it makes and sending them
+    Replay {
+        /// The webhook's JSON body
+        path: String,
 
-    #[structopt(short, long, default_value = "4001")]
-    port: u32,
+        /// Only print the messages
+        #[structopt(long)]
+        dry_run: bool,
+    },
 }
 
-#[derive(Deserialize, Debug)]
-struct GitlabConfig {
-    access_token: String,
-    hostname: String,
-    webhook_path: Option<String>,
-    webhook_token: Option<String>,
+/// Spans are exported too with telemetry configured, unless the exporter can't be set up.
+fn init_tracing(log_format: LogFormat, telemetry: Option<&TelemetryConfig>) {
+    let tracer = telemetry.and_then(|telemetry_config| match telemetry::tracer(telemetry_config) {
+        Ok(tracer) => Some(tracer),
+        Err(err) => {
+            eprintln!("Not exporting traces to {}: {}", telemetry_config.endpoint, err);
+            None
+        }
+    });
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+    match log_format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json().with_current_span(true).with_span_list(false)).init(),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct WebexConfig {
-    access_token: String,
-    webhook_path: Option<String>,
-    webhook_token: Option<String>,
-    whoami_link: Option<String>,
+async fn check_config(path: &str, ping: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(path).await?;
+    let problems = config.problems();
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        return Err(format!("Found {} problems in {}", problems.len(), path).into());
+    }
+
+    if ping {
+        let gitlab_client = GitlabClient::new(config.gitlab.hostname.clone(), config.gitlab.access_token.clone(), config.gitlab.cache_ttl()).await?;
+        verify_credentials(&config, &gitlab_client, &WebexClient::from_config(&config.webex)).await?;
+    }
+    println!("{} is OK", path);
+
+    Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    gitlab: GitlabConfig,
-    webex: WebexConfig,
+/// A Webex client for the subcommands. A running revbot holds the
+/// integration's tokens, the configured ones may still do.
+fn command_webex_client(config: &Config) -> WebexClient {
+    let webex_tokens = config.webex.oauth.as_ref().and_then(|oauth_config| match WebexTokens::open(oauth_config, &config.webex.access_token) {
+        Ok(webex_tokens) => Some(Arc::new(webex_tokens)),
+        Err(err) => {
+            warn!("Not refreshing the Webex access token, couldn't open {}: {}", oauth_config.path, err);
+            None
+        }
+    });
+    WebexClient::from_config(&config.webex).with_tokens(webex_tokens)
 }
 
-impl Config {
-    fn new(filename: &str) -> Result<Self, config::ConfigError> {
-        let mut config = config::Config::default();
-        config.merge(config::File::with_name(filename))?;
-        config.merge(config::Environment::with_prefix("REVBOT").separator("__"))?;
+async fn send_test(path: &str, to: String, room: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(path).await?;
+    let recipients = std::iter::once(Recipient::Person(to)).chain(room.map(Recipient::Room)).collect();
 
-        config.try_into()
-    }
+    send_test::run(&config, &command_webex_client(&config), recipients).await
 }
 
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+async fn replay(path: &str, webhook_path: &str, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(path).await?;
+    let gitlab_client = GitlabClient::new(config.gitlab.hostname.clone(), config.gitlab.access_token.clone(), config.gitlab.cache_ttl()).await?;
+
+    replay::run(webhook_path, dry_run, &config, &gitlab_client, &command_webex_client(&config)).await
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_tracing();
-
     let opt = Opt::from_args();
-    info!("We would start on: {}:{}", opt.address, opt.port);
+    match opt.command {
+        Some(Command::Loadtest { target, token, rate, duration }) => {
+            init_tracing(opt.log_format.unwrap_or_default(), None);
+            return loadtest::run(target, token, rate, duration).await;
+        }
+        Some(Command::CheckConfig { ping }) => {
+            init_tracing(opt.log_format.unwrap_or_default(), None);
+            return check_config(&opt.config, ping).await;
+        }
+        Some(Command::SendTest { to, room }) => {
+            init_tracing(opt.log_format.unwrap_or_default(), None);
+            return send_test(&opt.config, to, room).await;
+        }
+        Some(Command::Replay { path, dry_run }) => {
+            init_tracing(opt.log_format.unwrap_or_default(), None);
+            return replay(&opt.config, &path, dry_run).await;
+        }
+        None => {}
+    }
 
-    let config = Config::new("conf/default")?;
+    // The config says how to log, so it's loaded first.
+    let mut config = Config::load(&opt.config).await?;
+    init_tracing(opt.log_format.unwrap_or(config.log_format), config.telemetry.as_ref());
 
     debug!("Config (now what?): {:?}", config);
 
-    let gitlab_client = GitlabClient::new(config.gitlab.hostname, config.gitlab.access_token);
-    let webex_client = WebexClient::new(config.webex.access_token, config.webex.whoami_link);
+    let gitlab_client = GitlabClient::new(config.gitlab.hostname.clone(), config.gitlab.access_token.clone(), config.gitlab.cache_ttl()).await?;
+    let webex_tokens = match &config.webex.oauth {
+        Some(oauth_config) => Some(Arc::new(WebexTokens::open(oauth_config, &config.webex.access_token)?)),
+        None => None,
+    };
+    let webex_client = WebexClient::from_config(&config.webex).with_tokens(webex_tokens.clone());
+    let queue = match &config.queue {
+        Some(queue_config) => Some(Queue::open(&queue_config.path)?),
+        None => None,
+    };
+    let dead_letters = match &config.dead_letters {
+        Some(dead_letters_config) => Some(DeadLetters::open(&dead_letters_config.path)?),
+        None => None,
+    };
+    let events = match &config.events {
+        Some(events_config) => Some(Events::open(&events_config.path, events_config.max_events)?),
+        None => None,
+    };
+    let mutes = Mutes::open(&config.mutes.path)?;
+    let preferences = Preferences::open(&config.preferences.path)?;
+    let subscriptions = Subscriptions::open(&config.subscriptions.path)?;
+    let links = Links::open(&config.links.path)?;
+    config.linked_identities = links.identities();
+    let review_requests = match &config.review_sla {
+        Some(review_sla_config) => Some(ReviewRequests::open(&review_sla_config.path)?),
+        None => None,
+    };
+    let user_tokens = match config.actions.as_ref().and_then(|actions| Some((&actions.path, actions.encryption_key()?))) {
+        Some((path, key)) => Some(UserTokens::open(path, &key)?),
+        None => None,
+    };
+    let max_webhook_tasks = config.server.max_webhook_tasks;
+    let state = Arc::new(AppState {
+        config: ArcSwap::from_pointee(config),
+        gitlab_client: ArcSwap::from_pointee(gitlab_client),
+        webex_client: ArcSwap::from_pointee(webex_client),
+        webex_tokens,
+        digest: Digest::default(),
+        pipeline_statuses: PipelineStatusCache::default(),
+        queue,
+        dead_letters,
+        events,
+        mutes,
+        preferences,
+        subscriptions,
+        links,
+        review_requests,
+        user_tokens,
+        rate_limiter: RateLimiter::default(),
+        sent: SentMessages::default(),
+        in_flight: InFlight::default(),
+        webhook_tasks: Arc::new(Semaphore::new(max_webhook_tasks)),
+    });
 
-    let addr_str = format!("{}:{}", opt.address, opt.port);
-    let addr: SocketAddr = addr_str.parse().expect("Bad address");
+    if opt.skip_startup_checks {
+        warn!("Skipping startup checks");
+    } else if let Err(err) = verify_credentials(&state.config(), &state.gitlab_client(), &state.webex_client()).await {
+        error!("Startup check failed: {}", err);
+        std::process::exit(1);
+    }
 
-    let make_service = make_service_fn(move |_| {
-        let gitlab_client = gitlab_client.clone();
-        let webex_client = webex_client.clone();
+    tokio::spawn(scheduler::run_milestone_reminders(state.clone()));
+    tokio::spawn(scheduler::run_escalations(state.clone()));
+    tokio::spawn(scheduler::run_review_sla(state.clone()));
+    tokio::spawn(report::run_weekly_reports(state.clone()));
+    tokio::spawn(queue::run_delivery(state.clone()));
+    tokio::spawn(queue::run_quiet_hours(state.clone()));
+    tokio::spawn(digest::run_flushes(state.clone()));
+    tokio::spawn(ratelimit::run_summaries(state.clone()));
+    tokio::spawn(grpc::serve(state.clone()));
+    tokio::spawn(alerts::run_checks(state.clone()));
+    tokio::spawn(reload::watch(state.clone(), opt.config.clone()));
 
-        async move {
-            Ok::<_, Error>(service_fn(move |request: Request<Body>| {
-                let gitlab_client = gitlab_client.clone();
-                let webex_client = webex_client.clone();
-                handle(request, gitlab_client, webex_client)
-            }))
-        }
-    });
+    let config = state.config();
+    let server_config = &config.server;
+    let addr = server_config.listen_addr(opt.address.as_deref(), opt.port)?;
+    let make_handler = server::MakeHandler::new(state.clone(), server_config.max_connections);
+    let header_read_timeout = Duration::from_secs(server_config.header_read_timeout_secs);
 
-    let server = Server::bind(&addr).serve(make_service);
+    // Each server stops accepting connections on a signal, and finishes the requests in progress.
+    let signal_received = shutdown::signal_received().shared();
+    let mut servers: Vec<BoxFuture<'static, hyper::Result<()>>> = Vec::new();
+    // With TLS on the usual port, or a unix socket, there's no plain HTTP over TCP.
+    if let Some(path) = &server_config.unix_socket {
+        info!("Serving HTTP on unix socket: {}", path);
+        let server = Server::builder(server::unix_incoming(path)?)
+            .http1_header_read_timeout(header_read_timeout)
+            .http1_keepalive(server_config.keep_alive)
+            .serve(make_handler.clone())
+            .with_graceful_shutdown(signal_received.clone());
+        servers.push(server.boxed());
+    } else if config.tls.as_ref().is_none_or(|tls_config| tls_config.port.is_some()) {
+        let server = Server::try_bind(&addr)?
+            .http1_header_read_timeout(header_read_timeout)
+            .http1_keepalive(server_config.keep_alive)
+            .tcp_keepalive(server_config.tcp_keepalive_secs.map(Duration::from_secs))
+            .serve(make_handler.clone())
+            .with_graceful_shutdown(signal_received.clone());
+        info!("Serving HTTP on: {}", addr);
+        servers.push(server.boxed());
+    }
+    if let Some(tls_config) = &config.tls {
+        let tls_addr = SocketAddr::new(addr.ip(), tls_config.port.unwrap_or_else(|| addr.port()));
+        let listener = TcpListener::bind(tls_addr).await?;
+        info!("Serving HTTPS on: {}", tls_addr);
+        let server = Server::builder(tls::incoming(listener, tls::acceptor(tls_config)?))
+            .http1_header_read_timeout(header_read_timeout)
+            .http1_keepalive(server_config.keep_alive)
+            .serve(make_handler)
+            .with_graceful_shutdown(signal_received.clone());
+        servers.push(server.boxed());
+    }
+
+    for result in join_all(servers).await {
+        if let Err(e) = result {
+            error!("server error: {}", e);
+        }
+    }
 
-    if let Err(e) = server.await {
-        error!("server error: {}", e);
+    let shutdown_timeout = Duration::from_secs(state.config().server.shutdown_timeout_secs);
+    if tokio::time::timeout(shutdown_timeout, state.in_flight.wait_idle()).await.is_err() {
+        warn!("Gave up waiting for tasks after {:?}, their messages are lost", shutdown_timeout);
     }
+    telemetry::shutdown();
+    info!("Shut down");
 
     Ok(())
 }
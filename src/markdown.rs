@@ -0,0 +1,97 @@
+/// Characters which mean something in Webex markdown within a line.
+const SPECIAL: &[char] = &['\\', '`', '*', '_', '[', ']', '(', ')', '<', '>', '~', '|'];
+
+/// Escapes text from GitLab, e.g. a merge request title or a branch name, so
+/// that it shows as it is instead of breaking the link or message it's in.
+///
+/// Line breaks become spaces, as the text is shown on one line.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\r' | '\n' => escaped.push(' '),
+            c if SPECIAL.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The last `c` in the text which isn't backslash escaped.
+fn rfind_unescaped(text: &str, c: char) -> Option<usize> {
+    text.match_indices(c).map(|(i, _)| i).rev().find(|&i| !text[..i].ends_with('\\'))
+}
+
+/// Cuts the line to at most `max_bytes`, before a link that would be cut in
+/// half.
+fn truncate_line(line: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(line.len());
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &line[..end];
+    match (rfind_unescaped(cut, '['), rfind_unescaped(cut, ')')) {
+        (Some(open), Some(close)) if open < close => cut,
+        (Some(open), _) => &cut[..open],
+        (None, _) => cut,
+    }
+}
+
+/// Shortens markdown to at most `max_bytes`, keeping whole lines where it can
+/// so that links stay intact, and saying how many lines were left out.
+pub fn truncate(markdown: &str, max_bytes: usize) -> String {
+    if markdown.len() <= max_bytes {
+        return markdown.to_owned();
+    }
+
+    // Leaves room to say what was left out.
+    let budget = max_bytes.saturating_sub(48);
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut kept = 0;
+    let mut length = 0;
+    for line in &lines {
+        if length + line.len() + 1 > budget {
+            break;
+        }
+        length += line.len() + 1;
+        kept += 1;
+    }
+
+    if kept == 0 {
+        return format!("{}…", truncate_line(lines[0], budget));
+    }
+    format!("{}\n\n_…{} more lines left out_", lines[..kept].join("\n"), lines.len() - kept)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("Fix the build", escape("Fix the build"));
+        assert_eq!(r"Handle \[\] in \`parse\(\)\`", escape("Handle [] in `parse()`"));
+        assert_eq!(r"\]\(https://evil.example.com\) \<b\>bold\</b\>", escape("](https://evil.example.com) <b>bold</b>"));
+        assert_eq!(r"feature/snake\_case \*\*wip\*\* \~\~old\~\~ a \| b", escape("feature/snake_case **wip** ~~old~~ a | b"));
+        assert_eq!(r"C:\\path two lines", escape("C:\\path\ntwo lines"));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!("short", truncate("short", 100));
+
+        let list = (1..=10).map(|i| format!("- [!{} Fix it](https://gitlab.example.com/g/p/-/merge_requests/{})", i, i)).collect::<Vec<_>>().join("\n");
+        let truncated = truncate(&list, 200);
+        assert!(truncated.len() <= 200);
+        assert!(truncated.starts_with("- [!1 Fix it](https://gitlab.example.com/g/p/-/merge_requests/1)\n- [!2 "));
+        assert!(truncated.ends_with(")\n\n_…8 more lines left out_"));
+
+        let line = format!("{} [!12 Fix it](https://gitlab.example.com/g/p/-/merge_requests/12)", "a".repeat(80));
+        assert_eq!(format!("{} …", "a".repeat(80)), truncate(&line, 140));
+        assert_eq!(format!("{}…", "a".repeat(52)), truncate(&line, 100));
+    }
+}
@@ -1,7 +1,98 @@
+use std::fmt;
 
-#[derive(Clone, Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Recipient {
+    /// A direct message to the Webex user with this email address.
+    Person(String),
+    /// A message posted into the Webex room with this id.
+    Room(String),
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recipient::Person(email) => write!(f, "{}", email),
+            Recipient::Room(room_id) => write!(f, "room {}", room_id),
+        }
+    }
+}
+
+/// Identifies the merge request a message is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct MergeRequestRef {
+    pub project_id: u64,
+    pub iid: u64,
+}
+
+/// What a button asks revbot to do to a merge request, as whoever clicks it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeRequestAction {
+    Approve,
+    MergeWhenPipelineSucceeds,
+    RetryPipeline,
+}
+
+/// What's submitted to revbot when a button is clicked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Submit {
+    pub action: MergeRequestAction,
+    pub project_id: u64,
+    pub iid: u64,
+}
+
+/// A button opening a link, or submitting an action to revbot, for messages
+/// sent as cards.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Action {
+    pub title: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submit: Option<Submit>,
+}
+
+impl Action {
+    pub fn open(title: &str, url: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            url: url.to_owned(),
+            submit: None,
+        }
+    }
+
+    pub fn submit(title: &str, url: &str, action: MergeRequestAction, merge_request: MergeRequestRef) -> Self {
+        Self {
+            title: title.to_owned(),
+            url: url.to_owned(),
+            submit: Some(Submit {
+                action,
+                project_id: merge_request.project_id,
+                iid: merge_request.iid,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Message {
-    pub recipient_email: String,
+    pub recipient: Recipient,
     pub message: String,
+    pub merge_request: Option<MergeRequestRef>,
+    /// Sent as an Adaptive Card with these buttons, unless there are none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<Action>,
+    /// Messages to the same recipient with the same thread are replies to the
+    /// first one of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+    /// Messages to the same recipient with the same key edit the earlier
+    /// message, instead of being sent as a new one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<String>,
+    /// The event the message is about, by template name, which people can
+    /// turn off for themselves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
 }
-
@@ -0,0 +1,64 @@
+use std::convert::TryInto;
+
+use chrono::{DateTime, TimeZone, Utc};
+use tracing::{debug, warn};
+
+use crate::message::{Message, Recipient};
+
+/// People who asked not to be messaged for a while, by email in lower case.
+///
+/// Values are the end of the mute as big endian seconds since the epoch.
+pub struct Mutes {
+    db: sled::Db,
+}
+
+impl Mutes {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    pub async fn mute(&self, email: &str, until: DateTime<Utc>) -> sled::Result<()> {
+        self.db.insert(email.to_lowercase(), &until.timestamp().to_be_bytes())?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    pub async fn unmute(&self, email: &str) -> sled::Result<()> {
+        self.db.remove(email.to_lowercase())?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    /// When the person's mute ends, if they're muted at `now`.
+    pub fn muted_until(&self, email: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let value = match self.db.get(email.to_lowercase()) {
+            Ok(value) => value?,
+            Err(err) => {
+                warn!("Couldn't read mute for {}: {}", email, err);
+                return None;
+            }
+        };
+        let until = Utc.timestamp_opt(i64::from_be_bytes(value.as_ref().try_into().ok()?), 0).single()?;
+        if until > now { Some(until) } else { None }
+    }
+
+    /// Drops the messages for people who are muted.
+    pub fn filter(&self, messages: Vec<Message>) -> Vec<Message> {
+        let now = Utc::now();
+        messages
+            .into_iter()
+            .filter(|message| match &message.recipient {
+                Recipient::Person(email) => match self.muted_until(email, now) {
+                    Some(until) => {
+                        debug!("Dropping message to {}, muted until {}", email, until);
+                        false
+                    }
+                    None => true,
+                },
+                Recipient::Room(_) => true,
+            })
+            .collect()
+    }
+}
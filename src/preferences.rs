@@ -0,0 +1,107 @@
+use std::collections::BTreeSet;
+
+use tracing::{debug, warn};
+
+use crate::error::RevbotError;
+use crate::message::{Message, Recipient};
+
+/// The events people turned off for themselves with `disable`, by email in
+/// lower case.
+///
+/// Values are the JSON list of the events.
+pub struct Preferences {
+    db: sled::Db,
+}
+
+impl Preferences {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// The events the person doesn't want messages about.
+    pub fn disabled(&self, email: &str) -> BTreeSet<String> {
+        let value = match self.db.get(email.to_lowercase()) {
+            Ok(Some(value)) => value,
+            Ok(None) => return BTreeSet::new(),
+            Err(err) => {
+                warn!("Couldn't read preferences for {}: {}", email, err);
+                return BTreeSet::new();
+            }
+        };
+        serde_json::from_slice(&value).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable preferences for {}: {}", email, err);
+            BTreeSet::new()
+        })
+    }
+
+    /// Turns the event on or off for the person.
+    pub async fn set(&self, email: &str, event: &str, enabled: bool) -> Result<(), RevbotError> {
+        let mut disabled = self.disabled(email);
+        if enabled {
+            disabled.remove(event);
+        } else {
+            disabled.insert(event.to_owned());
+        }
+
+        if disabled.is_empty() {
+            self.db.remove(email.to_lowercase())?;
+        } else {
+            self.db.insert(email.to_lowercase(), serde_json::to_vec(&disabled)?)?;
+        }
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    /// Drops the messages about events their recipient turned off.
+    pub fn filter(&self, messages: Vec<Message>) -> Vec<Message> {
+        messages
+            .into_iter()
+            .filter(|message| match (&message.recipient, &message.event) {
+                (Recipient::Person(email), Some(event)) if self.disabled(email).contains(event) => {
+                    debug!("Dropping {} message to {}, who disabled it", event, email);
+                    false
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(email: &str, event: Option<&str>) -> Message {
+        Message {
+            recipient: Recipient::Person(email.to_owned()),
+            message: "Running".to_owned(),
+            merge_request: None,
+            actions: Vec::new(),
+            thread: None,
+            replaces: None,
+            event: event.map(str::to_owned),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter() {
+        let preferences = Preferences {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+        };
+        preferences.set("Someone@example.com", "pipeline_running", false).await.unwrap();
+
+        let messages = vec![
+            message("someone@example.com", Some("pipeline_running")),
+            message("someone@example.com", Some("pipeline_failed")),
+            message("someone@example.com", None),
+            message("else@example.com", Some("pipeline_running")),
+        ];
+        assert_eq!(3, preferences.filter(messages.clone()).len());
+
+        preferences.set("someone@example.com", "pipeline_running", true).await.unwrap();
+        assert!(preferences.disabled("someone@example.com").is_empty());
+        assert_eq!(4, preferences.filter(messages).len());
+    }
+}
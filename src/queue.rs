@@ -0,0 +1,207 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::error::RevbotError;
+use crate::message::{Message, Recipient};
+use crate::webex::SendError;
+use crate::AppState;
+
+/// How long to wait before trying again while Webex is unavailable.
+const UNAVAILABLE_PAUSE: Duration = Duration::from_secs(60);
+/// How often held messages are checked for quiet hours being over.
+const QUIET_HOURS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Messages waiting to be delivered, in the order they were queued.
+///
+/// Keys are ids from the database's monotonic counter, big endian so that
+/// they sort in the order they were generated. Messages for people in their
+/// quiet hours are held in a tree of their own, keyed the same way.
+pub struct Queue {
+    db: sled::Db,
+    held: sled::Tree,
+    pushed: Notify,
+}
+
+impl Queue {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            held: db.open_tree("held")?,
+            db,
+            pushed: Notify::new(),
+        })
+    }
+
+    /// How many messages are waiting to be delivered.
+    pub fn backlog(&self) -> usize {
+        self.db.len()
+    }
+
+    pub async fn push(&self, messages: Vec<Message>) -> Result<(), RevbotError> {
+        for message in messages {
+            let id = self.db.generate_id()?;
+            self.db.insert(id.to_be_bytes(), serde_json::to_vec(&message)?)?;
+        }
+        self.db.flush_async().await?;
+        self.pushed.notify_one();
+
+        Ok(())
+    }
+
+    /// Holds the messages for people in their quiet hours, and returns the ones to send now.
+    pub async fn hold_quiet(&self, messages: Vec<Message>, config: &Config) -> Vec<Message> {
+        let now = Utc::now();
+        let (quiet, messages): (Vec<_>, Vec<_>) = messages
+            .into_iter()
+            .partition(|message| matches!(&message.recipient, Recipient::Person(email) if config.is_quiet(email, now)));
+        if quiet.is_empty() {
+            return messages;
+        }
+
+        let mut not_held = Vec::new();
+        for mut message in quiet {
+            debug!("Holding message to {} until their quiet hours are over", message.recipient);
+            message.message = config.redaction.scrub(&message.message);
+            if let Err(err) = self.insert_held(&message) {
+                warn!("Couldn't hold message to {}, sending it now: {}", message.recipient, err);
+                not_held.push(message);
+            }
+        }
+        if let Err(err) = self.held.flush_async().await {
+            warn!("Couldn't flush held messages: {}", err);
+        }
+
+        messages.into_iter().chain(not_held).collect()
+    }
+
+    fn insert_held(&self, message: &Message) -> Result<(), RevbotError> {
+        let id = self.db.generate_id()?;
+        self.held.insert(id.to_be_bytes(), serde_json::to_vec(message)?)?;
+        Ok(())
+    }
+
+    /// Moves the held messages whose recipients' quiet hours are over into the queue.
+    async fn release_held(&self, config: &Config) -> sled::Result<usize> {
+        let now = Utc::now();
+        let mut released = 0;
+        for entry in self.held.iter() {
+            let (key, value) = entry?;
+            let quiet = match serde_json::from_slice::<Message>(&value) {
+                Ok(message) => matches!(&message.recipient, Recipient::Person(email) if config.is_quiet(email, now)),
+                Err(err) => {
+                    warn!("Dropping unreadable held message: {}", err);
+                    self.held.remove(key)?;
+                    continue;
+                }
+            };
+            if !quiet {
+                self.db.insert(self.db.generate_id()?.to_be_bytes(), value)?;
+                self.held.remove(key)?;
+                released += 1;
+            }
+        }
+        if released > 0 {
+            self.db.flush_async().await?;
+            self.pushed.notify_one();
+        }
+
+        Ok(released)
+    }
+
+    /// The oldest message, if there's one. Unreadable messages are dropped.
+    fn front(&self) -> sled::Result<Option<(sled::IVec, Message)>> {
+        while let Some((key, value)) = self.db.first()? {
+            match serde_json::from_slice(&value) {
+                Ok(message) => return Ok(Some((key, message))),
+                Err(err) => {
+                    warn!("Dropping unreadable queued message: {}", err);
+                    self.db.remove(key)?;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn remove(&self, key: &sled::IVec) -> sled::Result<()> {
+        self.db.remove(key)?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+}
+
+/// Delivers queued messages one by one, keeping each one queued until Webex
+/// has either taken or refused it.
+pub async fn run_delivery(state: Arc<AppState>) {
+    let queue = match &state.queue {
+        Some(queue) => queue,
+        None => return,
+    };
+    info!("Delivering {} queued messages", queue.db.len());
+
+    loop {
+        let (key, message) = match queue.front() {
+            Ok(Some(front)) => front,
+            Ok(None) => {
+                queue.pushed.notified().await;
+                continue;
+            }
+            Err(err) => {
+                warn!("Couldn't read the message queue: {}", err);
+                tokio::time::sleep(UNAVAILABLE_PAUSE).await;
+                continue;
+            }
+        };
+
+        let recipient = message.recipient.clone();
+        match crate::deliver_message(message.clone(), &state.webex_client(), &state.config(), &state.sent).await {
+            Ok(Some(created)) => info!(%recipient, outcome = "sent", "Sent message {} to: {}", created.id, recipient),
+            Ok(None) => info!(%recipient, outcome = "sent", "Sent message to: {}", recipient),
+            Err(err @ SendError::Rejected { .. }) | Err(err @ SendError::MessageTooLong) | Err(err @ SendError::UnknownPerson(_)) => {
+                warn!(%recipient, outcome = "dropped", "Dropping message to {}: {}", recipient, err);
+                crate::dead_letter(message, &err, &state).await;
+            }
+            // The token may well be replaced in the config, which is reloaded.
+            Err(err @ SendError::InvalidToken) => {
+                error!(%recipient, outcome = "kept", "Keeping message to {} queued: {}, check webex.access_token", recipient, err);
+                tokio::time::sleep(UNAVAILABLE_PAUSE).await;
+                continue;
+            }
+            Err(err @ SendError::RateLimited) | Err(err @ SendError::Unavailable(_)) => {
+                warn!(%recipient, outcome = "kept", "Keeping message to {} queued: {}", recipient, err);
+                tokio::time::sleep(UNAVAILABLE_PAUSE).await;
+                continue;
+            }
+        }
+
+        if let Err(err) = queue.remove(&key).await {
+            warn!("Couldn't remove delivered message from the queue: {}", err);
+            tokio::time::sleep(UNAVAILABLE_PAUSE).await;
+        }
+        debug!("{} messages left in the queue", queue.db.len());
+    }
+}
+
+/// Releases messages held during quiet hours once the hours are over.
+pub async fn run_quiet_hours(state: Arc<AppState>) {
+    let queue = match &state.queue {
+        Some(queue) => queue,
+        None => return,
+    };
+    info!("Holding {} messages for quiet hours", queue.held.len());
+
+    let mut interval = tokio::time::interval(QUIET_HOURS_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match queue.release_held(&state.config()).await {
+            Ok(0) => {}
+            Ok(released) => info!("Released {} messages held for quiet hours", released),
+            Err(err) => warn!("Couldn't release held messages: {}", err),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::config::RateLimitConfig;
+use crate::message::{Message, Recipient};
+use crate::AppState;
+
+/// How often the messages which were held back are summed up.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    /// Takes a token if there's one, after topping up for the time since the last refill.
+    fn take(&mut self, config: &RateLimitConfig, now: Instant) -> bool {
+        let capacity = f64::from(config.per_minute);
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A token bucket per recipient, so that nobody gets more than so many messages
+/// a minute. What's over the limit is counted, and sent as a summary later.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<Recipient, Bucket>>,
+    held_back: Mutex<HashMap<Recipient, usize>>,
+}
+
+impl RateLimiter {
+    /// The messages within their recipient's limit, the rest are counted.
+    pub fn limit(&self, messages: Vec<Message>, config: Option<&RateLimitConfig>) -> Vec<Message> {
+        let config = match config {
+            Some(config) => config,
+            None => return messages,
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut held_back = self.held_back.lock().unwrap();
+        messages
+            .into_iter()
+            .filter(|message| {
+                let bucket = buckets.entry(message.recipient.clone()).or_insert(Bucket {
+                    tokens: f64::from(config.per_minute),
+                    refilled_at: now,
+                });
+                if bucket.take(config, now) {
+                    return true;
+                }
+                debug!("Holding back message to {}, over the rate limit", message.recipient);
+                *held_back.entry(message.recipient.clone()).or_default() += 1;
+                false
+            })
+            .collect()
+    }
+
+    fn take_held_back(&self) -> HashMap<Recipient, usize> {
+        std::mem::take(&mut *self.held_back.lock().unwrap())
+    }
+}
+
+fn summary(recipient: Recipient, count: usize) -> Message {
+    let events = if count == 1 { "event" } else { "events" };
+    Message {
+        recipient,
+        message: format!("🌊 …and {} more {}, held back so as not to flood you", count, events),
+        merge_request: None,
+        actions: Vec::new(),
+        thread: None,
+        replaces: None,
+        event: None,
+    }
+}
+
+/// Periodically tells everyone who was over their limit how many messages they missed.
+pub async fn run_summaries(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SUMMARY_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let summaries: Vec<Message> = state.rate_limiter
+            .take_held_back()
+            .into_iter()
+            .map(|(recipient, count)| summary(recipient, count))
+            .collect();
+        if !summaries.is_empty() {
+            debug!("Sending {} rate limit summaries", summaries.len());
+            crate::dispatch_messages(summaries, &state).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_limit() {
+        let config = RateLimitConfig { per_minute: 2 };
+        let limiter = RateLimiter::default();
+        let message = |email: &str| Message {
+            recipient: Recipient::Person(email.to_owned()),
+            message: "Failed".to_owned(),
+            merge_request: None,
+            actions: Vec::new(),
+            thread: None,
+            replaces: None,
+            event: None,
+        };
+
+        let messages = vec![message("a@example.com"), message("a@example.com"), message("a@example.com"), message("b@example.com")];
+        assert_eq!(3, limiter.limit(messages, Some(&config)).len());
+        assert_eq!(0, limiter.limit(vec![message("a@example.com")], Some(&config)).len());
+
+        let held_back = limiter.take_held_back();
+        assert_eq!(Some(&2), held_back.get(&Recipient::Person("a@example.com".to_owned())));
+        assert_eq!(None, held_back.get(&Recipient::Person("b@example.com".to_owned())));
+    }
+}
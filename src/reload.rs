@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::config::{Config, WebexConfig};
+use crate::gitlab::client::GitlabClient;
+use crate::templates::DEFAULT_TEMPLATES_DIR;
+use crate::webex::WebexClient;
+use crate::AppState;
+
+/// Editors tend to write a file in a few steps, so changes are let settle first.
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+fn templates_dir(config: &Config) -> PathBuf {
+    PathBuf::from(config.templates_dir.as_deref().unwrap_or(DEFAULT_TEMPLATES_DIR))
+}
+
+/// The directory is watched rather than the file, as editors often replace
+/// the file instead of writing to it.
+fn config_dir(path: &str) -> &Path {
+    match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn watch_dir(watcher: &mut RecommendedWatcher, dir: &Path) {
+    match watcher.watch(dir, RecursiveMode::NonRecursive) {
+        Ok(_) => debug!("Watching for config changes in: {}", dir.display()),
+        Err(err) => debug!("Not watching {} for config changes: {}", dir.display(), err),
+    }
+}
+
+fn same_webex_client(a: &WebexConfig, b: &WebexConfig) -> bool {
+    a.access_token == b.access_token
+        && a.whoami_link == b.whoami_link
+        && a.mock == b.mock
+        && a.max_attempts == b.max_attempts
+        && a.initial_backoff_ms == b.initial_backoff_ms
+        && a.verify_recipients == b.verify_recipients
+}
+
+/// Loads the config again and swaps it in, along with new clients if their
+/// settings changed. A config which doesn't load leaves the current one in place.
+async fn reload(state: &AppState, path: &str) -> Option<Arc<Config>> {
+    let mut config = match Config::load(path).await {
+        Ok(config) => config,
+        Err(err) => {
+            warn!("Keeping the current config, couldn't load {}: {}", path, err);
+            return None;
+        }
+    };
+    let current = state.config();
+    config.linked_identities = current.linked_identities.clone();
+
+    if config.gitlab.hostname != current.gitlab.hostname
+        || config.gitlab.access_token != current.gitlab.access_token
+        || config.gitlab.cache_ttl_secs != current.gitlab.cache_ttl_secs {
+        match GitlabClient::new(config.gitlab.hostname.clone(), config.gitlab.access_token.clone(), config.gitlab.cache_ttl()).await {
+            Ok(gitlab_client) => state.gitlab_client.store(Arc::new(gitlab_client)),
+            Err(err) => {
+                warn!("Keeping the current config, couldn't connect to GitLab ({}): {}", config.gitlab.hostname, err);
+                return None;
+            }
+        }
+        info!("Reconnected to GitLab ({})", config.gitlab.hostname);
+    }
+    if !same_webex_client(&config.webex, &current.webex) {
+        state.webex_client.store(Arc::new(WebexClient::from_config(&config.webex).with_tokens(state.webex_tokens.clone())));
+        info!("Recreated the Webex client");
+    }
+
+    let config = Arc::new(config);
+    state.config.store(config.clone());
+    info!("Reloaded config from: {}", path);
+
+    Some(config)
+}
+
+/// Reloads the config whenever it, or one of the templates, changes.
+///
+/// The log format and the server, TLS, telemetry, gRPC, queue, dead letters,
+/// events, registered tokens, mutes, preferences, subscriptions, links, Webex
+/// integration, review SLA and schedule settings are only read when revbot starts.
+pub async fn watch(state: Arc<AppState>, path: String) {
+    let (changes_tx, mut changes) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        Ok(event) if !event.kind.is_access() => {
+            let _ = changes_tx.send(());
+        }
+        Ok(_) => {}
+        Err(err) => warn!("Error watching config: {}", err),
+    }) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            warn!("Can't watch the config for changes, it won't be reloaded: {}", err);
+            return;
+        }
+    };
+
+    watch_dir(&mut watcher, config_dir(&path));
+    let mut watched_templates = templates_dir(&state.config());
+    watch_dir(&mut watcher, &watched_templates);
+
+    while changes.recv().await.is_some() {
+        tokio::time::sleep(SETTLE_DELAY).await;
+        while changes.try_recv().is_ok() {}
+
+        let config = match reload(&state, &path).await {
+            Some(config) => config,
+            None => continue,
+        };
+        let templates = templates_dir(&config);
+        if templates != watched_templates {
+            let _ = watcher.unwatch(&watched_templates);
+            watch_dir(&mut watcher, &templates);
+            watched_templates = templates;
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::fs;
+
+use crate::config::Config;
+use crate::gitlab::client::GitlabClient;
+use crate::gitlab::dedup::PipelineStatusCache;
+use crate::gitlab::webhook::{parse_webhook, process_webhook};
+use crate::sent::SentMessages;
+use crate::webex::WebexClient;
+use crate::deliver_message;
+
+/// Works through a GitLab webhook saved to a file as if GitLab had sent it,
+/// printing the messages it makes and sending them unless it's a dry run.
+///
+/// Mutes, preferences and subscriptions aren't applied.
+pub async fn run(path: &str, dry_run: bool, config: &Config, gitlab_client: &GitlabClient, webex_client: &WebexClient) -> Result<(), Box<dyn std::error::Error>> {
+    let bytes = fs::read(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+    let webhook = parse_webhook(&bytes, config)?;
+    let messages = process_webhook(&webhook, gitlab_client, config, &PipelineStatusCache::default()).await?;
+    println!("{} made {} messages", path, messages.len());
+
+    let sent = SentMessages::default();
+    let mut failed = 0;
+    for message in messages {
+        println!("{}", serde_json::to_string_pretty(&message)?);
+        if dry_run {
+            continue;
+        }
+
+        let recipient = message.recipient.clone();
+        match deliver_message(message, webex_client, config, &sent).await {
+            Ok(Some(created)) => println!("Sent message {} to {}", created.id, recipient),
+            Ok(None) => println!("Didn't send message to {}, webex.mock is set or Webex's answer couldn't be read", recipient),
+            Err(err) => {
+                println!("Couldn't send message to {}: {}", recipient, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Couldn't send {} messages", failed).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+use crate::message::{Message, Recipient};
+use crate::AppState;
+
+/// How long from `now` until it's next `weekday` at `time_of_day`.
+fn until_next(weekday: Weekday, time_of_day: NaiveTime, now: DateTime<Utc>) -> Duration {
+    let now = now.naive_utc();
+    let days_ahead = (7 + weekday.num_days_from_monday() as i64 - now.weekday().num_days_from_monday() as i64) % 7;
+    let mut next = (now.date() + chrono::Duration::days(days_ahead)).and_time(time_of_day);
+    if next <= now {
+        next += chrono::Duration::weeks(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
+
+/// The week's numbers for one project, or `None` if GitLab couldn't say.
+async fn project_summary(project: &str, since: DateTime<Utc>, state: &AppState) -> Option<Value> {
+    let gitlab_client = state.gitlab_client();
+    let updated = gitlab_client.list_merge_requests_updated_after(project, since).await?;
+    let open = gitlab_client.list_open_merge_requests(project).await?;
+
+    let opened = updated.iter().filter(|merge_request| merge_request.created_at >= since).count();
+    let merged = updated.iter().filter(|merge_request| merge_request.merged_at.is_some_and(|merged_at| merged_at >= since)).count();
+    Some(json!({
+        "name": project,
+        "url": format!("https://{}/{}", state.config().gitlab.hostname, project),
+        "opened": opened,
+        "merged": merged,
+        "open": open.len(),
+    }))
+}
+
+/// Posts a summary of the past week's merge requests in the configured room,
+/// once a week.
+pub async fn run_weekly_reports(state: Arc<AppState>) {
+    let config = state.config();
+    let report_config = match &config.weekly_report {
+        Some(report_config) => report_config,
+        None => return,
+    };
+
+    loop {
+        tokio::time::sleep(until_next(report_config.weekday, report_config.time_of_day, Utc::now())).await;
+        let until = Utc::now();
+        let since = until - chrono::Duration::weeks(1);
+        if !state.config().notifications.enabled("weekly_report") {
+            continue;
+        }
+
+        let mut projects = Vec::new();
+        for project in report_config.projects.literal_paths() {
+            match project_summary(project, since, &state).await {
+                Some(summary) => projects.push(summary),
+                None => warn!("Couldn't fetch merge requests for the weekly report on {}", project),
+            }
+        }
+        debug!("Sending the weekly report on {} projects", projects.len());
+
+        let context = json!({
+            "since": since.format("%Y-%m-%d").to_string(),
+            "until": until.format("%Y-%m-%d").to_string(),
+            "projects": projects,
+        });
+        let recipient = Recipient::Room(report_config.room_id.to_owned());
+        let message = match state.config().render(&recipient, "weekly_report", &context) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        let report = Message {
+            recipient,
+            message,
+            merge_request: None,
+            actions: Vec::new(),
+            thread: None,
+            replaces: None,
+            event: Some("weekly_report".to_owned()),
+        };
+        crate::send_messages(vec![report], &state).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_until_next() {
+        let nine = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+        // Wednesday 2022-03-09 at noon.
+        let now = Utc.with_ymd_and_hms(2022, 3, 9, 12, 0, 0).unwrap();
+        assert_eq!(Duration::from_secs((4 * 24 + 21) * 60 * 60), until_next(Weekday::Mon, nine, now));
+        assert_eq!(Duration::from_secs((7 * 24 - 3) * 60 * 60), until_next(Weekday::Wed, nine, now));
+        assert_eq!(Duration::from_secs(60 * 60), until_next(Weekday::Wed, NaiveTime::from_hms_opt(13, 0, 0).unwrap(), now));
+    }
+}
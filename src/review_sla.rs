@@ -0,0 +1,164 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::ReviewSlaConfig;
+use crate::error::RevbotError;
+
+/// A reviewer asked to review a merge request, who hasn't done so yet.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct ReviewRequest {
+    pub project_id: u64,
+    pub project: String,
+    pub iid: u64,
+    pub reviewer_id: u64,
+    pub reviewer: String,
+    pub asked_at: DateTime<Utc>,
+    /// 1 once the reviewer was reminded, 2 once it was escalated.
+    pub escalations: i64,
+}
+
+impl ReviewRequest {
+    fn key(&self) -> String {
+        format!("{}!{}!{}", self.project_id, self.iid, self.reviewer_id)
+    }
+}
+
+/// Reviewers waiting to be heard from, keyed by project id, merge request iid
+/// and reviewer id.
+pub struct ReviewRequests {
+    db: sled::Db,
+}
+
+impl ReviewRequests {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// Keeps the request, unless the reviewer was asked already, in which
+    /// case the time they were first asked stays. Returns whether it's new.
+    pub async fn asked(&self, request: &ReviewRequest) -> Result<bool, RevbotError> {
+        let value = serde_json::to_vec(request)?;
+        let inserted = self.db.compare_and_swap(request.key(), None as Option<&[u8]>, Some(value))?.is_ok();
+        self.db.flush_async().await?;
+
+        Ok(inserted)
+    }
+
+    pub async fn update(&self, request: &ReviewRequest) -> Result<(), RevbotError> {
+        self.db.insert(request.key(), serde_json::to_vec(request)?)?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    pub async fn remove(&self, request: &ReviewRequest) -> sled::Result<()> {
+        self.db.remove(request.key())?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    /// All the requests, unreadable ones are left out.
+    pub fn list(&self) -> sled::Result<Vec<ReviewRequest>> {
+        let mut requests = Vec::new();
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            match serde_json::from_slice(&value) {
+                Ok(request) => requests.push(request),
+                Err(err) => warn!("Skipping unreadable review request {}: {}", String::from_utf8_lossy(&key), err),
+            }
+        }
+
+        Ok(requests)
+    }
+}
+
+fn local_time(date: NaiveDate, time: NaiveTime, timezone: &Tz) -> Option<DateTime<Utc>> {
+    Some(timezone.from_local_datetime(&date.and_time(time)).earliest()?.with_timezone(&Utc))
+}
+
+/// How much of the time from `from` to `to` falls within business hours,
+/// on weekdays.
+pub fn business_time(from: DateTime<Utc>, to: DateTime<Utc>, config: &ReviewSlaConfig) -> Duration {
+    let mut total = Duration::zero();
+    let mut date = from.with_timezone(&config.timezone).date_naive();
+    let last = to.with_timezone(&config.timezone).date_naive();
+    while date <= last {
+        if date.weekday().number_from_monday() <= 5 {
+            let start = local_time(date, config.business_start, &config.timezone);
+            let end = local_time(date, config.business_end, &config.timezone);
+            if let (Some(start), Some(end)) = (start, end) {
+                let (start, end) = (start.max(from), end.min(to));
+                if start < end {
+                    total += end - start;
+                }
+            }
+        }
+        date = match date.succ_opt() {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    total
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::config::{EscalationTarget, ProjectPatterns};
+
+    fn config() -> ReviewSlaConfig {
+        ReviewSlaConfig {
+            projects: ProjectPatterns::try_from(vec!["platform/**".to_owned()]).unwrap(),
+            sla_hours: 8,
+            timezone: chrono_tz::Europe::Madrid,
+            business_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            business_end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            escalate_to: EscalationTarget::Author,
+            path: String::new(),
+            check_interval_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_business_time() {
+        let config = config();
+        // Friday 2022-03-04 16:00 in Madrid to Monday 10:30, over the weekend.
+        let from = Utc.with_ymd_and_hms(2022, 3, 4, 15, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2022, 3, 7, 9, 30, 0).unwrap();
+        assert_eq!(Duration::minutes(150), business_time(from, to, &config));
+
+        let night = Utc.with_ymd_and_hms(2022, 3, 8, 20, 0, 0).unwrap();
+        assert_eq!(Duration::zero(), business_time(night, night + Duration::hours(10), &config));
+    }
+
+    #[tokio::test]
+    async fn test_asked() {
+        let requests = ReviewRequests {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+        };
+        let mut request = ReviewRequest {
+            project_id: 1,
+            project: "platform/revbot".to_owned(),
+            iid: 42,
+            reviewer_id: 7,
+            reviewer: "hayden".to_owned(),
+            asked_at: Utc.with_ymd_and_hms(2022, 3, 4, 15, 0, 0).unwrap(),
+            escalations: 0,
+        };
+        assert!(requests.asked(&request).await.unwrap());
+
+        let first_asked_at = request.asked_at;
+        request.asked_at = first_asked_at + Duration::hours(1);
+        assert!(!requests.asked(&request).await.unwrap());
+        assert_eq!(first_asked_at, requests.list().unwrap()[0].asked_at);
+
+        requests.remove(&request).await.unwrap();
+        assert!(requests.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::config::RuleConfig;
+use crate::message::{Message, Recipient};
+
+/// What a webhook is about, for matching against the configured rules.
+#[derive(Debug)]
+pub struct Event<'a> {
+    pub kind: &'static str,
+    pub project: &'a str,
+    pub branch: Option<&'a str>,
+    pub labels: Vec<&'a str>,
+    pub user: Option<&'a str>,
+    pub status: Option<&'a str>,
+}
+
+/// Whether every condition which is set matches.
+fn matches(rule: &RuleConfig, event: &Event) -> bool {
+    let listed = |list: &Option<Vec<String>>, value: Option<&str>| match list {
+        Some(list) => value.is_some_and(|value| list.iter().any(|listed| listed.eq_ignore_ascii_case(value))),
+        None => true,
+    };
+
+    listed(&rule.events, Some(event.kind))
+        && rule.projects.as_ref().is_none_or(|projects| projects.is_match(event.project))
+        && rule.branches.as_ref().is_none_or(|branches| event.branch.is_some_and(|branch| branches.is_match(branch)))
+        && rule.labels.as_ref().is_none_or(|labels| event.labels.iter().any(|label| labels.iter().any(|listed| listed == label)))
+        && listed(&rule.users, event.user)
+        && listed(&rule.statuses, event.status)
+}
+
+/// The first rule matching the event, if any does.
+pub fn find<'a>(rules: &'a [RuleConfig], event: &Event) -> Option<&'a RuleConfig> {
+    rules.iter().find(|rule| matches(rule, event))
+}
+
+/// Sends each distinct message to the recipients instead, and to its own
+/// recipient as well when `keep_default` is set.
+pub fn reroute(messages: Vec<Message>, recipients: &[Recipient], keep_default: bool) -> Vec<Message> {
+    let mut rerouted: Vec<Message> = Vec::new();
+    let mut push = |message: Message| {
+        if !rerouted.iter().any(|sent| sent.recipient == message.recipient && sent.message == message.message) {
+            rerouted.push(message);
+        }
+    };
+
+    for message in messages {
+        for recipient in recipients {
+            push(Message {
+                recipient: recipient.clone(),
+                ..message.clone()
+            });
+        }
+        if keep_default {
+            push(message);
+        }
+    }
+
+    rerouted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    use crate::config::{ProjectPatterns, RuleTarget};
+
+    #[test]
+    fn test_find_rule() {
+        let rule = |events: &[&str], projects: &[&str]| RuleConfig {
+            events: Some(events.iter().map(|event| event.to_string()).collect()),
+            projects: Some(ProjectPatterns::try_from(projects.iter().map(|project| project.to_string()).collect::<Vec<_>>()).unwrap()),
+            branches: None,
+            labels: None,
+            users: None,
+            statuses: Some(vec!["failed".to_owned()]),
+            drop: false,
+            notify: vec![RuleTarget::Author],
+        };
+        let rules = vec![rule(&["pipeline"], &["infra/**"]), rule(&["pipeline", "build"], &["**"])];
+        let event = |kind, project, status| Event {
+            kind,
+            project,
+            branch: Some("main"),
+            labels: Vec::new(),
+            user: Some("hds-"),
+            status: Some(status),
+        };
+
+        assert!(std::ptr::eq(&rules[0], find(&rules, &event("pipeline", "infra/deploy", "failed")).unwrap()));
+        assert!(std::ptr::eq(&rules[1], find(&rules, &event("build", "infra/deploy", "Failed")).unwrap()));
+        assert!(find(&rules, &event("pipeline", "infra/deploy", "success")).is_none());
+        assert!(find(&rules, &event("note", "infra/deploy", "failed")).is_none());
+    }
+
+    #[test]
+    fn test_find_rule_by_label() {
+        let rule = |labels: &[&str], drop| RuleConfig {
+            events: None,
+            projects: None,
+            branches: None,
+            labels: Some(labels.iter().map(|label| label.to_string()).collect()),
+            users: None,
+            statuses: None,
+            drop,
+            notify: vec![RuleTarget::Default, RuleTarget::Room("appsec".to_owned())],
+        };
+        let rules = vec![rule(&["bot"], true), rule(&["security", "cve"], false)];
+        let event = |labels| Event {
+            kind: "merge_request",
+            project: "infra/deploy",
+            branch: Some("fix-cve"),
+            labels,
+            user: Some("hds-"),
+            status: Some("open"),
+        };
+
+        assert!(find(&rules, &event(vec!["bot", "security"])).unwrap().drop);
+        assert!(std::ptr::eq(&rules[1], find(&rules, &event(vec!["backend", "cve"])).unwrap()));
+        assert!(find(&rules, &event(vec!["backend"])).is_none());
+        assert!(find(&rules, &event(Vec::new())).is_none());
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::config::{EscalationStep, EscalationTarget};
+use crate::gitlab::common::{MergeRequest, UserBasic};
+use crate::identity;
+use crate::markdown;
+use crate::message::{MergeRequestRef, Message, Recipient};
+use crate::review_sla::{self, ReviewRequest};
+use crate::AppState;
+
+const DEFAULT_DUE_SOON_DAYS: i64 = 3;
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 60 * 60;
+const DEFAULT_ESCALATION_STATE_PATH: &str = "revbot-escalation.json";
+
+/// Periodically posts a reminder for milestones whose due date is approaching.
+///
+/// Each milestone is only reminded about once per process.
+pub async fn run_milestone_reminders(state: Arc<AppState>) {
+    let config = state.config();
+    let milestones_config = match &config.milestones {
+        Some(milestones_config) => milestones_config,
+        None => return,
+    };
+    let projects = match &milestones_config.projects {
+        Some(projects) => projects,
+        None => return,
+    };
+    let due_soon_days = milestones_config.due_soon_days.unwrap_or(DEFAULT_DUE_SOON_DAYS);
+    let check_interval = Duration::from_secs(milestones_config.check_interval_secs.unwrap_or(DEFAULT_CHECK_INTERVAL_SECS));
+
+    let mut reminded = HashSet::new();
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        interval.tick().await;
+        let today = Utc::now().naive_utc().date();
+
+        let mut messages = Vec::new();
+        for project in projects.literal_paths() {
+            let milestones = match state.gitlab_client().get_active_milestones(project).await {
+                Some(milestones) => milestones,
+                None => {
+                    warn!("Couldn't fetch milestones for {}", project);
+                    continue;
+                }
+            };
+
+            for milestone in milestones {
+                let due_date = match milestone.due_date {
+                    Some(due_date) => due_date,
+                    None => continue,
+                };
+                let days_left = (due_date - today).num_days();
+                if days_left < 0 || days_left > due_soon_days || reminded.contains(&milestone.id) {
+                    continue;
+                }
+
+                debug!("Milestone {} in {} is due in {} days", milestone.title, project, days_left);
+                reminded.insert(milestone.id);
+                let message = format!(
+                    "[%{milestone_title}]({milestone_url}) \
+                    ({project}) \
+                    ⏰ Due {due_date}",
+                    milestone_title=markdown::escape(&milestone.title), milestone_url=milestone.web_url,
+                    project=markdown::escape(project), due_date=due_date);
+                messages.push(Message {
+                    recipient: Recipient::Room(milestones_config.room_id.to_owned()),
+                    message,
+                    merge_request: None,
+                    actions: Vec::new(),
+                    thread: None,
+                    replaces: None,
+                    event: None,
+                });
+            }
+        }
+
+        crate::send_messages(messages, &state).await;
+    }
+}
+
+/// How far an idle merge request has been escalated.
+#[derive(Deserialize, Serialize, Debug)]
+struct EscalationProgress {
+    /// Any activity on the merge request starts the escalation over.
+    updated_at: DateTime<Utc>,
+    steps_taken: usize,
+}
+
+fn load_escalation_state(path: &str) -> HashMap<String, EscalationProgress> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable escalation state in {}: {}", path, err);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn save_escalation_state(path: &str, progress: &HashMap<String, EscalationProgress>) -> std::io::Result<()> {
+    // Write to the side and rename, so that a crash doesn't leave half a file behind.
+    let tmp_path = format!("{}.tmp", path);
+    std::fs::write(&tmp_path, serde_json::to_vec(progress)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+async fn user_recipients(users: &Option<Vec<UserBasic>>, state: &AppState) -> Vec<Recipient> {
+    let mut recipients = Vec::new();
+    for user in users.iter().flatten() {
+        match identity::webex_email_by_id(user.id, Some(&user.username), &state.gitlab_client(), &state.config()).await {
+            Some(email) => recipients.push(Recipient::Person(email)),
+            None => warn!("No email visible for @{}, can't escalate to them", user.username),
+        }
+    }
+    recipients
+}
+
+async fn target_recipients(target: &EscalationTarget, merge_request: &MergeRequest, state: &AppState) -> Vec<Recipient> {
+    match target {
+        EscalationTarget::Author => user_recipients(&Some(vec![merge_request.author.clone()]), state).await,
+        EscalationTarget::Assignees => user_recipients(&merge_request.assignees, state).await,
+        EscalationTarget::Reviewers => user_recipients(&merge_request.reviewers, state).await,
+        EscalationTarget::Room(room_id) => vec![Recipient::Room(room_id.to_owned())],
+        EscalationTarget::Email(email) => vec![Recipient::Person(email.to_owned())],
+    }
+}
+
+async fn escalation_messages(step: &EscalationStep, merge_request: &MergeRequest, project: &str, state: &AppState) -> Vec<Message> {
+    let recipients = target_recipients(&step.notify, merge_request, state).await;
+    let message = format!(
+        "[!{mr_iid} {mr_title}]({mr_url}) \
+        ({project}) \
+        ⏰ No activity for {hours}h",
+        mr_iid=merge_request.iid, mr_title=markdown::escape(&merge_request.title), mr_url=merge_request.web_url,
+        project=markdown::escape(project), hours=step.after_hours);
+
+    reminders(recipients, message, merge_request)
+}
+
+fn reminders(recipients: Vec<Recipient>, message: String, merge_request: &MergeRequest) -> Vec<Message> {
+    recipients
+        .into_iter()
+        .map(|recipient| Message {
+            recipient,
+            message: message.clone(),
+            merge_request: Some(MergeRequestRef {
+                project_id: merge_request.project_id,
+                iid: merge_request.iid,
+            }),
+            actions: Vec::new(),
+            thread: None,
+            replaces: None,
+            event: None,
+        })
+        .collect()
+}
+
+/// Periodically reminds people about open merge requests without activity,
+/// moving along the configured escalation steps the longer they stay idle.
+///
+/// When revbot wasn't running for a while, only the latest overdue step is
+/// taken, not all the ones in between.
+pub async fn run_escalations(state: Arc<AppState>) {
+    let config = state.config();
+    let escalation_config = match &config.escalation {
+        Some(escalation_config) => escalation_config,
+        None => return,
+    };
+    let state_path = escalation_config.state_path.as_deref().unwrap_or(DEFAULT_ESCALATION_STATE_PATH);
+    let check_interval = Duration::from_secs(escalation_config.check_interval_secs.unwrap_or(DEFAULT_CHECK_INTERVAL_SECS));
+    let mut steps: Vec<&EscalationStep> = escalation_config.steps.iter().collect();
+    steps.sort_by_key(|step| step.after_hours);
+
+    let mut progress = load_escalation_state(state_path);
+    info!("Loaded escalation state for {} merge requests", progress.len());
+
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+
+        let mut messages = Vec::new();
+        let mut open = HashSet::new();
+        for project in escalation_config.projects.literal_paths() {
+            let merge_requests = match state.gitlab_client().list_open_merge_requests(project).await {
+                Some(merge_requests) => merge_requests,
+                None => {
+                    warn!("Couldn't fetch open merge requests for {}", project);
+                    continue;
+                }
+            };
+
+            for merge_request in merge_requests {
+                if state.config().filters.skips_title(&merge_request.title)
+                    || state.config().filters.silences_labels(merge_request.labels.iter().map(|label| label.as_str())) {
+                    continue;
+                }
+
+                let key = format!("{}!{}", merge_request.project_id, merge_request.iid);
+                open.insert(key.clone());
+                let mr_progress = progress.entry(key).or_insert(EscalationProgress {
+                    updated_at: merge_request.updated_at,
+                    steps_taken: 0,
+                });
+                if mr_progress.updated_at != merge_request.updated_at {
+                    mr_progress.updated_at = merge_request.updated_at;
+                    mr_progress.steps_taken = 0;
+                }
+
+                let idle_hours = (now - merge_request.updated_at).num_hours();
+                let steps_due = steps.iter().take_while(|step| step.after_hours <= idle_hours).count();
+                if steps_due > mr_progress.steps_taken {
+                    debug!("Escalating !{} in {} to step {}", merge_request.iid, project, steps_due);
+                    mr_progress.steps_taken = steps_due;
+                    messages.extend(escalation_messages(steps[steps_due - 1], &merge_request, project, &state).await);
+                }
+            }
+        }
+
+        progress.retain(|key, _| open.contains(key));
+        if let Err(err) = save_escalation_state(state_path, &progress) {
+            warn!("Couldn't save escalation state to {}: {}", state_path, err);
+        }
+
+        crate::send_messages(messages, &state).await;
+    }
+}
+
+/// Whether the reviewer commented on or approved the merge request since
+/// they were asked, or `None` if GitLab couldn't say.
+async fn has_reviewed(request: &ReviewRequest, state: &AppState) -> Option<bool> {
+    let gitlab_client = state.gitlab_client();
+    let approvals = gitlab_client.get_merge_request_approvals(request.project_id, request.iid).await?;
+    if approvals.approved_by.iter().any(|approval| approval.user.id == request.reviewer_id) {
+        return Some(true);
+    }
+    let notes = gitlab_client.get_merge_request_notes(request.project_id, request.iid).await?;
+    Some(notes.iter().any(|note| !note.system && note.author.id == request.reviewer_id && note.created_at >= request.asked_at))
+}
+
+/// The reviewer is reminded once the SLA is up, and whoever the review SLA
+/// escalates to is told once it's up again.
+async fn review_sla_messages(request: &ReviewRequest, merge_request: &MergeRequest, hours: i64, escalate_to: &EscalationTarget, state: &AppState) -> Vec<Message> {
+    let (recipients, waiting_for) = if request.escalations == 1 {
+        let recipients = match identity::webex_email_by_id(request.reviewer_id, Some(&request.reviewer), &state.gitlab_client(), &state.config()).await {
+            Some(email) => vec![Recipient::Person(email)],
+            None => {
+                warn!("No email visible for @{}, can't remind them", request.reviewer);
+                Vec::new()
+            }
+        };
+        (recipients, "your review".to_owned())
+    } else {
+        let recipients = target_recipients(escalate_to, merge_request, state).await;
+        (recipients, format!("a review by @{}", markdown::escape(&request.reviewer)))
+    };
+
+    let message = format!(
+        "[!{mr_iid} {mr_title}]({mr_url}) \
+        ({project}) \
+        ⏰ Waiting {hours} business hours for {waiting_for}",
+        mr_iid=merge_request.iid, mr_title=markdown::escape(&merge_request.title), mr_url=merge_request.web_url,
+        project=markdown::escape(&request.project), hours=hours, waiting_for=waiting_for);
+
+    reminders(recipients, message, merge_request)
+}
+
+/// Periodically checks on the reviewers who were asked and haven't reviewed
+/// yet, reminding them once the review SLA is up and escalating once it's up
+/// again. Requests are forgotten once the reviewer comments or approves, or
+/// once the merge request is closed or merged.
+pub async fn run_review_sla(state: Arc<AppState>) {
+    let config = state.config();
+    let (review_sla_config, review_requests) = match (&config.review_sla, &state.review_requests) {
+        (Some(review_sla_config), Some(review_requests)) => (review_sla_config, review_requests),
+        _ => return,
+    };
+    let check_interval = Duration::from_secs(review_sla_config.check_interval_secs.unwrap_or(DEFAULT_CHECK_INTERVAL_SECS));
+    let sla_hours = review_sla_config.sla_hours.max(1);
+
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let requests = match review_requests.list() {
+            Ok(requests) => requests,
+            Err(err) => {
+                warn!("Couldn't read review requests: {}", err);
+                continue;
+            }
+        };
+        info!("Checking on {} review requests", requests.len());
+
+        let mut messages = Vec::new();
+        for mut request in requests {
+            let waited_hours = review_sla::business_time(request.asked_at, now, review_sla_config).num_hours();
+            let escalations = (waited_hours / sla_hours).min(2);
+            if escalations <= request.escalations {
+                continue;
+            }
+
+            let merge_request = match state.gitlab_client().get_merge_request_details(request.project_id, request.iid).await {
+                Ok(merge_request) => merge_request,
+                Err(err) => {
+                    warn!("Couldn't fetch !{} in {}: {}", request.iid, request.project, err);
+                    continue;
+                }
+            };
+            let still_reviewer = merge_request.reviewers.iter().flatten().any(|reviewer| reviewer.id == request.reviewer_id);
+            let mut done = merge_request.state != "opened" || !still_reviewer
+                || state.config().filters.skips_title(&merge_request.title)
+                || state.config().filters.silences_labels(merge_request.labels.iter().map(|label| label.as_str()));
+            if !done {
+                done = match has_reviewed(&request, &state).await {
+                    Some(reviewed) => reviewed,
+                    None => {
+                        warn!("Couldn't find out if @{} reviewed !{} in {}", request.reviewer, request.iid, request.project);
+                        continue;
+                    }
+                };
+            }
+            if done {
+                debug!("No longer waiting for @{} to review !{} in {}", request.reviewer, request.iid, request.project);
+                if let Err(err) = review_requests.remove(&request).await {
+                    warn!("Couldn't remove review request: {}", err);
+                }
+                continue;
+            }
+
+            debug!("@{} hasn't reviewed !{} in {} for {} business hours", request.reviewer, request.iid, request.project, waited_hours);
+            request.escalations = escalations;
+            messages.extend(review_sla_messages(&request, &merge_request, escalations * sla_hours, &review_sla_config.escalate_to, &state).await);
+            if let Err(err) = review_requests.update(&request).await {
+                warn!("Couldn't update review request: {}", err);
+            }
+        }
+
+        crate::send_messages(messages, &state).await;
+    }
+}
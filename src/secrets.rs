@@ -0,0 +1,59 @@
+use std::fs;
+
+use serde_json::Value;
+
+use crate::config::VaultConfig;
+
+/// Reads a secret from a file, like those Kubernetes or the Vault agent
+/// mount, without the trailing newline.
+pub fn read_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map(|secret| secret.trim_end_matches(&['\r', '\n'][..]).to_owned())
+        .map_err(|err| format!("Couldn't read secret from {}: {}", path, err))
+}
+
+/// Splits a `<path>#<key>` reference to a Vault secret.
+fn split_reference(reference: &str) -> Result<(&str, &str), String> {
+    match reference.rsplit_once('#') {
+        Some((path, key)) if !path.is_empty() && !key.is_empty() => Ok((path.trim_matches('/'), key)),
+        _ => Err(format!("Vault secret {} isn't <path>#<key>", reference)),
+    }
+}
+
+/// Looks up a `<path>#<key>` secret in Vault's KV (version 2) secrets engine.
+pub async fn read_vault(config: &VaultConfig, reference: &str) -> Result<String, String> {
+    let (path, key) = split_reference(reference)?;
+    let token = match &config.token_file {
+        Some(token_file) => read_file(token_file)?,
+        None => config.token.clone().unwrap_or_default(),
+    };
+    let url = format!("{}/v1/{}/data/{}", config.address.trim_end_matches('/'), config.mount.trim_matches('/'), path);
+
+    let secret: Value = reqwest::Client::new()
+        .get(&url)
+        .header("X-Vault-Token", token)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|err| format!("Couldn't read {} from Vault: {}", path, err))?
+        .json()
+        .await
+        .map_err(|err| format!("Couldn't read {} from Vault: {}", path, err))?;
+
+    match &secret["data"]["data"][key] {
+        Value::String(value) => Ok(value.to_owned()),
+        _ => Err(format!("No {} in Vault secret {}", key, path)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_reference() {
+        assert_eq!(Ok(("revbot/gitlab", "token")), split_reference("/revbot/gitlab#token"));
+        assert!(split_reference("revbot/gitlab").is_err());
+        assert!(split_reference("revbot/gitlab#").is_err());
+    }
+}
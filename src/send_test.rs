@@ -0,0 +1,44 @@
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::message::Recipient;
+use crate::webex::{self, WebexClient};
+
+/// What a successful pipeline on a made up merge request would be rendered with.
+fn sample_context(config: &Config) -> Value {
+    let project_url = format!("https://{}/revbot/send-test", config.gitlab.hostname);
+    json!({
+        "merge_request": { "iid": 1, "title": "Test message from revbot", "url": format!("{}/-/merge_requests/1", project_url) },
+        "project": { "name": "send-test", "url": project_url },
+        "pipeline": { "id": 1, "url": format!("{}/-/pipelines/1", project_url), "kind": "" },
+        "user": "revbot",
+    })
+}
+
+/// Sends a sample message to each recipient, rendered like any other, and
+/// reports what Webex answered.
+pub async fn run(config: &Config, webex_client: &WebexClient, recipients: Vec<Recipient>) -> Result<(), Box<dyn std::error::Error>> {
+    let context = sample_context(config);
+    let mut failed = 0;
+    for recipient in recipients {
+        let markdown = config.render(&recipient, "pipeline_success", &context)?;
+        let message = match &recipient {
+            Recipient::Person(email) => webex::Message::to_person(email.to_owned(), markdown),
+            Recipient::Room(room_id) => webex::Message::to_room(room_id.to_owned(), markdown),
+        };
+
+        match webex_client.send_message(message).await {
+            Ok(Some(created)) => println!("{}: sent message {} to room {} at {}", recipient, created.id, created.room_id, created.created),
+            Ok(None) => println!("{}: not sent, webex.mock is set or Webex's answer couldn't be read", recipient),
+            Err(err) => {
+                println!("{}: {}", recipient, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Couldn't send {} test messages", failed).into());
+    }
+    Ok(())
+}
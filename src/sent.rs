@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::message::Recipient;
+use crate::webex::CreatedMessage;
+
+/// Sent messages are only followed up on for so long, after that a new one is sent.
+const SENT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Messages sent earlier, by recipient and a key saying what they were about,
+/// e.g. the first message of a thread. They're kept in memory, so they're
+/// forgotten when revbot restarts.
+#[derive(Default)]
+pub struct SentMessages {
+    sent: Mutex<HashMap<(Recipient, String), (CreatedMessage, Instant)>>,
+}
+
+impl SentMessages {
+    pub fn get(&self, recipient: &Recipient, key: &str) -> Option<CreatedMessage> {
+        let sent = self.sent.lock().unwrap();
+        let (created, sent_at) = sent.get(&(recipient.clone(), key.to_owned()))?;
+        if sent_at.elapsed() < SENT_TTL { Some(created.clone()) } else { None }
+    }
+
+    pub fn insert(&self, recipient: Recipient, key: String, created: CreatedMessage) {
+        let mut sent = self.sent.lock().unwrap();
+        sent.retain(|_, (_, sent_at)| sent_at.elapsed() < SENT_TTL);
+        sent.insert((recipient, key), (created, Instant::now()));
+    }
+}
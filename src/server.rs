@@ -0,0 +1,892 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::{fs, io};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{ready, Context, Poll};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use hmac::{Hmac, Mac};
+use bytes::BytesMut;
+use chrono::Utc;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::AddrStream;
+use hyper::service::Service;
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use serde_json::json;
+use sha1::Sha1;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::PollSemaphore;
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+
+use crate::commands;
+use crate::config::Config;
+use crate::github;
+use crate::gitlab::mirror::mirror_notifications;
+use crate::gitlab::webhook::{parse_webhook, process_webhook, ParsedWebhook, WebhookError};
+use crate::webex;
+use crate::AppState;
+
+const DEFAULT_GITLAB_WEBHOOK_PATH: &str = "/gitlab";
+const DEFAULT_WEBEX_WEBHOOK_PATH: &str = "/webex";
+const DEFAULT_GITHUB_WEBHOOK_PATH: &str = "/github";
+/// How many times a webhook is processed while GitLab is unavailable, waiting
+/// a bit longer each time.
+const WEBHOOK_ATTEMPTS: u32 = 3;
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// The endpoints served over HTTP, anything else is a 404.
+#[derive(Debug)]
+enum Route {
+    Gitlab,
+    Webex,
+    Github,
+    DeadLetters,
+    Events,
+    Metrics,
+}
+
+fn route(path: &str, config: &Config) -> Option<Route> {
+    let gitlab_path = config.gitlab.webhook_path.as_deref().unwrap_or(DEFAULT_GITLAB_WEBHOOK_PATH);
+    if path == gitlab_path {
+        return Some(Route::Gitlab);
+    }
+    let webex_path = config.webex.webhook_path.as_deref().unwrap_or(DEFAULT_WEBEX_WEBHOOK_PATH);
+    if path == webex_path {
+        return Some(Route::Webex);
+    }
+    if let Some(github) = &config.github {
+        if path == github.webhook_path.as_deref().unwrap_or(DEFAULT_GITHUB_WEBHOOK_PATH) {
+            return Some(Route::Github);
+        }
+    }
+    if config.server.metrics_path.as_deref() == Some(path) {
+        return Some(Route::Metrics);
+    }
+    // The admin endpoints are off without a token.
+    if config.server.admin_token().is_some() {
+        if let Some(dead_letters) = &config.dead_letters {
+            if path == dead_letters.admin_path {
+                return Some(Route::DeadLetters);
+            }
+        }
+        if let Some(events) = &config.events {
+            if path.strip_prefix(events.admin_path.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')) {
+                return Some(Route::Events);
+            }
+        }
+    }
+
+    None
+}
+
+/// How long to ask senders to wait when there are too many webhooks to work on.
+const BUSY_RETRY_AFTER_SECS: u64 = 30;
+
+/// A permit to work on a webhook, or a 503 for the response if there are
+/// already too many being worked on.
+fn webhook_permit(state: &AppState, response: &mut Response<Body>) -> Option<OwnedSemaphorePermit> {
+    match state.webhook_tasks.clone().try_acquire_owned() {
+        Ok(permit) => Some(permit),
+        Err(_) => {
+            warn!("Turning webhook away, there are too many being worked on");
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(BUSY_RETRY_AFTER_SECS));
+            None
+        }
+    }
+}
+
+/// Ids for requests, unique enough to find a request's log lines by.
+fn next_request_id() -> String {
+    static REQUESTS: AtomicU64 = AtomicU64::new(0);
+    let count = REQUESTS.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}", Utc::now().timestamp_millis(), count)
+}
+
+/// The span everything done for a GitLab webhook happens in, including the
+/// GitLab and Webex calls. GitLab's own id for the delivery is kept too, to
+/// find it in the project's webhook log.
+fn gitlab_webhook_span(request: &Request<Body>, request_id: &str) -> Span {
+    let event_uuid = request.headers().get("X-Gitlab-Event-UUID").and_then(|value| value.to_str().ok());
+    info_span!(
+        "gitlab_webhook",
+        request_id,
+        gitlab_event_uuid = event_uuid,
+        kind = field::Empty,
+        project = field::Empty,
+        merge_request = field::Empty,
+        pipeline = field::Empty,
+        event = field::Empty,
+    )
+}
+
+/// Keeps the webhook with the events, if they're kept, returning its id.
+async fn record_event(state: &AppState, payload: &[u8], kind: &str, project: Option<&str>, outcome: &str) -> Option<u64> {
+    let events = state.events.as_ref()?;
+    match events.record(payload, kind, project, outcome).await {
+        Ok(id) => {
+            Span::current().record("event", id);
+            Some(id)
+        }
+        Err(err) => {
+            warn!("Couldn't keep webhook event: {}", err);
+            None
+        }
+    }
+}
+
+async fn set_event_outcome(state: &AppState, event: Option<u64>, outcome: &str) {
+    if let (Some(events), Some(id)) = (&state.events, event) {
+        if let Err(err) = events.set_outcome(id, outcome).await {
+            warn!("Couldn't record what came of webhook event {}: {}", id, err);
+        }
+    }
+}
+
+/// Starts the clock for reviewers the webhook asks to review, in projects with a review SLA.
+async fn record_review_requests(webhook: &ParsedWebhook, state: &AppState, config: &Config) {
+    let (review_requests, review_sla_config) = match (&state.review_requests, &config.review_sla) {
+        (Some(review_requests), Some(review_sla_config)) => (review_requests, review_sla_config),
+        _ => return,
+    };
+    for request in webhook.review_requests() {
+        if !review_sla_config.projects.is_match(&request.project) || !config.projects.allows(&request.project) {
+            continue;
+        }
+        match review_requests.asked(&request).await {
+            Ok(true) => debug!("Waiting for @{} to review !{} in {}", request.reviewer, request.iid, request.project),
+            Ok(false) => {}
+            Err(err) => warn!("Couldn't record review request for @{}: {}", request.reviewer, err),
+        }
+    }
+}
+
+/// Processing takes a while, so it carries on after the response is sent,
+/// holding the permit until it's done.
+///
+/// Processing is tried again when GitLab couldn't be reached for the details.
+/// What came of it is recorded with the webhook's event, if it has one.
+fn handle_webhook(webhook: ParsedWebhook, permit: OwnedSemaphorePermit, state: Arc<AppState>, event: Option<u64>) {
+    let span = Span::current();
+    span.record("kind", webhook.kind());
+    if let Some(project) = webhook.project_path() {
+        span.record("project", project);
+    }
+    if let Some(merge_request) = webhook.merge_request_iid() {
+        span.record("merge_request", merge_request);
+    }
+    if let Some(pipeline) = webhook.pipeline_id() {
+        span.record("pipeline", pipeline);
+    }
+
+    let task = async move {
+        let _permit = permit;
+        let _in_flight = state.in_flight.start();
+        let (config, gitlab_client) = (state.config(), state.gitlab_client());
+        let mut attempt = 1;
+        let messages = loop {
+            match process_webhook(&webhook, &gitlab_client, &config, &state.pipeline_statuses).await {
+                Ok(messages) => break messages,
+                Err(error) if error.is_transient() && attempt < WEBHOOK_ATTEMPTS => {
+                    warn!("Error creating messages from webhook, attempt {} of {}: {}", attempt, WEBHOOK_ATTEMPTS, error);
+                    tokio::time::sleep(WEBHOOK_RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+                Err(error) => {
+                    warn!("Error creating messages from webhook: {}", error);
+                    set_event_outcome(&state, event, &format!("failed: {}", error)).await;
+                    return;
+                }
+            }
+        };
+        info!(messages = messages.len(), "Created {} messages from {} webhook", messages.len(), webhook.kind());
+        set_event_outcome(&state, event, &format!("created {} messages", messages.len())).await;
+        record_review_requests(&webhook, &state, &config).await;
+        if config.gitlab.mirror_notifications {
+            mirror_notifications(&messages, &gitlab_client, &config).await;
+        }
+        let messages = match webhook.project_path() {
+            Some(project) => state.subscriptions.add_rooms(messages, project),
+            None => messages,
+        };
+        crate::send_messages(messages, &state).await;
+    };
+    tokio::spawn(task.instrument(span));
+}
+
+/// Whether the request carries the configured GitLab webhook token, if one is configured.
+fn has_gitlab_token(request: &Request<Body>, config: &Config) -> bool {
+    let webhook_token = match &config.gitlab.webhook_token {
+        Some(webhook_token) => webhook_token,
+        None => return true,
+    };
+
+    let token = request.headers().get("X-Gitlab-Token").map(HeaderValue::as_bytes);
+    token.is_some_and(|token| token.ct_eq(webhook_token.as_bytes()).into())
+}
+
+/// Reads the body up to `max_bytes`, or `None` if there's more than that.
+async fn to_bytes_limited(mut body: Body, max_bytes: usize) -> Result<Option<Bytes>, hyper::Error> {
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max_bytes {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(bytes.freeze()))
+}
+
+/// Reads the whole body, or says which status to respond with when that fails.
+///
+/// Bodies over the configured size are refused, without reading the rest of
+/// them when they say how long they are up front.
+async fn read_body(request: Request<Body>, config: &Config) -> Result<Bytes, StatusCode> {
+    let max_bytes = config.server.max_body_bytes;
+    let content_length = request.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    if let Some(length) = content_length.filter(|length| *length > max_bytes) {
+        warn!("Rejecting request body of {} bytes, over the limit of {}", length, max_bytes);
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let read_timeout = Duration::from_secs(config.server.read_timeout_secs);
+    match tokio::time::timeout(read_timeout, to_bytes_limited(request.into_body(), max_bytes)).await {
+        Ok(Ok(Some(bytes))) => Ok(bytes),
+        Ok(Ok(None)) => {
+            warn!("Rejecting request body over the limit of {} bytes", max_bytes);
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        }
+        Ok(Err(error)) => {
+            warn!("Error getting request body: {}", error);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(_) => {
+            warn!("Timed out getting request body after {:?}", read_timeout);
+            Err(StatusCode::REQUEST_TIMEOUT)
+        }
+    }
+}
+
+async fn handle_gitlab(request: Request<Body>, state: Arc<AppState>) -> Response<Body> {
+    let request_id = next_request_id();
+    let span = gitlab_webhook_span(&request, &request_id);
+    let mut response = handle_gitlab_webhook(request, state).instrument(span).await;
+    if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("X-Request-Id", request_id);
+    }
+
+    response
+}
+
+async fn handle_gitlab_webhook(request: Request<Body>, state: Arc<AppState>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let config = state.config();
+
+    if !has_gitlab_token(&request, &config) {
+        warn!("Rejecting webhook with missing or wrong token");
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+    let permit = match webhook_permit(&state, &mut response) {
+        Some(permit) => permit,
+        None => return response,
+    };
+
+    let bytes = match read_body(request, &config).await {
+        Ok(bytes) => bytes,
+        Err(status) => {
+            *response.status_mut() = status;
+            return response;
+        }
+    };
+
+    match parse_webhook(&bytes, &config) {
+        Ok(webhook) => {
+            let event = record_event(&state, &bytes, webhook.kind(), webhook.project_path(), "received").await;
+            handle_webhook(webhook, permit, state, event);
+        }
+        Err(WebhookError::Malformed(error)) => {
+            warn!("Rejecting malformed webhook: {}", error);
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+        }
+        Err(WebhookError::Unsupported(object_kind)) => {
+            debug!("Ignoring unsupported webhook: {}", object_kind);
+            record_event(&state, &bytes, &object_kind, None, "ignored, unsupported").await;
+            ignored(&mut response, format!("Unsupported object_kind: {}", object_kind));
+        }
+    }
+
+    response
+}
+
+fn json_response(response: &mut Response<Body>, body: serde_json::Value) {
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    *response.body_mut() = Body::from(body.to_string());
+}
+
+/// Responds to a webhook which won't be processed, saying why.
+fn ignored(response: &mut Response<Body>, reason: String) {
+    let body = json!({
+        "status": "ignored",
+        "reason": reason,
+    });
+    *response.status_mut() = StatusCode::ACCEPTED;
+    json_response(response, body);
+}
+
+/// Whether the body is signed with the configured Webex webhook secret, if one
+/// is configured. Webex sends the hex HMAC-SHA1 of the body in X-Spark-Signature.
+fn has_webex_signature(signature: Option<&HeaderValue>, bytes: &[u8], config: &Config) -> bool {
+    let webhook_token = match &config.webex.webhook_token {
+        Some(webhook_token) => webhook_token,
+        None => return true,
+    };
+
+    let signature = match signature.and_then(|value| hex::decode(value.as_bytes()).ok()) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha1>::new_from_slice(webhook_token.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(bytes);
+    mac.verify_slice(&signature).is_ok()
+}
+
+async fn handle_webex(request: Request<Body>, state: Arc<AppState>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let config = state.config();
+    let permit = match webhook_permit(&state, &mut response) {
+        Some(permit) => permit,
+        None => return response,
+    };
+
+    let signature = request.headers().get("X-Spark-Signature").cloned();
+    let bytes = match read_body(request, &config).await {
+        Ok(bytes) => bytes,
+        Err(status) => {
+            *response.status_mut() = status;
+            return response;
+        }
+    };
+    if !has_webex_signature(signature.as_ref(), &bytes, &config) {
+        warn!("Rejecting Webex webhook with missing or wrong signature");
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+
+    let webhook: webex::Webhook = match serde_json::from_slice(&bytes) {
+        Ok(webhook) => webhook,
+        Err(error) => {
+            warn!("Rejecting malformed Webex webhook: {}", error);
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+    };
+    if webhook.event != "created" || !matches!(webhook.resource.as_str(), "messages" | "attachmentActions") {
+        debug!("Ignoring Webex webhook: {} {}", webhook.resource, webhook.event);
+        return response;
+    }
+
+    tokio::spawn(async move {
+        let _permit = permit;
+        let _in_flight = state.in_flight.start();
+        match webhook.resource.as_str() {
+            "attachmentActions" => commands::handle_card_action(webhook.data, state.clone()).await,
+            _ => commands::handle_message(webhook.data, state.clone()).await,
+        }
+    });
+
+    response
+}
+
+/// Whether the body is signed with the configured GitHub webhook secret, if
+/// one is configured. GitHub sends `sha256=` and the hex HMAC-SHA256 of the
+/// body in X-Hub-Signature-256.
+fn has_github_signature(signature: Option<&HeaderValue>, bytes: &[u8], config: &Config) -> bool {
+    let webhook_secret = match config.github.as_ref().and_then(|github| github.webhook_secret.as_ref()) {
+        Some(webhook_secret) => webhook_secret,
+        None => return true,
+    };
+
+    let signature = signature
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(|value| hex::decode(value).ok());
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(webhook_secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(bytes);
+    mac.verify_slice(&signature).is_ok()
+}
+
+async fn handle_github(request: Request<Body>, state: Arc<AppState>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let config = state.config();
+    let permit = match webhook_permit(&state, &mut response) {
+        Some(permit) => permit,
+        None => return response,
+    };
+
+    let signature = request.headers().get("X-Hub-Signature-256").cloned();
+    let event = request.headers().get("X-GitHub-Event").and_then(|value| value.to_str().ok()).unwrap_or_default().to_owned();
+    let bytes = match read_body(request, &config).await {
+        Ok(bytes) => bytes,
+        Err(status) => {
+            *response.status_mut() = status;
+            return response;
+        }
+    };
+    if !has_github_signature(signature.as_ref(), &bytes, &config) {
+        warn!("Rejecting GitHub webhook with missing or wrong signature");
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+
+    match github::webhook::parse_webhook(&event, &bytes, &config) {
+        Ok(webhook) => {
+            tokio::spawn(async move {
+                let _permit = permit;
+                let _in_flight = state.in_flight.start();
+                let messages = github::webhook::process_webhook(&webhook, &config);
+                crate::send_messages(messages, &state).await;
+            });
+        }
+        Err(WebhookError::Malformed(error)) => {
+            warn!("Rejecting malformed GitHub webhook: {}", error);
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+        }
+        Err(WebhookError::Unsupported(event)) => {
+            debug!("Ignoring unsupported GitHub webhook: {}", event);
+            ignored(&mut response, format!("Unsupported event: {}", event));
+        }
+    }
+
+    response
+}
+
+/// Whether the request carries the admin token as a bearer token.
+fn has_admin_token(request: &Request<Body>, config: &Config) -> bool {
+    let admin_token = match config.server.admin_token() {
+        Some(admin_token) => admin_token,
+        None => return false,
+    };
+
+    let token = request.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+    token.is_some_and(|token| token.ct_eq(admin_token.as_bytes()).into())
+}
+
+/// The dead letter to send again, from `?id=`, or `None` for all of them.
+fn dead_letter_id(request: &Request<Body>) -> Result<Option<u64>, StatusCode> {
+    let query = request.uri().query().unwrap_or_default();
+    match query.split('&').find_map(|pair| pair.strip_prefix("id=")) {
+        Some(id) => id.parse().map(Some).map_err(|_| StatusCode::BAD_REQUEST),
+        None => Ok(None),
+    }
+}
+
+/// Lists the dead letters on GET, and sends them again on POST.
+async fn handle_dead_letters(request: Request<Body>, state: Arc<AppState>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let dead_letters = match &state.dead_letters {
+        Some(dead_letters) => dead_letters,
+        None => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+    };
+    if !has_admin_token(&request, &state.config()) {
+        warn!("Rejecting dead letters request with missing or wrong token");
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+
+    match *request.method() {
+        Method::GET => match dead_letters.list() {
+            Ok(listed) => {
+                let listed: Vec<_> = listed
+                    .into_iter()
+                    .map(|(id, dead_letter)| json!({
+                        "id": id,
+                        "failed_at": dead_letter.failed_at,
+                        "error": dead_letter.error,
+                        "message": dead_letter.message,
+                    }))
+                    .collect();
+                json_response(&mut response, json!(listed));
+            }
+            Err(err) => {
+                warn!("Couldn't list dead letters: {}", err);
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        },
+        Method::POST => {
+            let id = match dead_letter_id(&request) {
+                Ok(id) => id,
+                Err(status) => {
+                    *response.status_mut() = status;
+                    return response;
+                }
+            };
+            let messages = match dead_letters.take(id).await {
+                Ok(messages) => messages,
+                Err(err) => {
+                    warn!("Couldn't take dead letters to send again: {}", err);
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    return response;
+                }
+            };
+            info!("Sending {} dead letters again", messages.len());
+            *response.status_mut() = StatusCode::ACCEPTED;
+            json_response(&mut response, json!({ "replayed": messages.len() }));
+            // Those which fail again become dead letters again.
+            tokio::spawn(async move {
+                let _in_flight = state.in_flight.start();
+                crate::dispatch_messages(messages, &state).await;
+            });
+        }
+        _ => {
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        }
+    }
+
+    response
+}
+
+/// Lists the events, shows one with its payload, or processes one again.
+async fn handle_events(request: Request<Body>, state: Arc<AppState>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let config = state.config();
+    let (events, admin_path) = match (&state.events, &config.events) {
+        (Some(events), Some(events_config)) => (events, events_config.admin_path.as_str()),
+        _ => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+    };
+    if !has_admin_token(&request, &config) {
+        warn!("Rejecting events request with missing or wrong token");
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+
+    let path = request.uri().path().strip_prefix(admin_path).unwrap_or_default().trim_matches('/');
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let id = match segments.first().map(|id| id.parse::<u64>()) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+        None => None,
+    };
+
+    match (request.method(), id, &segments[..]) {
+        (&Method::GET, None, _) => match events.list() {
+            Ok(listed) => {
+                let listed: Vec<_> = listed
+                    .into_iter()
+                    .map(|(id, event)| json!({
+                        "id": id,
+                        "received_at": event.received_at,
+                        "kind": event.kind,
+                        "project": event.project,
+                        "outcome": event.outcome,
+                    }))
+                    .collect();
+                json_response(&mut response, json!(listed));
+            }
+            Err(err) => {
+                warn!("Couldn't list events: {}", err);
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        },
+        (&Method::GET, Some(id), [_]) => match events.get(id) {
+            Ok(Some(event)) => json_response(&mut response, json!({ "id": id, "event": event })),
+            Ok(None) => *response.status_mut() = StatusCode::NOT_FOUND,
+            Err(err) => {
+                warn!("Couldn't read event {}: {}", id, err);
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        },
+        (&Method::POST, Some(id), [_, "replay"]) => {
+            let event = match events.get(id) {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                    return response;
+                }
+                Err(err) => {
+                    warn!("Couldn't read event {}: {}", id, err);
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    return response;
+                }
+            };
+            let permit = match webhook_permit(&state, &mut response) {
+                Some(permit) => permit,
+                None => return response,
+            };
+            let span = info_span!(
+                "gitlab_webhook",
+                request_id = next_request_id().as_str(),
+                kind = field::Empty,
+                project = field::Empty,
+                merge_request = field::Empty,
+                pipeline = field::Empty,
+                event = id,
+            );
+            match parse_webhook(event.payload.as_bytes(), &config) {
+                Ok(webhook) => {
+                    info!(event = id, "Processing webhook event {} again", id);
+                    set_event_outcome(&state, Some(id), "replaying").await;
+                    span.in_scope(|| handle_webhook(webhook, permit, state.clone(), Some(id)));
+                    *response.status_mut() = StatusCode::ACCEPTED;
+                    json_response(&mut response, json!({ "status": "replaying" }));
+                }
+                Err(error) => {
+                    debug!("Not processing webhook event {} again: {}", id, error);
+                    ignored(&mut response, error.to_string());
+                }
+            }
+        }
+        (&Method::GET, ..) | (&Method::POST, ..) => *response.status_mut() = StatusCode::NOT_FOUND,
+        _ => *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+    }
+
+    response
+}
+
+/// The GitLab client's cache hits and misses, in the Prometheus text format.
+fn handle_metrics(request: Request<Body>, state: &AppState) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    if request.method() != Method::GET {
+        *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        return response;
+    }
+
+    let gitlab_client = state.gitlab_client();
+    let caches = [("pipeline", gitlab_client.pipeline_cache().hits(), gitlab_client.pipeline_cache().misses()),
+        ("merge_request", gitlab_client.merge_request_cache().hits(), gitlab_client.merge_request_cache().misses())];
+    let mut body = String::new();
+    body.push_str("# HELP revbot_gitlab_cache_hits_total GitLab details found in the cache.\n");
+    body.push_str("# TYPE revbot_gitlab_cache_hits_total counter\n");
+    for (details, hits, _) in &caches {
+        body.push_str(&format!("revbot_gitlab_cache_hits_total{{details=\"{}\"}} {}\n", details, hits));
+    }
+    body.push_str("# HELP revbot_gitlab_cache_misses_total GitLab details fetched as they weren't in the cache.\n");
+    body.push_str("# TYPE revbot_gitlab_cache_misses_total counter\n");
+    for (details, _, misses) in &caches {
+        body.push_str(&format!("revbot_gitlab_cache_misses_total{{details=\"{}\"}} {}\n", details, misses));
+    }
+
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4"));
+    *response.body_mut() = Body::from(body);
+    response
+}
+
+/// The path with `server.base_path` taken off, or None if it's outside of it.
+fn strip_base_path<'a>(path: &'a str, base_path: Option<&str>) -> Option<&'a str> {
+    let base_path = match base_path.map(|base_path| base_path.trim_end_matches('/')) {
+        Some(base_path) if !base_path.is_empty() => base_path,
+        _ => return Some(path),
+    };
+    match path.strip_prefix(base_path) {
+        Some("") => Some("/"),
+        Some(rest) if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Takes `server.base_path` off the request's URI, so that the handlers see
+/// the same paths with or without one. Returns whether it was under it.
+fn without_base_path(request: &mut Request<Body>, base_path: Option<&str>) -> bool {
+    let path_and_query = match (strip_base_path(request.uri().path(), base_path), request.uri().query()) {
+        (Some(path), Some(query)) => format!("{}?{}", path, query),
+        (Some(path), None) => path.to_owned(),
+        (None, _) => return false,
+    };
+    match path_and_query.parse::<Uri>() {
+        Ok(uri) => {
+            *request.uri_mut() = uri;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// The span a request is handled in, with who made it and over what. Behind
+/// a trusted reverse proxy, that's what its X-Forwarded-For and
+/// X-Forwarded-Proto headers say rather than the proxy's own connection.
+fn request_span(request: &Request<Body>, config: &Config, remote_addr: Option<SocketAddr>, scheme: &str) -> Span {
+    // The reverse proxy appends what it saw, anything before that is up to the client.
+    let forwarded = |name: &str| {
+        request.headers()
+            .get(name)
+            .filter(|_| config.server.trust_forwarded_headers)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+    let client = forwarded("X-Forwarded-For").or_else(|| remote_addr.map(|addr| addr.ip().to_string()));
+    let proto = forwarded("X-Forwarded-Proto").unwrap_or_else(|| scheme.to_owned());
+    info_span!("request", client = client.as_deref().unwrap_or("local"), proto = proto.as_str())
+}
+
+pub async fn handle(mut request: Request<Body>, state: Arc<AppState>) -> Result<Response<Body>, Infallible> {
+    if !without_base_path(&mut request, state.config().server.base_path.as_deref()) {
+        debug!("Outside of the base path: {}", request.uri().path());
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let response = match route(request.uri().path(), &state.config()) {
+        Some(Route::Gitlab) => handle_gitlab(request, state).await,
+        Some(Route::Webex) => handle_webex(request, state).await,
+        Some(Route::Github) => handle_github(request, state).await,
+        Some(Route::DeadLetters) => handle_dead_letters(request, state).await,
+        Some(Route::Events) => handle_events(request, state).await,
+        Some(Route::Metrics) => handle_metrics(request, &state),
+        None => {
+            debug!("No route for: {}", request.uri().path());
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    };
+
+    Ok(response)
+}
+
+/// What the handlers need to know about a connection.
+pub trait Connection {
+    /// Where the connection comes from, unless it's over a unix socket.
+    fn remote_addr(&self) -> Option<SocketAddr>;
+    fn scheme(&self) -> &'static str;
+}
+
+impl Connection for AddrStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(AddrStream::remote_addr(self))
+    }
+
+    fn scheme(&self) -> &'static str {
+        "http"
+    }
+}
+
+impl Connection for UnixStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn scheme(&self) -> &'static str {
+        "http"
+    }
+}
+
+/// Makes the service for each connection, over plain HTTP or TLS alike. Each
+/// connection holds a permit until it's closed. The permit is taken before
+/// hyper accepts the connection, so that it stops accepting once there are
+/// `max_connections` of them.
+pub struct MakeHandler {
+    state: Arc<AppState>,
+    connections: PollSemaphore,
+    /// Taken once ready, for the next connection.
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl MakeHandler {
+    pub fn new(state: Arc<AppState>, max_connections: usize) -> Self {
+        Self {
+            state,
+            connections: PollSemaphore::new(Arc::new(Semaphore::new(max_connections))),
+            permit: None,
+        }
+    }
+}
+
+/// Shares the limit on connections, but not the permit taken for the next one.
+impl Clone for MakeHandler {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            connections: self.connections.clone(),
+            permit: None,
+        }
+    }
+}
+
+/// Connections to a unix socket, which is replaced if it's left over from
+/// an earlier run.
+pub fn unix_incoming(path: &str) -> io::Result<impl Accept<Conn = UnixStream, Error = io::Error>> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+
+    Ok(accept::poll_fn(move |cx| listener.poll_accept(cx).map(|accepted| Some(accepted.map(|(stream, _)| stream)))))
+}
+
+impl<'a, T: Connection> Service<&'a T> for MakeHandler {
+    type Response = Handler;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Handler, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        if self.permit.is_none() {
+            let permit = ready!(self.connections.poll_acquire(cx)).expect("Connection semaphore closed");
+            self.permit = Some(permit);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, connection: &'a T) -> Self::Future {
+        let permit = self.permit.take().expect("MakeHandler called before it was ready");
+        let handler = Handler {
+            state: self.state.clone(),
+            remote_addr: connection.remote_addr(),
+            scheme: connection.scheme(),
+            _permit: permit,
+        };
+        Box::pin(async move { Ok(handler) })
+    }
+}
+
+/// Handles the requests on one connection.
+pub struct Handler {
+    state: Arc<AppState>,
+    remote_addr: Option<SocketAddr>,
+    scheme: &'static str,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Service<Request<Body>> for Handler {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let span = request_span(&request, &self.state.config(), self.remote_addr, self.scheme);
+        Box::pin(handle(request, self.state.clone()).instrument(span))
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// Counts the tasks which are still working on messages, so that shutdown
+/// can wait for them.
+#[derive(Default)]
+pub struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks a task as in flight until it's dropped.
+pub struct InFlightGuard<'a> {
+    in_flight: &'a InFlight,
+}
+
+impl InFlight {
+    pub fn start(&self) -> InFlightGuard<'_> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard { in_flight: self }
+    }
+
+    pub async fn wait_idle(&self) {
+        loop {
+            // Created before checking, so a notification in between isn't missed.
+            let idle = self.idle.notified();
+            let count = self.count.load(Ordering::SeqCst);
+            if count == 0 {
+                return;
+            }
+            info!("Waiting for {} tasks to finish", count);
+            idle.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.in_flight.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
+}
+
+/// Resolves on the first SIGTERM or SIGINT.
+pub async fn signal_received() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(err) => {
+            warn!("Can't listen for SIGTERM: {}", err);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+    }
+}
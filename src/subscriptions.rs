@@ -0,0 +1,138 @@
+use std::collections::BTreeSet;
+
+use tracing::{debug, warn};
+
+use crate::error::RevbotError;
+use crate::message::{Message, Recipient};
+use crate::templates;
+
+/// What can be subscribed to besides single events, by the events they cover.
+const KINDS: &[(&str, &[&str])] = &[
+    ("pipelines", &["pipeline_success", "pipeline_failed", "pipeline_running", "branch_broken", "job_failed"]),
+    ("merges", &["merged"]),
+    ("merge_requests", &["ready_for_review", "approved", "unapproved", "merged", "closed"]),
+    ("comments", &["note"]),
+    ("deployments", &["deployment_running", "deployment_success", "deployment_failed"]),
+];
+
+/// Whether rooms can subscribe to it, as a kind or an event.
+pub fn is_kind(kind: &str) -> bool {
+    KINDS.iter().any(|(name, _)| *name == kind) || templates::is_event(kind)
+}
+
+fn covers(kind: &str, event: &str) -> bool {
+    kind == event || KINDS.iter().any(|(name, events)| *name == kind && events.contains(&event))
+}
+
+/// Rooms which asked for a project's events with `subscribe`, keyed by the
+/// project path and room id.
+///
+/// Values are the JSON list of the kinds subscribed to.
+pub struct Subscriptions {
+    db: sled::Db,
+}
+
+impl Subscriptions {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    fn key(project: &str, room_id: &str) -> String {
+        format!("{}!{}", project.to_lowercase(), room_id)
+    }
+
+    pub async fn subscribe(&self, project: &str, room_id: &str, kinds: &BTreeSet<String>) -> Result<(), RevbotError> {
+        self.db.insert(Self::key(project, room_id), serde_json::to_vec(kinds)?)?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    /// Returns whether the room was subscribed.
+    pub async fn unsubscribe(&self, project: &str, room_id: &str) -> sled::Result<bool> {
+        let removed = self.db.remove(Self::key(project, room_id))?.is_some();
+        self.db.flush_async().await?;
+        Ok(removed)
+    }
+
+    /// The rooms subscribed to the project's event.
+    pub fn rooms(&self, project: &str, event: &str) -> Vec<String> {
+        let prefix = Self::key(project, "");
+        let mut rooms = Vec::new();
+        for entry in self.db.scan_prefix(&prefix) {
+            let (key, value) = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("Couldn't read subscriptions to {}: {}", project, err);
+                    break;
+                }
+            };
+            let kinds: BTreeSet<String> = match serde_json::from_slice(&value) {
+                Ok(kinds) => kinds,
+                Err(err) => {
+                    warn!("Skipping unreadable subscription {}: {}", String::from_utf8_lossy(&key), err);
+                    continue;
+                }
+            };
+            if kinds.iter().any(|kind| covers(kind, event)) {
+                rooms.push(String::from_utf8_lossy(&key[prefix.len()..]).into_owned());
+            }
+        }
+
+        rooms
+    }
+
+    /// Adds a message for each room subscribed to the project's events,
+    /// copied from the first message about each event. Events which didn't
+    /// message anyone don't reach the rooms either.
+    pub fn add_rooms(&self, mut messages: Vec<Message>, project: &str) -> Vec<Message> {
+        let mut events: Vec<&str> = messages.iter().filter_map(|message| message.event.as_deref()).collect();
+        events.sort_unstable();
+        events.dedup();
+        let mut copies = Vec::new();
+        for event in events {
+            let message = match messages.iter().find(|message| message.event.as_deref() == Some(event)) {
+                Some(message) => message,
+                None => continue,
+            };
+            for room_id in self.rooms(project, event) {
+                let recipient = Recipient::Room(room_id);
+                if messages.iter().any(|message| message.recipient == recipient) {
+                    continue;
+                }
+                debug!("Sending {} message to subscribed {}", event, recipient);
+                copies.push(Message {
+                    recipient,
+                    ..message.clone()
+                });
+            }
+        }
+
+        messages.extend(copies);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rooms() {
+        let subscriptions = Subscriptions {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+        };
+        let kinds = ["pipelines".to_owned(), "merged".to_owned()].iter().cloned().collect();
+        subscriptions.subscribe("hds-/MR-test", "room-1", &kinds).await.unwrap();
+        subscriptions.subscribe("hds-/mr-test-2", "room-2", &kinds).await.unwrap();
+
+        assert_eq!(vec!["room-1".to_owned()], subscriptions.rooms("hds-/mr-test", "pipeline_failed"));
+        assert_eq!(vec!["room-1".to_owned()], subscriptions.rooms("hds-/mr-test", "merged"));
+        assert!(subscriptions.rooms("hds-/mr-test", "note").is_empty());
+
+        assert!(subscriptions.unsubscribe("hds-/mr-test", "room-1").await.unwrap());
+        assert!(subscriptions.rooms("hds-/mr-test", "merged").is_empty());
+        assert!(is_kind("merges") && is_kind("pipeline_running") && !is_kind("everything"));
+    }
+}
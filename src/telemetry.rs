@@ -0,0 +1,22 @@
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+
+use crate::config::TelemetryConfig;
+
+/// A tracer which sends spans to the OTLP collector in batches.
+///
+/// The batches are sent from a tokio task, so this has to be called in the runtime.
+pub fn tracer(config: &TelemetryConfig) -> Result<trace::Tracer, TraceError> {
+    let resource = Resource::new(vec![KeyValue::new("service.name", config.service_name.clone())]);
+    opentelemetry_otlp::new_pipeline()
+        .with_endpoint(config.endpoint.as_str())
+        .with_trace_config(trace::config().with_resource(resource))
+        .with_tonic()
+        .install_batch(opentelemetry::runtime::Tokio)
+}
+
+/// Sends the spans which haven't been yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use handlebars::{
+    no_escape, Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError, TemplateError,
+};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::markdown;
+
+/// Where to look for templates when `templates_dir` isn't configured.
+pub const DEFAULT_TEMPLATES_DIR: &str = "conf/templates";
+
+/// The built in templates, each of which can be replaced by a `<name>.hbs`
+/// file in the templates directory. `merge_request`, `project` and
+/// `environment` are partials, used by the others as e.g. `{{> project}}`.
+/// Their words are wrapped in `{{t "…"}}` to be translated.
+///
+/// Templates for merge request events can also use `merge_request.description`,
+/// `draft`, `labels`, `source_branch` and `target_branch`.
+/// `pipeline_success` and `pipeline_failed` can also use `pipeline.duration` and
+/// `pipeline.queued`, and `pipeline_failed` `failed_jobs`, each with a `name`,
+/// `stage` and `url`.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("merge_request", "[!{{merge_request.iid}} {{merge_request.title}}]({{merge_request.url}})"),
+    ("project", "([{{project.name}}]({{project.url}}))"),
+    ("environment", "{{#if environment.url}}[{{environment.name}}]({{environment.url}}){{else}}{{environment.name}}{{/if}}"),
+    ("assignee_added", "{{> merge_request}} {{> project}} {{t \"by\"}} @{{user}} 🤩 {{t \"Added as assignee\"}}"),
+    ("issue_assignee_added", "[#{{issue.iid}} {{issue.title}}]({{issue.url}}) {{> project}}{{#if issue.author}} {{t \"opened by\"}} @{{issue.author}}{{/if}} 🤩 {{t \"Assigned by\"}} @{{user}}"),
+    ("reviewer_added", "{{> merge_request}} {{> project}} {{t \"by\"}} @{{user}} 👀 {{t \"Added as reviewer\"}}"),
+    ("ready_for_review", "{{> merge_request}} {{> project}} {{t \"by\"}} @{{user}} 🚀 {{t \"Ready for review\"}}"),
+    ("approved", "{{> merge_request}} {{> project}} ✅ {{t \"Approved by\"}} @{{user}}"),
+    ("unapproved", "{{> merge_request}} {{> project}} ❌ {{t \"Approval revoked by\"}} @{{user}}"),
+    ("merge_conflict", "{{> merge_request}} {{> project}} 💥 {{t \"Conflicts with\"}} {{#if merge_request.target_branch}}{{merge_request.target_branch}}{{else}}{{t \"the target branch\"}}{{/if}}"),
+    ("merged", "{{> merge_request}} {{> project}} {{t \"by\"}} @{{user}} 🎉 {{t \"Merged\"}}"),
+    ("closed", "{{> merge_request}} {{> project}} {{t \"by\"}} @{{user}} 🚫 {{t \"Closed\"}}"),
+    ("pipeline_success", "{{> merge_request}} {{> project}} [#{{pipeline.id}}]({{pipeline.url}}){{#if pipeline.kind}} ({{t pipeline.kind}}){{/if}} 🌞 {{t \"Success\"}}{{#if pipeline.duration}}, {{t \"took\"}} {{pipeline.duration}}{{/if}}{{#if pipeline.queued}} ({{t \"queued\"}} {{pipeline.queued}}){{/if}}"),
+    ("pipeline_failed", "{{> merge_request}} {{> project}} [#{{pipeline.id}}]({{pipeline.url}}){{#if pipeline.kind}} ({{t pipeline.kind}}){{/if}} ⛈️ {{t \"Failed\"}}{{#if pipeline.duration}}, {{t \"took\"}} {{pipeline.duration}}{{/if}}{{#if pipeline.queued}} ({{t \"queued\"}} {{pipeline.queued}}){{/if}}{{#each failed_jobs}}\n- [{{name}}]({{url}}) ({{stage}}){{/each}}"),
+    ("pipeline_running", "{{> merge_request}} {{> project}} [#{{pipeline.id}}]({{pipeline.url}}){{#if pipeline.kind}} ({{t pipeline.kind}}){{/if}} ⏳ {{t \"Running\"}}"),
+    ("branch_broken", "[#{{pipeline.id}}]({{pipeline.url}}) {{t \"on\"}} {{ref}} {{> project}} 💔 {{t \"Broken by\"}} [{{commit.short_id}}]({{commit.url}}) {{commit.title}} ({{commit.author}}){{#each failed_jobs}}\n- [{{name}}]({{url}}) ({{stage}}){{/each}}"),
+    ("job_failed", "[{{job.name}}]({{job.url}}) ({{job.stage}}) {{t \"in\"}} [#{{pipeline.id}}]({{pipeline.url}}) {{t \"on\"}} {{ref}} {{> project}} ⛈️ {{t \"Failed\"}}{{#if job.failure_reason}} ({{job.failure_reason}}){{/if}}"),
+    ("note", "{{> merge_request}} {{> project}} {{t \"by\"}} @{{user}} 💬 {{t \"Commented\"}}{{#if snippet}}\n\n{{snippet}}{{/if}}"),
+    ("feature_flag_enabled", "🚩 [{{feature_flag.name}}]({{feature_flag.url}}) {{> project}} {{t \"by\"}} @{{user}} 🟢 {{t \"Enabled\"}}"),
+    ("feature_flag_disabled", "🚩 [{{feature_flag.name}}]({{feature_flag.url}}) {{> project}} {{t \"by\"}} @{{user}} 🔴 {{t \"Disabled\"}}"),
+    ("milestone_created", "[%{{milestone.title}}]({{milestone.url}}) {{> project}} 🏁 {{t \"Created\"}}{{#if milestone.due_date}}, {{t \"due\"}} {{milestone.due_date}}{{/if}}"),
+    ("milestone_closed", "[%{{milestone.title}}]({{milestone.url}}) {{> project}} 🏆 {{t \"Closed\"}}"),
+    ("milestone_reopened", "[%{{milestone.title}}]({{milestone.url}}) {{> project}} 🔄 {{t \"Reopened\"}}"),
+    ("deployment_running", "🚀 {{> environment}} {{> project}} {{ref}} {{t \"by\"}} @{{user}} ⏳ [{{t \"Deploying\"}}]({{deployable_url}})"),
+    ("deployment_success", "🚀 {{> environment}} {{> project}} {{ref}} {{t \"by\"}} @{{user}} 🌞 [{{t \"Deployed\"}}]({{deployable_url}})"),
+    ("deployment_failed", "🚀 {{> environment}} {{> project}} {{ref}} {{t \"by\"}} @{{user}} ⛈️ [{{t \"Deployment failed\"}}]({{deployable_url}})"),
+    ("push", "⬆️ [{{branch}}]({{branch_url}}) {{> project}} {{t \"by\"}} @{{user}} {{t \"Pushed\"}}{{#each commits}}\n- [{{short_id}}]({{url}}) {{title}}{{/each}}{{#if more}}\n- …{{t \"and\"}} {{more}} {{t \"more\"}}{{/if}}"),
+    ("release_created", "📦 [{{release.name}}]({{release.url}}) ({{release.tag}}) {{> project}} 🚢 {{t \"Released\"}}{{#if release.notes}}\n\n{{release.notes}}{{/if}}"),
+    ("tag_pushed", "🏷️ [{{tag.name}}]({{tag.url}}) {{> project}} {{t \"by\"}} @{{user}} {{t \"Tagged\"}}"),
+    ("wiki_page_created", "[{{wiki_page.title}}]({{wiki_page.url}}) {{> project}} {{t \"by\"}} @{{user}} 📝 {{t \"Created\"}}{{#if wiki_page.diff_url}} ([{{t \"diff\"}}]({{wiki_page.diff_url}})){{/if}}"),
+    ("wiki_page_updated", "[{{wiki_page.title}}]({{wiki_page.url}}) {{> project}} {{t \"by\"}} @{{user}} ✏️ {{t \"Updated\"}}{{#if wiki_page.diff_url}} ([{{t \"diff\"}}]({{wiki_page.diff_url}})){{/if}}"),
+    ("wiki_page_deleted", "[{{wiki_page.title}}]({{wiki_page.url}}) {{> project}} {{t \"by\"}} @{{user}} 🗑️ {{t \"Deleted\"}}{{#if wiki_page.diff_url}} ([{{t \"diff\"}}]({{wiki_page.diff_url}})){{/if}}"),
+    ("weekly_report", "📊 {{t \"Merge requests from\"}} {{since}} {{t \"to\"}} {{until}}\n\n| {{t \"Project\"}} | {{t \"Opened\"}} | {{t \"Merged\"}} | {{t \"Still open\"}} |\n|---|---:|---:|---:|{{#each projects}}\n| [{{name}}]({{url}}) | {{opened}} | {{merged}} | {{open}} |{{/each}}"),
+];
+
+/// Fields which are markdown already, left as they are like URLs (`url` and
+/// `*_url`) when everything else is escaped.
+const MARKDOWN_FIELDS: &[&str] = &["description", "notes", "snippet"];
+
+fn is_raw(field: &str) -> bool {
+    field == "url" || field.ends_with("_url") || MARKDOWN_FIELDS.contains(&field)
+}
+
+/// Escapes the markdown in the context's text, so that e.g. a `]` in a title
+/// doesn't break the link around it.
+fn escape_context(value: Value, raw: bool) -> Value {
+    match value {
+        Value::String(text) if !raw => Value::String(markdown::escape(&text)),
+        Value::Array(values) => Value::Array(values.into_iter().map(|value| escape_context(value, raw)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(field, value)| {
+                    let raw = is_raw(&field);
+                    (field, escape_context(value, raw))
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Phrases by locale, each catalog mapping the English phrase to its
+/// translation.
+type Catalogs = HashMap<String, HashMap<String, String>>;
+
+/// `{{t "Merged"}}` is the phrase in the context's `locale`, or as it is when
+/// there's no translation for it.
+struct Translate {
+    catalogs: Arc<Catalogs>,
+}
+
+impl HelperDef for Translate {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        context: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let phrase = helper
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or_else(|| RenderError::new("t needs the phrase to translate"))?;
+        let translated = context.data()["locale"]
+            .as_str()
+            .and_then(|locale| self.catalogs.get(locale))
+            .and_then(|catalog| catalog.get(phrase));
+        out.write(translated.map_or(phrase, String::as_str))?;
+        Ok(())
+    }
+}
+
+/// Partials are only used by other templates, the rest are events.
+const PARTIALS: &[&str] = &["merge_request", "project", "environment"];
+
+/// Whether there's a built in template for the event.
+pub fn is_event(name: &str) -> bool {
+    !PARTIALS.contains(&name) && BUILT_IN.iter().any(|(built_in, _)| *built_in == name)
+}
+
+/// The templates messages are rendered from, one per kind of event.
+pub struct Templates {
+    registry: Handlebars<'static>,
+    catalogs: Arc<Catalogs>,
+}
+
+/// The catalogs in `dir`, one `<locale>.toml` file each.
+fn load_catalogs(dir: &str) -> Result<Catalogs, Box<TemplateError>> {
+    let mut catalogs = Catalogs::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(catalogs),
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let locale = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(locale) if path.extension().is_some_and(|extension| extension == "toml") => locale.to_owned(),
+            _ => continue,
+        };
+        info!("Using catalog: {}", path.display());
+        let catalog = std::fs::read_to_string(&path).map_err(|err| TemplateError::from((err, locale.clone())))?;
+        let catalog = toml::from_str(&catalog).map_err(|err| {
+            TemplateError::from((std::io::Error::new(std::io::ErrorKind::InvalidData, err), locale.clone()))
+        })?;
+        catalogs.insert(locale, catalog);
+    }
+
+    Ok(catalogs)
+}
+
+impl Templates {
+    fn with_catalogs(catalogs: Catalogs) -> Self {
+        let catalogs = Arc::new(catalogs);
+        let mut registry = Handlebars::new();
+        // Messages are markdown, not HTML.
+        registry.register_escape_fn(no_escape);
+        registry.register_helper("t", Box::new(Translate { catalogs: catalogs.clone() }));
+        for (name, template) in BUILT_IN {
+            registry.register_template_string(name, template).expect("Bad built in template");
+        }
+
+        Self { registry, catalogs }
+    }
+
+    /// The built in templates, with any found in `dir` taking their place, and
+    /// the catalogs found there.
+    pub fn load(dir: &str) -> Result<Self, Box<TemplateError>> {
+        let mut templates = Self::with_catalogs(load_catalogs(dir)?);
+        for (name, _) in BUILT_IN {
+            let path = Path::new(dir).join(format!("{}.hbs", name));
+            if path.is_file() {
+                info!("Using template: {}", path.display());
+                // Editors like to end files with a newline, which would end up in the message.
+                let template = std::fs::read_to_string(&path).map_err(|err| TemplateError::from((err, name.to_string())))?;
+                templates.registry.register_template_string(name, template.trim_end())?;
+            }
+        }
+
+        Ok(templates)
+    }
+
+    /// Whether there's a catalog for the locale. English needs none.
+    pub fn has_locale(&self, locale: &str) -> bool {
+        locale == "en" || self.catalogs.contains_key(locale)
+    }
+
+    /// Renders the template in English.
+    pub fn render<T: Serialize>(&self, name: &str, context: &T) -> Result<String, Box<RenderError>> {
+        self.render_in(None, name, context)
+    }
+
+    /// Text in the context is escaped for markdown, except for URLs and the
+    /// fields which are markdown already.
+    pub fn render_in<T: Serialize>(&self, locale: Option<&str>, name: &str, context: &T) -> Result<String, Box<RenderError>> {
+        let context = serde_json::to_value(context).map_err(|err| Box::new(RenderError::from_error("context", err)))?;
+        let mut context = escape_context(context, false);
+        if let (Some(locale), Value::Object(fields)) = (locale, &mut context) {
+            fields.insert("locale".to_owned(), Value::String(locale.to_owned()));
+        }
+        self.registry.render(name, &context).map_err(|err| {
+            warn!("Couldn't render template {}: {}", name, err);
+            Box::new(err)
+        })
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self::with_catalogs(Catalogs::new())
+    }
+}
+
+impl fmt::Debug for Templates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<_> = self.registry.get_templates().keys().collect();
+        names.sort();
+        f.debug_struct("Templates").field("names", &names).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_built_in_templates() {
+        let templates = Templates::default();
+        let context = json!({
+            "merge_request": { "iid": 12, "title": "Fix it", "url": "https://gitlab.example.com/g/p/-/merge_requests/12" },
+            "project": { "name": "p", "url": "https://gitlab.example.com/g/p" },
+            "pipeline": { "id": 34, "url": "https://gitlab.example.com/g/p/-/pipelines/34", "kind": "" },
+            "user": "someone",
+        });
+
+        assert_eq!(
+            "[!12 Fix it](https://gitlab.example.com/g/p/-/merge_requests/12) \
+            ([p](https://gitlab.example.com/g/p)) \
+            [#34](https://gitlab.example.com/g/p/-/pipelines/34) \
+            🌞 Success",
+            templates.render("pipeline_success", &context).unwrap());
+
+        let mut context = context;
+        context["pipeline"]["duration"] = json!("14m 32s");
+        assert!(templates.render("pipeline_success", &context).unwrap().ends_with("🌞 Success, took 14m 32s"));
+        context["pipeline"]["duration"] = json!(null);
+        context["pipeline"]["kind"] = json!("merged result");
+        context["snippet"] = json!("> <b>Nice</b> & tidy");
+        assert!(templates.render("pipeline_failed", &context).unwrap().ends_with("/pipelines/34) (merged result) ⛈️ Failed"));
+        assert!(templates.render("note", &context).unwrap().ends_with("by @someone 💬 Commented\n\n> <b>Nice</b> & tidy"));
+
+        context["failed_jobs"] = json!([{ "name": "lint", "stage": "test", "url": "https://gitlab.example.com/g/p/-/jobs/56" }]);
+        assert!(templates.render("pipeline_failed", &context).unwrap().ends_with("⛈️ Failed\n- [lint](https://gitlab.example.com/g/p/-/jobs/56) (test)"));
+    }
+
+    #[test]
+    fn test_translated() {
+        let mut catalogs = Catalogs::new();
+        let spanish = [("by", "por"), ("Merged", "Fusionado")].iter().map(|(en, es)| (en.to_string(), es.to_string())).collect();
+        catalogs.insert("es".to_owned(), spanish);
+        let templates = Templates::with_catalogs(catalogs);
+        let context = json!({
+            "merge_request": { "iid": 12, "title": "Merged by", "url": "https://gitlab.example.com/g/p/-/merge_requests/12" },
+            "project": { "name": "p", "url": "https://gitlab.example.com/g/p" },
+            "user": "someone",
+        });
+
+        assert!(templates.render("merged", &context).unwrap().starts_with("[!12 Merged by](https://gitlab.example.com/g/p/-/merge_requests/12) ([p](https://gitlab.example.com/g/p)) by @someone 🎉 Merged"));
+        assert!(templates.render_in(Some("es"), "merged", &context).unwrap().starts_with("[!12 Merged by](https://gitlab.example.com/g/p/-/merge_requests/12) ([p](https://gitlab.example.com/g/p)) por @someone 🎉 Fusionado"));
+        // Phrases missing from the catalog stay in English.
+        assert!(templates.render_in(Some("es"), "closed", &context).unwrap().ends_with("por @someone 🚫 Closed"));
+        assert!(templates.has_locale("es") && templates.has_locale("en") && !templates.has_locale("fr"));
+    }
+
+    #[test]
+    fn test_escaped_context() {
+        let templates = Templates::default();
+        let context = json!({
+            "merge_request": { "iid": 12, "title": "Handle ](x) and `code`", "url": "https://gitlab.example.com/g/p_q/-/merge_requests/12" },
+            "project": { "name": "p_q", "url": "https://gitlab.example.com/g/p_q" },
+            "user": "some_one",
+            "snippet": "> **Nice**",
+        });
+
+        assert_eq!(
+            concat!(
+                r"[!12 Handle \]\(x\) and \`code\`](https://gitlab.example.com/g/p_q/-/merge_requests/12) ",
+                r"([p\_q](https://gitlab.example.com/g/p_q)) by @some\_one 💬 Commented",
+                "\n\n> **Nice**",
+            ),
+            templates.render("note", &context).unwrap()
+        );
+    }
+}
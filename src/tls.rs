@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::server::accept::{self, Accept};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+use crate::config::TlsConfig;
+use crate::server::Connection;
+
+/// Handshakes which take longer than this are given up on.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_secs(1);
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the certificate chain and private key, both PEM encoded.
+pub fn acceptor(config: &TlsConfig) -> io::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(invalid_data(format!("No certificates in: {}", config.cert_path)));
+    }
+
+    let mut key_reader = BufReader::new(File::open(&config.key_path)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key)) | Some(rustls_pemfile::Item::RSAKey(key)) | Some(rustls_pemfile::Item::ECKey(key)) => {
+                break PrivateKey(key);
+            }
+            Some(_) => continue,
+            None => return Err(invalid_data(format!("No private key in: {}", config.key_path))),
+        }
+    };
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| invalid_data(err.to_string()))?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+impl Connection for TlsStream<TcpStream> {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().0.peer_addr().ok()
+    }
+
+    fn scheme(&self) -> &'static str {
+        "https"
+    }
+}
+
+/// Connections on the listener, once their TLS handshake is done. Handshakes
+/// happen in tasks of their own, so that a slow client doesn't hold up the rest.
+pub fn incoming(listener: TcpListener, acceptor: TlsAcceptor) -> impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error> {
+    let (connections_tx, mut connections) = mpsc::channel(32);
+    tokio::spawn(async move {
+        // Waits for room for each connection before accepting it, so that none
+        // are accepted while hyper isn't taking them. Stops once the server
+        // has shut down, and isn't taking connections any more.
+        while let Ok(slot) = connections_tx.clone().reserve_owned().await {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Usually running out of file descriptors, which takes a moment to recover from.
+                    warn!("Couldn't accept connection: {}", err);
+                    tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        slot.send(Ok::<_, io::Error>(stream));
+                    }
+                    Ok(Err(err)) => debug!("TLS handshake with {} failed: {}", addr, err),
+                    Err(_) => debug!("TLS handshake with {} timed out", addr),
+                }
+            });
+        }
+    });
+
+    accept::from_stream(async_stream::stream! {
+        while let Some(connection) = connections.recv().await {
+            yield connection;
+        }
+    })
+}
@@ -0,0 +1,97 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use tracing::warn;
+
+/// How long the nonce in front of each encrypted token is.
+const NONCE_BYTES: usize = 12;
+
+/// GitLab access tokens people registered with revbot, to act as them.
+///
+/// Keys are GitLab usernames in lower case. Values are a random nonce followed
+/// by the token encrypted with ChaCha20-Poly1305, with the username as
+/// associated data so that a token can't be moved to somebody else.
+pub struct UserTokens {
+    db: sled::Db,
+    cipher: ChaCha20Poly1305,
+}
+
+impl UserTokens {
+    /// Opens the store with a 32 byte key.
+    pub fn open(path: &str, key: &[u8]) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        })
+    }
+
+    pub async fn set(&self, username: &str, token: &str) -> sled::Result<()> {
+        let username = username.to_lowercase();
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self.cipher
+            .encrypt(&nonce, Payload { msg: token.as_bytes(), aad: username.as_bytes() })
+            .expect("Encrypting takes any length of token");
+        let mut value = nonce.to_vec();
+        value.extend_from_slice(&encrypted);
+        self.db.insert(username, value)?;
+        self.db.flush_async().await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, username: &str) -> sled::Result<bool> {
+        let removed = self.db.remove(username.to_lowercase())?;
+        self.db.flush_async().await?;
+
+        Ok(removed.is_some())
+    }
+
+    /// The user's token, if they registered one and it can be decrypted.
+    pub fn get(&self, username: &str) -> Option<String> {
+        let username = username.to_lowercase();
+        let value = match self.db.get(&username) {
+            Ok(value) => value?,
+            Err(err) => {
+                warn!("Couldn't read the GitLab token of {}: {}", username, err);
+                return None;
+            }
+        };
+        if value.len() < NONCE_BYTES {
+            warn!("Ignoring the GitLab token of {}, it's too short", username);
+            return None;
+        }
+
+        let (nonce, encrypted) = value.split_at(NONCE_BYTES);
+        match self.cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: encrypted, aad: username.as_bytes() }) {
+            Ok(token) => String::from_utf8(token).ok(),
+            Err(_) => {
+                warn!("Couldn't decrypt the GitLab token of {}, was actions.encryption_key changed?", username);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_user_tokens() {
+        let user_tokens = UserTokens {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&[7; 32])),
+        };
+        user_tokens.set("Someone", "glpat-secret").await.unwrap();
+
+        assert_eq!(Some("glpat-secret".to_owned()), user_tokens.get("someone"));
+        let stored = user_tokens.db.get("someone").unwrap().unwrap();
+        assert!(!stored.windows(6).any(|window| window == b"secret"));
+
+        // Tokens can't be moved to somebody else.
+        user_tokens.db.insert("somebody-else", stored).unwrap();
+        assert_eq!(None, user_tokens.get("somebody-else"));
+
+        assert!(user_tokens.remove("someone").await.unwrap());
+        assert_eq!(None, user_tokens.get("someone"));
+    }
+}
@@ -1,57 +1,510 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use tracing::{debug, warn};
+use tokio::sync::OnceCell;
+
+use crate::config::WebexConfig;
+use crate::error::RevbotError;
+use crate::markdown;
+use crate::webex_tokens::WebexTokens;
+use tracing::{debug, info, instrument, warn};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Message {
-    #[serde(rename = "toPersonEmail")]
-    to_person_email: String,
+    #[serde(rename = "toPersonEmail", skip_serializing_if = "Option::is_none")]
+    to_person_email: Option<String>,
+    #[serde(rename = "roomId", skip_serializing_if = "Option::is_none")]
+    room_id: Option<String>,
     markdown: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Value>,
+    #[serde(rename = "parentId", skip_serializing_if = "Option::is_none")]
+    parent_id: Option<String>,
 }
 
 impl Message {
-    pub fn new(to_person_email: String, markdown: String) -> Self {
+    pub fn to_person(to_person_email: String, markdown: String) -> Self {
+        Message {
+            to_person_email: Some(to_person_email),
+            room_id: None,
+            markdown,
+            attachments: Vec::new(),
+            parent_id: None,
+        }
+    }
+
+    pub fn to_room(room_id: String, markdown: String) -> Self {
         Message {
-            to_person_email,
+            to_person_email: None,
+            room_id: Some(room_id),
             markdown,
+            attachments: Vec::new(),
+            parent_id: None,
         }
     }
+
+    pub fn with_attachment(mut self, attachment: Value) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    /// A reply to the message, which has to be a top level one in the same room.
+    pub fn in_thread(mut self, parent_id: String) -> Self {
+        self.parent_id = Some(parent_id);
+        self
+    }
+}
+
+/// The message Webex created, which it can be found by later.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedMessage {
+    pub id: String,
+    pub room_id: String,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Person {
+    pub id: String,
+    pub emails: Vec<String>,
+    pub display_name: String,
+}
+
+/// A notification from a Webex webhook, about something happening to a resource.
+#[derive(Deserialize, Debug)]
+pub struct Webhook {
+    pub resource: String,
+    pub event: String,
+    pub data: WebhookData,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookData {
+    /// The id of the resource, e.g. the message.
+    pub id: String,
+    pub person_id: Option<String>,
+}
+
+/// A message someone sent, as read back from Webex.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivedMessage {
+    pub id: String,
+    pub room_id: String,
+    /// `direct` for a 1:1 space, `group` otherwise.
+    #[serde(default)]
+    pub room_type: String,
+    pub person_id: String,
+    pub person_email: String,
+    /// Missing when the message is only a file.
+    #[serde(default)]
+    pub text: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct People {
+    items: Vec<Person>,
+}
+
+/// A button on a card which somebody clicked, with what it submitted.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentAction {
+    pub id: String,
+    pub room_id: String,
+    pub person_id: String,
+    #[serde(default)]
+    pub inputs: Value,
+}
+
+/// Someone not being on Webex is only remembered for so long, they may join.
+const UNKNOWN_PERSON_TTL: Duration = Duration::from_secs(60 * 60);
+/// Webex refuses messages with more markdown than this.
+const MAX_MESSAGE_BYTES: usize = 7439;
+
+#[derive(Debug)]
+pub enum SendError {
+    /// Webex refused the message, so trying again won't help.
+    Rejected { status: StatusCode, reason: String },
+    /// Webex doesn't accept the access token, nothing can be sent until it's replaced.
+    InvalidToken,
+    /// The message is longer than Webex allows.
+    MessageTooLong,
+    /// Webex kept asking us to slow down through every attempt.
+    RateLimited,
+    /// Webex couldn't be reached, or stayed unavailable through every attempt.
+    Unavailable(String),
+    /// There's nobody on Webex with this email.
+    UnknownPerson(String),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Rejected { status, reason } => write!(f, "Webex rejected the message: {} ({})", status, reason),
+            SendError::InvalidToken => write!(f, "Webex rejected the access token"),
+            SendError::MessageTooLong => write!(f, "Message too long for Webex"),
+            SendError::RateLimited => write!(f, "Webex rate limited every attempt"),
+            SendError::Unavailable(err) => write!(f, "Webex unavailable: {}", err),
+            SendError::UnknownPerson(email) => write!(f, "Nobody on Webex with the email: {}", email),
+        }
+    }
+}
+
+/// The body of an error response from Webex.
+#[derive(Deserialize, Debug, Default)]
+struct ErrorBody {
+    message: Option<String>,
+}
+
+/// Why Webex refused a message, from the status and error body of its response.
+fn rejection(status: StatusCode, body: &str, to_person_email: Option<&str>) -> SendError {
+    let reason = serde_json::from_str::<ErrorBody>(body).unwrap_or_default().message.unwrap_or_default();
+    match (status, to_person_email) {
+        (StatusCode::UNAUTHORIZED, _) => SendError::InvalidToken,
+        (StatusCode::NOT_FOUND, Some(email)) => SendError::UnknownPerson(email.to_owned()),
+        (StatusCode::BAD_REQUEST, _) if reason.to_lowercase().contains("too long") => SendError::MessageTooLong,
+        _ => SendError::Rejected { status, reason },
+    }
+}
+
+impl std::error::Error for SendError {}
+
 #[derive(Clone, Debug)]
 pub struct WebexClient {
     access_token: String,
+    /// The integration's tokens, which replace `access_token` if set.
+    tokens: Option<Arc<WebexTokens>>,
     whoami_link: Option<String>,
+    mock: bool,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    verify_recipients: bool,
+    /// Whether there's anybody with the email, and when we found out.
+    known_people: Arc<Mutex<HashMap<String, (bool, Instant)>>>,
+    /// Who the access token belongs to, looked up once it's needed.
+    me: Arc<OnceCell<Person>>,
+}
+
+/// Rate limiting and server side trouble are worth another try, anything else isn't.
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// How long Webex asked us to wait, in seconds, before trying again.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let seconds = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
 }
 
 impl WebexClient {
-    pub fn new(access_token: String, whoami_link: Option<String>) -> Self {
+    pub fn new(access_token: String, whoami_link: Option<String>, mock: bool, max_attempts: u32, initial_backoff: Duration, verify_recipients: bool) -> Self {
         Self {
             access_token,
+            tokens: None,
             whoami_link,
+            mock,
+            max_attempts: max_attempts.max(1),
+            initial_backoff,
+            verify_recipients,
+            known_people: Arc::new(Mutex::new(HashMap::new())),
+            me: Arc::new(OnceCell::new()),
         }
     }
 
-    pub async fn send_message(self, msg: Message) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn from_config(config: &WebexConfig) -> Self {
+        Self::new(
+            config.access_token.clone(),
+            config.whoami_link.clone(),
+            config.mock,
+            config.max_attempts,
+            Duration::from_millis(config.initial_backoff_ms),
+            config.verify_recipients)
+    }
+
+    /// Uses the integration's tokens instead of the configured access token.
+    pub fn with_tokens(mut self, tokens: Option<Arc<WebexTokens>>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    fn access_token(&self) -> String {
+        match &self.tokens {
+            Some(tokens) => tokens.access_token(),
+            None => self.access_token.clone(),
+        }
+    }
+
+    /// A new access token in place of the rejected one, if it can be refreshed.
+    async fn refreshed(&self, rejected: &str) -> Option<String> {
+        match self.tokens.as_ref()?.refresh(rejected).await {
+            Ok(access_token) => Some(access_token),
+            Err(err) => {
+                warn!("Couldn't refresh the Webex access token: {}", err);
+                None
+            }
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str, query: &[(&str, &str)]) -> reqwest::Result<T> {
+        let request = |access_token: &str| reqwest::Client::new().get(url).query(query).bearer_auth(access_token).send();
+        let access_token = self.access_token();
+        let mut res = request(&access_token).await?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            if let Some(access_token) = self.refreshed(&access_token).await {
+                res = request(&access_token).await?;
+            }
+        }
+
+        res.error_for_status()?.json::<T>().await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_person_by_email(&self, email: &str) -> Result<Option<Person>, RevbotError> {
+        let people: People = self.get("https://api.ciscospark.com/v1/people", &[("email", email)])
+            .await
+            .map_err(|source| RevbotError::Webex { call: "find_person_by_email", source })?;
+        debug!("People with email {}: {:?}", email, people.items);
+
+        Ok(people.items.into_iter().next())
+    }
+
+    /// Whether anybody on Webex has the email. If Webex can't tell us, we
+    /// assume they do, and ask again next time.
+    async fn person_exists(&self, email: &str) -> bool {
+        let key = email.to_lowercase();
+        if let Some((exists, checked_at)) = self.known_people.lock().unwrap().get(&key) {
+            if *exists || checked_at.elapsed() < UNKNOWN_PERSON_TTL {
+                return *exists;
+            }
+        }
+
+        match self.find_person_by_email(email).await {
+            Ok(person) => {
+                let exists = person.is_some();
+                self.known_people.lock().unwrap().insert(key, (exists, Instant::now()));
+                exists
+            }
+            Err(err) => {
+                warn!("Couldn't look up {} on Webex: {}", email, err);
+                true
+            }
+        }
+    }
+
+    /// Webhooks only say that a message was sent, its text has to be fetched.
+    #[instrument(skip(self))]
+    pub async fn get_message(&self, message_id: &str) -> Result<ReceivedMessage, RevbotError> {
+        let message: ReceivedMessage = self.get(&format!("https://api.ciscospark.com/v1/messages/{}", message_id), &[])
+            .await
+            .map_err(|source| RevbotError::Webex { call: "get_message", source })?;
+        // The text isn't logged, it may be somebody's token.
+        debug!("Received message {} from {}", message.id, message.person_email);
+
+        Ok(message)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_person(&self, person_id: &str) -> Result<Person, RevbotError> {
+        let person: Person = self.get(&format!("https://api.ciscospark.com/v1/people/{}", person_id), &[])
+            .await
+            .map_err(|source| RevbotError::Webex { call: "get_person", source })?;
+        debug!("Person: {:?}", person);
+
+        Ok(person)
+    }
+
+    /// Webhooks only say that a button was clicked, what it submitted has to be fetched.
+    #[instrument(skip(self))]
+    pub async fn get_attachment_action(&self, action_id: &str) -> Result<AttachmentAction, RevbotError> {
+        let action: AttachmentAction = self.get(&format!("https://api.ciscospark.com/v1/attachment/actions/{}", action_id), &[])
+            .await
+            .map_err(|source| RevbotError::Webex { call: "get_attachment_action", source })?;
+        debug!("Attachment action: {:?}", action);
+
+        Ok(action)
+    }
+
+    /// The person the access token belongs to, which errors if Webex rejects the token.
+    #[instrument(skip(self))]
+    pub async fn get_me(&self) -> Result<Person, RevbotError> {
+        let person: Person = self.get("https://api.ciscospark.com/v1/people/me", &[])
+            .await
+            .map_err(|source| RevbotError::Webex { call: "get_me", source })?;
+        debug!("Me: {:?}", person);
+
+        Ok(person)
+    }
+
+    /// The person revbot acts as, looked up the first time it's needed.
+    pub async fn me(&self) -> Option<&Person> {
+        match self.me.get_or_try_init(|| self.get_me()).await {
+            Ok(me) => Some(me),
+            Err(err) => {
+                warn!("Couldn't look up who revbot is on Webex: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Adds the "who am I?" link, truncating the markdown before it if
+    /// together they'd be longer than Webex allows.
+    fn with_whoami_link(&self, markdown: String) -> String {
+        let whoami = match &self.whoami_link {
+            Some(whoami_link) => format!(" ([who am I?]({}))", whoami_link),
+            None => String::new(),
+        };
+        let max_bytes = MAX_MESSAGE_BYTES.saturating_sub(whoami.len());
+        let mut markdown = if markdown.len() > max_bytes {
+            warn!("Truncating message of {} bytes to fit in Webex", markdown.len());
+            markdown::truncate(&markdown, max_bytes)
+        } else {
+            markdown
+        };
+        markdown.push_str(&whoami);
+        markdown
+    }
+
+    /// Replaces the text of a message sent earlier.
+    #[instrument(skip(self, previous, markdown), fields(message_id = %previous.id))]
+    pub async fn update_message(&self, previous: &CreatedMessage, markdown: String) -> Result<CreatedMessage, RevbotError> {
+        let markdown = self.with_whoami_link(markdown);
+        if self.mock {
+            info!("Not updating message {} (mock): {}", previous.id, markdown);
+            return Ok(previous.clone());
+        }
+
+        let body = serde_json::json!({
+            "roomId": previous.room_id,
+            "markdown": markdown,
+        });
+        let request = |access_token: &str| reqwest::Client::new()
+            .put(format!("https://api.ciscospark.com/v1/messages/{}", previous.id))
+            .json(&body)
+            .bearer_auth(access_token)
+            .send();
+        let access_token = self.access_token();
+        let mut res = request(&access_token).await;
+        if matches!(&res, Ok(res) if res.status() == StatusCode::UNAUTHORIZED) {
+            if let Some(access_token) = self.refreshed(&access_token).await {
+                res = request(&access_token).await;
+            }
+        }
+        let updated: CreatedMessage = res
+            .and_then(Response::error_for_status)
+            .map_err(|source| RevbotError::Webex { call: "update_message", source })?
+            .json()
+            .await
+            .map_err(|source| RevbotError::Webex { call: "update_message", source })?;
+        debug!("Updated message: {:?}", updated);
+
+        Ok(updated)
+    }
+
+    /// Sends the message, returning what Webex created unless the message was
+    /// mocked or Webex's answer couldn't be read.
+    #[instrument(skip(self, msg))]
+    pub async fn send_message(&self, mut msg: Message) -> Result<Option<CreatedMessage>, SendError> {
         let client = reqwest::Client::new();
+        msg.markdown = self.with_whoami_link(msg.markdown);
 
-        let mut msg = msg.clone();
-        if let Some(whoami_link) = self.whoami_link {
-            msg.markdown.push_str(&format!(" ([who am I?]({}))", whoami_link));
+        if self.mock {
+            info!("Not sending message (mock): {:?}", &msg);
+            return Ok(None);
+        }
+
+        if let Some(email) = &msg.to_person_email {
+            if self.verify_recipients && !self.person_exists(email).await {
+                return Err(SendError::UnknownPerson(email.to_owned()));
+            }
         }
 
         debug!("Sending message: {:?}", &msg);
-        let res = client.post("https://api.ciscospark.com/v1/messages")
-            .json(&msg)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await?;
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        let mut refreshed = false;
+        loop {
+            let mut rate_limited = false;
+            let access_token = self.access_token();
+            let res = client.post("https://api.ciscospark.com/v1/messages")
+                .json(&msg)
+                .bearer_auth(&access_token)
+                .send()
+                .await;
+
+            // Backs off exponentially, unless Webex says how long to wait.
+            let (err, wait) = match res {
+                Ok(res) if res.status().is_success() => {
+                    return match res.json::<CreatedMessage>().await {
+                        Ok(created) => {
+                            debug!("Created message: {:?}", created);
+                            Ok(Some(created))
+                        }
+                        Err(err) => {
+                            warn!("Couldn't parse created message: {}", err);
+                            Ok(None)
+                        }
+                    };
+                }
+                Ok(res) if is_transient(res.status()) => {
+                    rate_limited = res.status() == StatusCode::TOO_MANY_REQUESTS;
+                    (format!("Webex answered {}", res.status()), retry_after(&res).unwrap_or(backoff))
+                }
+                // An expired access token doesn't count as an attempt.
+                Ok(res) if res.status() == StatusCode::UNAUTHORIZED && !refreshed && self.tokens.is_some() => {
+                    refreshed = true;
+                    if self.refreshed(&access_token).await.is_some() {
+                        continue;
+                    }
+                    return Err(SendError::InvalidToken);
+                }
+                Ok(res) => {
+                    let status = res.status();
+                    let body = res.text().await.unwrap_or_default();
+                    debug!("Webex rejected message with {}: {}", status, body);
+                    return Err(rejection(status, &body, msg.to_person_email.as_deref()));
+                }
+                Err(err) => (err.to_string(), backoff),
+            };
 
-        match res.json::<Value>().await {
-            Ok(json) => debug!("Response body: {}", json),
-            Err(err) => warn!("Couldn't parse body to JSON: {}", err),
+            if attempt >= self.max_attempts {
+                if rate_limited {
+                    return Err(SendError::RateLimited);
+                }
+                return Err(SendError::Unavailable(format!("{} (gave up after {} attempts)", err, attempt)));
+            }
+            warn!("Sending message failed: {}, attempt {} of {} in {:?}", err, attempt + 1, self.max_attempts, wait);
+            tokio::time::sleep(wait).await;
+            backoff *= 2;
+            attempt += 1;
         }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        Ok(())
+    #[test]
+    fn test_rejection() {
+        assert!(matches!(rejection(StatusCode::UNAUTHORIZED, "", None), SendError::InvalidToken));
+        assert!(matches!(
+            rejection(StatusCode::NOT_FOUND, "{}", Some("someone@example.com")),
+            SendError::UnknownPerson(email) if email == "someone@example.com"));
+        assert!(matches!(
+            rejection(StatusCode::BAD_REQUEST, r#"{"message": "Message text is too long.", "trackingId": "ROUTER_1"}"#, None),
+            SendError::MessageTooLong));
+        assert!(matches!(
+            rejection(StatusCode::BAD_REQUEST, r#"{"message": "Invalid roomId"}"#, None),
+            SendError::Rejected { status: StatusCode::BAD_REQUEST, reason } if reason == "Invalid roomId"));
     }
 }
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::config::WebexOauthConfig;
+use crate::error::RevbotError;
+
+const ACCESS_TOKEN_URL: &str = "https://webexapis.com/v1/access_token";
+
+/// The key the current tokens are kept under.
+const TOKENS_KEY: &str = "tokens";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: String,
+    /// When Webex said the access token expires, unknown for the configured one.
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// What Webex answers a refresh with.
+#[derive(Deserialize)]
+struct Refreshed {
+    access_token: String,
+    expires_in: i64,
+    refresh_token: String,
+}
+
+/// The access and refresh tokens of a Webex integration, which Webex hands
+/// out anew when the access token is refreshed.
+///
+/// The latest tokens are kept in the store so that they survive a restart,
+/// the configured ones are only used until then.
+pub struct WebexTokens {
+    db: sled::Db,
+    client_id: String,
+    client_secret: String,
+    current: RwLock<Tokens>,
+    /// Held while refreshing, so that requests rejected at the same time
+    /// refresh only once.
+    refreshing: Mutex<()>,
+}
+
+impl fmt::Debug for WebexTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebexTokens").field("client_id", &self.client_id).finish()
+    }
+}
+
+impl WebexTokens {
+    pub fn open(config: &WebexOauthConfig, access_token: &str) -> Result<Self, RevbotError> {
+        Self::from_db(sled::open(&config.path)?, config, access_token)
+    }
+
+    fn from_db(db: sled::Db, config: &WebexOauthConfig, access_token: &str) -> Result<Self, RevbotError> {
+        let current = match db.get(TOKENS_KEY)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Tokens {
+                access_token: access_token.to_owned(),
+                refresh_token: config.refresh_token.clone(),
+                expires_at: None,
+            },
+        };
+
+        Ok(Self {
+            db,
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone(),
+            current: RwLock::new(current),
+            refreshing: Mutex::new(()),
+        })
+    }
+
+    pub fn access_token(&self) -> String {
+        self.current.read().unwrap().access_token.clone()
+    }
+
+    /// Refreshes the access token Webex rejected, returning the new one. If
+    /// it's been refreshed since, the newer one is returned as it is.
+    pub async fn refresh(&self, rejected: &str) -> Result<String, RevbotError> {
+        let _refreshing = self.refreshing.lock().await;
+        let refresh_token = {
+            let current = self.current.read().unwrap();
+            if current.access_token != rejected {
+                return Ok(current.access_token.clone());
+            }
+            current.refresh_token.clone()
+        };
+
+        let refreshed: Refreshed = reqwest::Client::new()
+            .post(ACCESS_TOKEN_URL)
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("refresh_token", &refresh_token),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|source| RevbotError::Webex { call: "refresh_access_token", source })?
+            .json()
+            .await
+            .map_err(|source| RevbotError::Webex { call: "refresh_access_token", source })?;
+
+        let expires_at = Utc::now() + Duration::seconds(refreshed.expires_in);
+        info!("Refreshed the Webex access token, which now expires at {}", expires_at);
+        self.set(Tokens {
+            access_token: refreshed.access_token,
+            refresh_token: refreshed.refresh_token,
+            expires_at: Some(expires_at),
+        }).await
+    }
+
+    /// Swaps in the tokens, keeping them for the next start. Tokens which
+    /// can't be kept are still used, as Webex may have revoked the old ones.
+    async fn set(&self, tokens: Tokens) -> Result<String, RevbotError> {
+        let access_token = tokens.access_token.clone();
+        let value = serde_json::to_vec(&tokens)?;
+        *self.current.write().unwrap() = tokens;
+        if let Err(err) = self.store(value).await {
+            warn!("Couldn't keep the refreshed Webex tokens, they'll be lost on restart: {}", err);
+        }
+
+        Ok(access_token)
+    }
+
+    async fn store(&self, value: Vec<u8>) -> sled::Result<()> {
+        self.db.insert(TOKENS_KEY, value)?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tokens_survive_restart() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let config = WebexOauthConfig {
+            client_id: "client".to_owned(),
+            client_secret: "secret".to_owned(),
+            refresh_token: "refresh-1".to_owned(),
+            path: String::new(),
+        };
+        let tokens = WebexTokens::from_db(db.clone(), &config, "access-1").unwrap();
+        assert_eq!("access-1", tokens.access_token());
+        // Somebody else already refreshed the rejected token.
+        tokens.set(Tokens {
+            access_token: "access-2".to_owned(),
+            refresh_token: "refresh-2".to_owned(),
+            expires_at: None,
+        }).await.unwrap();
+        assert_eq!("access-2", tokens.refresh("access-1").await.unwrap());
+
+        let tokens = WebexTokens::from_db(db, &config, "access-1").unwrap();
+        assert_eq!("access-2", tokens.access_token());
+        assert_eq!("refresh-2", tokens.current.read().unwrap().refresh_token);
+    }
+}